@@ -7,7 +7,7 @@ extern crate env_logger;
 use actix_web::*;
 use actix::prelude::*;
 
-use sockjs::{Message, Session, SockJSManager, SockJSContext};
+use sockjs::{Message, Session, SockJSManager, SockJSContext, TransportKind};
 
 #[derive(Debug)]
 struct Echo;
@@ -81,7 +81,7 @@ fn main() {
                 "/close", sockjs::SockJS::new(cl.clone()))
             .handler(
                 "/disabled_websocket_echo",
-                sockjs::SockJS::new(sm.clone()).disable_transports(vec!["websocket"]))
+                sockjs::SockJS::new(sm.clone()).disable(vec![TransportKind::Websocket]))
             .handler(
                 "/cookie_needed_echo",
                 sockjs::SockJS::new(sm.clone()).cookie_needed(true))
@@ -0,0 +1,12 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate sockjs;
+
+use sockjs::decode;
+
+fuzz_target!(|data: &[u8]| {
+    // form-urlencoded body and raw json body
+    let _ = decode::jsonp_send(data, true);
+    let _ = decode::jsonp_send(data, false);
+});
@@ -0,0 +1,18 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate sockjs;
+
+use std::str;
+
+use sockjs::{decode, encode};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = str::from_utf8(data) {
+        if let Ok(msgs) = decode::websocket(text) {
+            // decoded messages survive encoding
+            let text = encode::array(&msgs);
+            assert_eq!(decode::websocket(&text), Ok(msgs));
+        }
+    }
+});
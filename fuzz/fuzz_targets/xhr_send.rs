@@ -0,0 +1,14 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate sockjs;
+
+use sockjs::{decode, encode};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msgs) = decode::xhr_send(data) {
+        // decoded messages survive encoding
+        let body = encode::array(&msgs);
+        assert_eq!(decode::xhr_send(body.as_bytes()), Ok(msgs));
+    }
+});
@@ -1,5 +1,5 @@
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::rc::Rc;
 
@@ -13,11 +13,17 @@ use rand::{self, Rng, ThreadRng};
 
 use context::SockJSContext;
 use manager::SessionManager;
+use presence::UserKey;
 use protocol;
 use session::Session;
-use transports;
+use transports::{self, Deflate, TransportKind};
 use utils::{Info, SockjsHeaders};
 
+type Route<A, SM, S> =
+    fn(&SockJS<A, SM, S>, HttpRequest<Addr<Syn, SM>>) -> AsyncResult<HttpResponse>;
+
+type Factory<SM> = Box<Fn(HttpRequest<Addr<Syn, SM>>) -> AsyncResult<HttpResponse>>;
+
 /// Sockjs application
 ///
 /// Sockjs application implements sockjs protocol.
@@ -33,9 +39,17 @@ where
     patterns: Vec<Resource>,
     iframe_html: Rc<String>,
     iframe_html_md5: String,
-    disabled_transports: HashSet<String>,
-    max_size: usize,
+    routes: HashMap<TransportKind, Route<A, SM, S>>,
+    custom: HashMap<String, Factory<SM>>,
+    max_streaming_size: usize,
+    max_body_size: usize,
+    max_frame_size: usize,
+    max_message_size: usize,
+    protocols: Vec<&'static str>,
+    deflate: Option<Deflate>,
+    streaming_compression: bool,
     cookie_needed: bool,
+    presence_key: Option<Box<Fn(&HttpRequest<S>) -> Option<String>>>,
 }
 
 const ROUTES: [RouteType; 5] = [
@@ -78,27 +92,155 @@ where
             manager: Rc::new(manager),
             iframe_html: Rc::new(html),
             iframe_html_md5: format!("{:x}", digest),
-            disabled_transports: HashSet::new(),
-            max_size: transports::MAXSIZE,
+            routes: Self::routes(),
+            custom: HashMap::new(),
+            max_streaming_size: transports::MAXSIZE,
+            max_body_size: transports::MAXSIZE,
+            max_frame_size: transports::MAX_FRAME_SIZE,
+            max_message_size: transports::MAXSIZE,
+            protocols: Vec::new(),
+            deflate: None,
+            streaming_compression: false,
             cookie_needed: false,
+            presence_key: None,
         }
     }
 
     /// Disable specific transports
-    pub fn disable_transports<T, I>(mut self, disabled: I) -> Self
+    ///
+    /// Unknown transport names are logged and ignored.
+    #[deprecated(note = "use `SockJS::disable()`")]
+    pub fn disable_transports<T, I>(self, disabled: I) -> Self
     where
         T: Into<String>,
         I: IntoIterator<Item = T>,
     {
-        for i in disabled {
-            self.disabled_transports.insert(i.into());
+        let disabled: Vec<_> = disabled
+            .into_iter()
+            .filter_map(|name| {
+                let name = name.into();
+                let kind = TransportKind::from_name(&name);
+                if kind.is_none() {
+                    warn!("Unknown sockjs transport is ignored: {}", name);
+                }
+                kind
+            })
+            .collect();
+        self.disable(disabled)
+    }
+
+    /// Disable specific transports.
+    ///
+    /// Disabling `TransportKind::Websocket` disables raw websocket as well,
+    /// use `SockJS::enable_only()` to serve raw websocket only.
+    pub fn disable<I>(mut self, disabled: I) -> Self
+    where
+        I: IntoIterator<Item = TransportKind>,
+    {
+        for kind in disabled {
+            if kind == TransportKind::Websocket {
+                self.routes.remove(&TransportKind::RawWebsocket);
+            }
+            self.routes.remove(&kind);
         }
         self
     }
 
+    /// Enable only specific transports, all other transports get disabled
+    pub fn enable_only<I>(mut self, enabled: I) -> Self
+    where
+        I: IntoIterator<Item = TransportKind>,
+    {
+        let enabled: Vec<_> = enabled.into_iter().collect();
+        self.routes.retain(|kind, _| enabled.contains(kind));
+        self
+    }
+
+    /// Register custom transport, served at `{server}/{session}/{name}` path.
+    ///
+    /// Factory creates transport actor for the request, transport actor
+    /// implements `Transport` trait and acquires session with
+    /// `Transport::init_transport()`. Custom transport replaces
+    /// built-in transport with the same name.
+    pub fn transport<F, R>(mut self, name: &str, factory: F) -> Self
+    where
+        F: Fn(HttpRequest<Addr<Syn, SM>>) -> R + 'static,
+        R: Into<AsyncResult<HttpResponse>>,
+    {
+        self.custom.insert(name.to_owned(), Box::new(move |req| factory(req).into()));
+        self
+    }
+
     /// Set max size for single streaming request (EventSource, XhrStreamimng).
-    pub fn maxsize(mut self, size: usize) -> Self {
-        self.max_size = size;
+    ///
+    /// This is an alias for `max_streaming_size()`.
+    pub fn maxsize(self, size: usize) -> Self {
+        self.max_streaming_size(size)
+    }
+
+    /// Set max size of single streaming response (EventSource, XhrStreaming, HTMLFile).
+    ///
+    /// Response get closed and client reconnects once this amount of bytes is sent.
+    pub fn max_streaming_size(mut self, size: usize) -> Self {
+        self.max_streaming_size = size;
+        self
+    }
+
+    /// Set max size of request body for `xhr_send` and `jsonp_send` transports.
+    ///
+    /// Bigger requests are refused with `413 Payload Too Large`.
+    pub fn max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = size;
+        self
+    }
+
+    /// Set max size of single websocket frame, 256K bytes by default.
+    ///
+    /// Websocket connection get closed if client sends bigger frame.
+    /// Sockjs websocket frame is json encoded array of messages, so frame
+    /// limit should be bigger than `max_message_size()`.
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = size;
+        self
+    }
+
+    /// Set max size for single inbound message, 128K bytes by default.
+    ///
+    /// Http transports reply with `413 Payload Too Large` and websocket
+    /// transports close connection with `1009` code if message is bigger.
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Set websocket subprotocols supported by raw websocket transport.
+    ///
+    /// First protocol from client's `Sec-WebSocket-Protocol` header that is
+    /// supported by the server get selected, session can access it
+    /// with `SockJSContext::transport()`.
+    pub fn protocols<I>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = &'static str>,
+    {
+        self.protocols = protocols.into_iter().collect();
+        self
+    }
+
+    /// Enable permessage-deflate compression for websocket transports.
+    ///
+    /// Compression is used only if client offers the extension.
+    pub fn websocket_deflate(mut self, deflate: Deflate) -> Self {
+        self.deflate = Some(deflate);
+        self
+    }
+
+    /// Enable gzip/deflate compression of streaming responses
+    /// (XhrStreaming, EventSource, HTMLFile).
+    ///
+    /// Response is compressed if client accepts it, compressed bytes
+    /// count toward `max_streaming_size()`.
+    pub fn streaming_compression(mut self, val: bool) -> Self {
+        self.streaming_compression = val;
         self
     }
 
@@ -107,6 +249,81 @@ where
         self.cookie_needed = val;
         self
     }
+
+    /// Set function that computes user key of the request,
+    /// for example from authentication cookie.
+    ///
+    /// User key get reported by session manager's presence tracking.
+    pub fn presence_key<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&HttpRequest<S>) -> Option<String> + 'static,
+    {
+        self.presence_key = Some(Box::new(f));
+        self
+    }
+}
+
+impl<A, SM, S> SockJS<A, SM, S>
+where
+    A: Actor<Context = SockJSContext<A>> + Session,
+    SM: SessionManager<A>,
+    S: 'static,
+{
+    fn routes() -> HashMap<TransportKind, Route<A, SM, S>> {
+        let mut routes: HashMap<_, Route<A, SM, S>> = HashMap::new();
+        routes.insert(TransportKind::Websocket, |app, req| {
+            transports::Websocket::<A, _>::init(
+                req, app.max_frame_size, app.max_message_size, app.deflate.as_ref()).into()
+        });
+        routes.insert(TransportKind::RawWebsocket, |app, req| {
+            transports::RawWebsocket::<A, _>::init(
+                req,
+                app.max_frame_size,
+                app.max_message_size,
+                &app.protocols,
+                app.deflate.as_ref(),
+            ).into()
+        });
+        routes.insert(TransportKind::XhrStreaming, |app, req| {
+            transports::XhrStreaming::<A, _>::init(
+                req, app.max_streaming_size, app.streaming_compression).into()
+        });
+        routes.insert(TransportKind::Xhr, |_, req| {
+            transports::Xhr::<A, _>::init(req).into()
+        });
+        routes.insert(TransportKind::XhrSend, |app, req| {
+            match transports::XhrSend(req, app.max_body_size, app.max_message_size) {
+                Either::A(resp) => resp.into(),
+                Either::B(fut) => fut.into(),
+            }
+        });
+        routes.insert(TransportKind::EventSource, |app, req| {
+            transports::EventSource::<A, _>::init(
+                req, app.max_streaming_size, app.streaming_compression).into()
+        });
+        routes.insert(TransportKind::HtmlFile, |app, req| {
+            transports::HTMLFile::<A, _>::init(
+                req, app.max_streaming_size, app.streaming_compression).into()
+        });
+        routes.insert(TransportKind::Jsonp, |_, req| {
+            transports::JSONPolling::<A, _>::init(req).into()
+        });
+        routes.insert(TransportKind::JsonpSend, |app, req| {
+            match transports::JSONPollingSend(req, app.max_body_size, app.max_message_size) {
+                Either::A(resp) => resp.into(),
+                Either::B(fut) => fut.into(),
+            }
+        });
+        routes
+    }
+
+    fn set_user_key(&self, req: &mut HttpRequest<S>) {
+        if let Some(ref f) = self.presence_key {
+            if let Some(key) = f(req) {
+                req.extensions_mut().insert(UserKey(key));
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -125,7 +342,7 @@ where
 {
     type Result = AsyncResult<HttpResponse>;
 
-    fn handle(&mut self, req: HttpRequest<S>) -> AsyncResult<HttpResponse> {
+    fn handle(&mut self, mut req: HttpRequest<S>) -> AsyncResult<HttpResponse> {
         let idx = if let Some(path) = req.match_info().get("tail") {
             if path.is_empty() {
                 return HttpResponse::Ok()
@@ -160,7 +377,7 @@ where
                         .sockjs_cors_headers(req.headers())
                         .json(Info::new(
                             self.rng.borrow_mut().gen::<u32>(),
-                            !self.disabled_transports.contains("websocket"),
+                            self.routes.contains_key(&TransportKind::Websocket),
                             self.cookie_needed,
                         ))
                         .into()
@@ -194,9 +411,13 @@ where
                 }
             }
             RouteType::Transport => {
-                let req2 = req.change_state(Rc::clone(&self.manager));
-                let tr = req.match_info().get("transport").unwrap().to_owned();
-                if self.disabled_transports.contains(&tr) {
+                let name = req.match_info().get("transport").unwrap().to_owned();
+                let custom = self.custom.contains_key(&name);
+                let route = match TransportKind::from_name(&name) {
+                    Some(TransportKind::RawWebsocket) | None => None,
+                    Some(kind) => self.routes.get(&kind).cloned(),
+                };
+                if !custom && route.is_none() {
                     return HttpResponse::NotFound().finish().into();
                 }
 
@@ -207,42 +428,23 @@ where
                     if sid.is_empty() || sid.contains('.') || server.contains('.') {
                         return HttpResponse::NotFound().finish().into();
                     }
-                    trace!(
-                        "sockjs transport: {}, session: {}, srv: {}",
-                        tr,
-                        sid,
-                        server
-                    );
+                    trace!("sockjs transport: {}, session: {}, srv: {}", name, sid, server);
                 }
 
-                if tr == "websocket" {
-                    transports::Websocket::<A, _>::init(req2).into()
-                } else if tr == "xhr_streaming" {
-                    transports::XhrStreaming::<A, _>::init(req2, self.max_size).into()
-                } else if tr == "xhr" {
-                    transports::Xhr::<A, _>::init(req2).into()
-                } else if tr == "xhr_send" {
-                    match transports::XhrSend(req2) {
-                        Either::A(resp) => resp.into(),
-                        Either::B(fut) => fut.into(),
-                    }
-                } else if tr == "htmlfile" {
-                    transports::HTMLFile::<A, _>::init(req2, self.max_size).into()
-                } else if tr == "eventsource" {
-                    transports::EventSource::<A, _>::init(req2, self.max_size).into()
-                } else if tr == "jsonp" {
-                    transports::JSONPolling::<A, _>::init(req2).into()
-                } else if tr == "jsonp_send" {
-                    match transports::JSONPollingSend(req2) {
-                        Either::A(resp) => resp.into(),
-                        Either::B(fut) => fut.into(),
-                    }
-                } else {
-                    HttpResponse::NotFound().finish().into()
+                self.set_user_key(&mut req);
+                let req = req.change_state(Rc::clone(&self.manager));
+                match route {
+                    Some(route) if !custom => route(self, req),
+                    _ => (self.custom[&name])(req),
                 }
             }
             RouteType::RawWebsocket => {
-                transports::RawWebsocket::init(req.change_state(Rc::clone(&self.manager))).into()
+                if let Some(route) = self.routes.get(&TransportKind::RawWebsocket).cloned() {
+                    self.set_user_key(&mut req);
+                    route(self, req.change_state(Rc::clone(&self.manager)))
+                } else {
+                    HttpResponse::NotFound().finish().into()
+                }
             }
         }
     }
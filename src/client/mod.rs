@@ -0,0 +1,497 @@
+//! SockJS client
+//!
+//! `Client` opens session over websocket, xhr-streaming or xhr-polling
+//! transport. Transports are tried in order until one of them opens the
+//! session. `SockJSClient` is a `Stream` of session events and a `Sink`
+//! of messages. Client uses actix-web client, so it has to run within
+//! actix system.
+//!
+//! ```rust,no_run
+//! # extern crate actix;
+//! # extern crate futures;
+//! # extern crate sockjs;
+//! use futures::{Future, Sink, Stream};
+//! use sockjs::Message;
+//! use sockjs::client::{Client, ClientEvent};
+//!
+//! fn main() {
+//!     let sys = actix::System::new("client");
+//!
+//!     let client = Client::new("http://127.0.0.1:52081/echo").connect();
+//!     let (sink, stream) = client.split();
+//!     actix::Arbiter::handle().spawn(
+//!         sink.send(Message::from("hello")).map(|_| ()).map_err(|_| ()));
+//!     actix::Arbiter::handle().spawn(
+//!         stream
+//!             .for_each(|ev| {
+//!                 if let ClientEvent::Message(msg) = ev {
+//!                     println!("received: {}", msg);
+//!                 }
+//!                 Ok(())
+//!             })
+//!             .map_err(|_| ()));
+//!
+//!     sys.run();
+//! }
+//! ```
+use std::mem;
+use std::time::{Duration, Instant};
+
+use actix::*;
+use actix_web::client::{self, SendRequestError};
+use actix_web::http::StatusCode;
+use actix_web::ws;
+use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use rand::{self, Rng};
+use protocol::{decode, encode, Frame};
+use session::Message;
+use transports::{self, TransportKind};
+
+mod transport;
+
+use self::transport::{Incoming, Lines, Tagged, WsFrames};
+
+/// Timeout of polling and streaming requests, in seconds
+const POLL_TIMEOUT: u64 = 60;
+
+/// Session event
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientEvent {
+    /// Session is opened over transport
+    Open(TransportKind),
+    /// Message from the server
+    Message(String),
+    /// Session is closed by the server, close code and reason
+    Close(usize, String),
+    /// Transport connection is lost
+    Interrupted,
+}
+
+/// Client error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClientError {
+    /// None of transports could open session
+    Unavailable,
+    /// Client is closed
+    Closed,
+}
+
+/// SockJS client builder
+pub struct Client {
+    url: String,
+    transports: Vec<TransportKind>,
+    heartbeat: Option<Duration>,
+    reconnect: Option<Duration>,
+    max_frame_size: usize,
+}
+
+impl Client {
+    /// Create client for sockjs service url, for example `http://localhost:8080/echo`
+    pub fn new<U: Into<String>>(url: U) -> Client {
+        let url = url.into().trim_right_matches('/').to_owned();
+        Client {
+            url,
+            transports: vec![TransportKind::Websocket,
+                             TransportKind::XhrStreaming,
+                             TransportKind::Xhr],
+            heartbeat: None,
+            reconnect: None,
+            max_frame_size: transports::MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Set transports in fallback order.
+    ///
+    /// Client supports `Websocket`, `XhrStreaming` and `Xhr` transports,
+    /// other kinds are ignored.
+    pub fn transports<I>(mut self, transports: I) -> Self
+        where I: IntoIterator<Item=TransportKind>
+    {
+        self.transports = transports
+            .into_iter()
+            .filter(|kind| match *kind {
+                TransportKind::Websocket | TransportKind::XhrStreaming | TransportKind::Xhr => true,
+                _ => false,
+            })
+            .collect();
+        self
+    }
+
+    /// Consider connection lost if server does not send any frame
+    /// within `timeout`.
+    ///
+    /// SockJS servers send heartbeat frames periodically, sockjs-node
+    /// every 25 seconds. Heartbeat detection is disabled by default.
+    pub fn heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.heartbeat = Some(timeout);
+        self
+    }
+
+    /// Open new session after `delay` once connection is lost or none
+    /// of transports could open session. Client does not reconnect by default.
+    pub fn reconnect(mut self, delay: Duration) -> Self {
+        self.reconnect = Some(delay);
+        self
+    }
+
+    /// Maximum size of inbound frame, 256K bytes by default.
+    ///
+    /// Connection is considered lost if server sends bigger frame.
+    /// Xhr-streaming response starts with 2K bytes prelude, so limit
+    /// has to be bigger than that.
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = size;
+        self
+    }
+
+    /// Start client, must be called within running actix system
+    pub fn connect(self) -> SockJSClient {
+        let (tx, rx) = unbounded();
+        let (events, events_rx) = unbounded();
+
+        let _: Addr<Unsync, _> = Connection::create(move |ctx| {
+            ctx.add_stream(rx);
+            Connection {
+                events,
+                url: self.url,
+                transports: self.transports,
+                heartbeat: self.heartbeat,
+                reconnect: self.reconnect,
+                max_frame_size: self.max_frame_size,
+                state: State::Connecting,
+                idx: 0,
+                gen: 0,
+                session: String::new(),
+                stream: None,
+                ws: None,
+                pending: Vec::new(),
+                sending: None,
+                seen: Instant::now(),
+            }
+        });
+        SockJSClient { tx: Some(tx), rx: events_rx }
+    }
+}
+
+/// Client session, stream of session events and sink of messages.
+///
+/// Stream ends once session is closed. Dropping client closes the session.
+pub struct SockJSClient {
+    tx: Option<UnboundedSender<String>>,
+    rx: UnboundedReceiver<Result<ClientEvent, ClientError>>,
+}
+
+impl Stream for SockJSClient {
+    type Item = ClientEvent;
+    type Error = ClientError;
+
+    fn poll(&mut self) -> Poll<Option<ClientEvent>, ClientError> {
+        match self.rx.poll() {
+            Ok(Async::Ready(Some(Ok(ev)))) => Ok(Async::Ready(Some(ev))),
+            Ok(Async::Ready(Some(Err(err)))) => Err(err),
+            Ok(Async::Ready(None)) | Err(_) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl Sink for SockJSClient {
+    type SinkItem = Message;
+    type SinkError = ClientError;
+
+    fn start_send(&mut self, msg: Message) -> StartSend<Message, ClientError> {
+        match self.tx {
+            Some(ref tx) => match tx.unbounded_send(msg.0) {
+                Ok(()) => Ok(AsyncSink::Ready),
+                Err(_) => Err(ClientError::Closed),
+            },
+            None => Err(ClientError::Closed),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), ClientError> {
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), ClientError> {
+        self.tx.take();
+        Ok(Async::Ready(()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Connecting,
+    Open,
+    Closed,
+}
+
+/// Session url with random server and session ids
+fn session_url(url: &str) -> String {
+    let mut rng = rand::thread_rng();
+    let sid: String = rng.gen_ascii_chars().take(16).collect();
+    format!("{}/{:03}/{}", url, rng.gen_range(0, 1000), sid)
+}
+
+/// Client connection actor
+struct Connection {
+    url: String,
+    transports: Vec<TransportKind>,
+    heartbeat: Option<Duration>,
+    reconnect: Option<Duration>,
+    max_frame_size: usize,
+    events: UnboundedSender<Result<ClientEvent, ClientError>>,
+    state: State,
+    /// index of current transport
+    idx: usize,
+    /// connection generation, frames and responses of previous
+    /// connections get ignored
+    gen: usize,
+    session: String,
+    stream: Option<SpawnHandle>,
+    ws: Option<ws::ClientWriter>,
+    pending: Vec<String>,
+    /// messages of in-flight `xhr_send` request
+    sending: Option<Vec<String>>,
+    seen: Instant,
+}
+
+impl Connection {
+    fn event(&self, ev: ClientEvent) {
+        let _ = self.events.unbounded_send(Ok(ev));
+    }
+
+    /// Open new session with current transport
+    fn connect(&mut self, ctx: &mut Context<Self>) {
+        self.gen += 1;
+        self.state = State::Connecting;
+        self.session = session_url(&self.url);
+
+        let gen = self.gen;
+        match self.transports[self.idx] {
+            TransportKind::Websocket => {
+                ws::Client::new(format!("{}/websocket", self.session))
+                    .max_frame_size(self.max_frame_size)
+                    .connect()
+                    .into_actor(self)
+                    .map(move |(reader, writer), act, ctx| {
+                        if act.gen == gen {
+                            act.ws = Some(writer);
+                            act.stream = Some(ctx.add_stream(Tagged::new(gen, WsFrames(reader))));
+                        }
+                    })
+                    .map_err(move |_, act, ctx| {
+                        if act.gen == gen {
+                            act.failed(ctx);
+                        }
+                    })
+                    .spawn(ctx);
+            }
+            kind => self.poll(kind, ctx),
+        }
+    }
+
+    /// Send polling or streaming request
+    fn poll(&mut self, kind: TransportKind, ctx: &mut Context<Self>) {
+        let gen = self.gen;
+        let req = match client::post(format!("{}/{}", self.session, kind.name())).finish() {
+            Ok(req) => req,
+            Err(_) => return self.failed(ctx),
+        };
+        req.send()
+            .timeout(Duration::new(POLL_TIMEOUT, 0))
+            .into_actor(self)
+            .map(move |resp, act, ctx| {
+                if act.gen == gen {
+                    if resp.status() == StatusCode::OK {
+                        let lines = Lines::new(resp, act.max_frame_size);
+                        act.stream = Some(ctx.add_stream(Tagged::new(gen, lines)));
+                    } else {
+                        act.failed(ctx);
+                    }
+                }
+            })
+            .map_err(move |err, act, ctx| {
+                if act.gen == gen {
+                    match err {
+                        // idle poll, session is still open
+                        SendRequestError::Timeout if act.state == State::Open => {
+                            act.poll(kind, ctx)
+                        }
+                        _ => act.failed(ctx),
+                    }
+                }
+            })
+            .spawn(ctx);
+    }
+
+    /// Send pending messages
+    fn flush(&mut self, ctx: &mut Context<Self>) {
+        if self.pending.is_empty() || self.sending.is_some() {
+            return
+        }
+        let body = encode::array(&self.pending);
+
+        if let Some(ref mut writer) = self.ws {
+            writer.text(body);
+            self.pending.clear();
+            return
+        }
+
+        let req = match client::post(format!("{}/xhr_send", self.session)).body(body) {
+            Ok(req) => req,
+            Err(_) => return,
+        };
+        let gen = self.gen;
+        self.sending = Some(mem::replace(&mut self.pending, Vec::new()));
+        req.send()
+            .into_actor(self)
+            .map(move |resp, act, ctx| {
+                if act.gen == gen {
+                    if resp.status().is_success() {
+                        act.sending = None;
+                        act.flush(ctx);
+                    } else {
+                        act.failed(ctx);
+                    }
+                }
+            })
+            .map_err(move |_, act, ctx| {
+                if act.gen == gen {
+                    act.failed(ctx);
+                }
+            })
+            .spawn(ctx);
+    }
+
+    /// Drop transport connection, unsent messages stay pending
+    fn disconnect(&mut self, ctx: &mut Context<Self>) {
+        self.gen += 1;
+        self.ws = None;
+        if let Some(handle) = self.stream.take() {
+            ctx.cancel_future(handle);
+        }
+        if let Some(mut msgs) = self.sending.take() {
+            msgs.extend(self.pending.drain(..));
+            self.pending = msgs;
+        }
+    }
+
+    /// Session is closed by the server
+    fn closed(&mut self, code: usize, reason: String, ctx: &mut Context<Self>) {
+        self.event(ClientEvent::Close(code, reason));
+        self.disconnect(ctx);
+        self.state = State::Closed;
+        ctx.stop();
+    }
+
+    /// Transport failed, try next transport or reconnect
+    fn failed(&mut self, ctx: &mut Context<Self>) {
+        self.disconnect(ctx);
+
+        match self.state {
+            State::Connecting => {
+                if self.idx + 1 < self.transports.len() {
+                    self.idx += 1;
+                    self.connect(ctx);
+                    return
+                }
+            }
+            State::Open => self.event(ClientEvent::Interrupted),
+            State::Closed => return,
+        }
+
+        if let Some(delay) = self.reconnect {
+            self.idx = 0;
+            self.state = State::Connecting;
+            ctx.run_later(delay, |act, ctx| act.connect(ctx));
+        } else {
+            if self.state == State::Connecting {
+                let _ = self.events.unbounded_send(Err(ClientError::Unavailable));
+            }
+            self.state = State::Closed;
+            ctx.stop();
+        }
+    }
+}
+
+impl Actor for Connection {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        if self.transports.is_empty() {
+            let _ = self.events.unbounded_send(Err(ClientError::Unavailable));
+            ctx.stop();
+            return
+        }
+
+        if let Some(timeout) = self.heartbeat {
+            ctx.run_interval(timeout / 2, move |act, ctx| {
+                if act.state == State::Open && act.seen.elapsed() > timeout {
+                    act.failed(ctx);
+                }
+            });
+        }
+        self.connect(ctx);
+    }
+}
+
+/// Frames of current transport connection
+impl StreamHandler<(usize, Incoming), ()> for Connection {
+    fn handle(&mut self, (gen, frame): (usize, Incoming), ctx: &mut Context<Self>) {
+        if gen != self.gen {
+            return
+        }
+        self.seen = Instant::now();
+
+        match frame {
+            Incoming::Frame(Frame::Open) => {
+                if self.state == State::Connecting {
+                    self.state = State::Open;
+                    self.event(ClientEvent::Open(self.transports[self.idx]));
+                    self.flush(ctx);
+                }
+            }
+            Incoming::Frame(Frame::MessageVec(msgs)) => match decode::xhr_send(msgs.as_bytes()) {
+                Ok(msgs) => for msg in msgs {
+                    self.event(ClientEvent::Message(msg));
+                },
+                Err(_) => self.failed(ctx),
+            },
+            Incoming::Frame(Frame::Message(msg)) => self.event(ClientEvent::Message(msg)),
+            Incoming::Frame(Frame::Close(code)) => {
+                self.closed(code.num(), code.reason().to_owned(), ctx)
+            }
+            Incoming::Frame(Frame::Heartbeat) | Incoming::Frame(Frame::MessageBlob(_)) => (),
+            Incoming::Close(code, reason) => self.closed(code, reason, ctx),
+            Incoming::Eof => {
+                let kind = self.transports[self.idx];
+                match kind {
+                    // response is complete, poll again
+                    TransportKind::Xhr | TransportKind::XhrStreaming
+                        if self.state == State::Open => self.poll(kind, ctx),
+                    _ => self.failed(ctx),
+                }
+            }
+            Incoming::Error => self.failed(ctx),
+        }
+    }
+
+    fn finished(&mut self, _: &mut Context<Self>) {}
+}
+
+/// Messages from `SockJSClient`
+impl StreamHandler<String, ()> for Connection {
+    fn handle(&mut self, msg: String, ctx: &mut Context<Self>) {
+        self.pending.push(msg);
+        if self.state == State::Open {
+            self.flush(ctx);
+        }
+    }
+
+    /// Client is dropped or closed
+    fn finished(&mut self, ctx: &mut Context<Self>) {
+        ctx.stop();
+    }
+}
@@ -0,0 +1,136 @@
+//! Client side framing of sockjs transports
+use std::str;
+
+use actix_web::error::PayloadError;
+use actix_web::ws;
+use bytes::{Bytes, BytesMut};
+use futures::{Async, Poll, Stream};
+
+use protocol::{decode, Frame};
+
+/// Event of transport connection
+#[derive(Debug, PartialEq)]
+pub(crate) enum Incoming {
+    /// Sockjs frame
+    Frame(Frame),
+    /// Close frame, `c[code,"reason"]`, close code may be unknown to `CloseCode`
+    Close(usize, String),
+    /// Transport connection is closed
+    Eof,
+    /// Malformed frame or transport error
+    Error,
+}
+
+/// Decode sockjs frame
+fn incoming(text: &str) -> Incoming {
+    if let Ok((code, reason)) = decode::close(text) {
+        return Incoming::Close(code, reason)
+    }
+    match decode::frame(text) {
+        Ok(frame) => Incoming::Frame(frame),
+        Err(_) => Incoming::Error,
+    }
+}
+
+/// Newline delimited frames of xhr transports.
+///
+/// Line longer than `maxsize` fails stream with `PayloadError::Overflow`.
+pub(crate) struct Lines<S> {
+    stream: S,
+    buf: BytesMut,
+    maxsize: usize,
+}
+
+impl<S> Lines<S> {
+    pub fn new(stream: S, maxsize: usize) -> Lines<S> {
+        Lines { stream, maxsize, buf: BytesMut::new() }
+    }
+}
+
+impl<S> Stream for Lines<S>
+    where S: Stream<Item=Bytes, Error=PayloadError>
+{
+    type Item = Incoming;
+    type Error = PayloadError;
+
+    fn poll(&mut self) -> Poll<Option<Incoming>, PayloadError> {
+        loop {
+            if let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+                if pos > self.maxsize {
+                    return Err(PayloadError::Overflow)
+                }
+                let line = self.buf.split_to(pos + 1);
+                let frame = match str::from_utf8(&line) {
+                    Ok(line) => incoming(line),
+                    Err(_) => Incoming::Error,
+                };
+                return Ok(Async::Ready(Some(frame)))
+            }
+            if self.buf.len() > self.maxsize {
+                return Err(PayloadError::Overflow)
+            }
+            match self.stream.poll()? {
+                Async::Ready(Some(chunk)) => self.buf.extend_from_slice(&chunk),
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// Frames of websocket transport, one frame per text message
+pub(crate) struct WsFrames(pub ws::ClientReader);
+
+impl Stream for WsFrames {
+    type Item = Incoming;
+    type Error = ws::ProtocolError;
+
+    fn poll(&mut self) -> Poll<Option<Incoming>, ws::ProtocolError> {
+        loop {
+            match self.0.poll()? {
+                Async::Ready(Some(ws::Message::Text(text))) => {
+                    return Ok(Async::Ready(Some(incoming(&text))))
+                }
+                Async::Ready(Some(ws::Message::Close(_))) | Async::Ready(None) => {
+                    return Ok(Async::Ready(None))
+                }
+                Async::Ready(Some(_)) => (),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// Tags frames with connection generation.
+///
+/// Transport error and end of stream get delivered as
+/// `Incoming::Error` and `Incoming::Eof` frames.
+pub(crate) struct Tagged<S> {
+    gen: usize,
+    stream: Option<S>,
+}
+
+impl<S> Tagged<S> {
+    pub fn new(gen: usize, stream: S) -> Tagged<S> {
+        Tagged { gen, stream: Some(stream) }
+    }
+}
+
+impl<S> Stream for Tagged<S> where S: Stream<Item=Incoming> {
+    type Item = (usize, Incoming);
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<(usize, Incoming)>, ()> {
+        let last = match self.stream {
+            Some(ref mut stream) => match stream.poll() {
+                Ok(Async::Ready(Some(frame))) => return Ok(Async::Ready(Some((self.gen, frame)))),
+                Ok(Async::Ready(None)) => Incoming::Eof,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(_) => Incoming::Error,
+            },
+            None => return Ok(Async::Ready(None)),
+        };
+        self.stream = None;
+        Ok(Async::Ready(Some((self.gen, last))))
+    }
+}
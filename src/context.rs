@@ -1,31 +1,54 @@
 use std;
+use std::mem;
+use std::rc::Rc;
 use std::sync::Arc;
-use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, VecDeque};
 
 use actix::dev::*;
 use actix::{ActorState, Message as ActixMessage};
 
+use bytes::Bytes;
 use serde_json;
-use futures::{Async, Future, Poll, Stream};
-use futures::sync::oneshot::Sender;
+use futures::{task, Async, Future, Poll, Stream};
+use futures::sync::oneshot::{self, Sender};
 use futures::sync::mpsc::{unbounded, UnboundedSender, UnboundedReceiver};
 
 use session::{Message, Session, CloseReason};
-use protocol::{CloseCode, Frame};
-use manager::{SockJSManager, Broadcast};
+use protocol::{encode, CloseCode, Frame};
+use transports::TransportInfo;
+use manager::{SockJSManager, Broadcast, Persist, Suspend, SuspendedState};
+use reliable::Control;
+use topics::{Publish, SubscribeTopic, UnsubscribeTopic};
+use rpc::{self, CallError, RpcCall, RpcError, RpcMessage};
+use serde_json::Value;
 
 #[derive(Debug)]
 pub enum SockJSChannel {
     Opened,
-    Acquired(UnboundedSender<ChannelItem>),
+    Resumed(SuspendedState),
+    Restored(Option<Vec<u8>>),
+    Acquired(UnboundedSender<ChannelItem>, TransportInfo),
     Released,
+    Message(Message),
+    Binary(Bytes),
+    Rpc(RpcMessage),
+    Save,
+    /// Fail rpc calls with deadline before given time
+    Expire(Instant),
     Closed(CloseReason),
 }
 
+/// Message from session context to transport
 #[derive(Message, Debug)]
 pub enum ChannelItem {
+    /// Frame to send
     Frame(Frame),
+    /// Transport is registered within session context
     Ready,
+    /// Reliable mode control message, applied to the record held by transport
+    #[doc(hidden)]
+    Control(Control),
 }
 
 #[derive(Debug)]
@@ -44,6 +67,8 @@ impl BufItem {
     }
 }
 
+/// Method handler of JSON-RPC layer
+type Method<A> = Rc<Fn(&mut A, Value, &mut SockJSContext<A>) -> Result<Value, RpcError>>;
 
 /// Sockjs session context
 pub struct SockJSContext<A> where A: Session, A::Context: AsyncContext<A>
@@ -54,6 +79,16 @@ pub struct SockJSContext<A> where A: Session, A::Context: AsyncContext<A>
     tx: Option<UnboundedSender<ChannelItem>>,
     buf: VecDeque<BufItem>,
     sm: Addr<Syn, SockJSManager<A>>,
+    resume: Option<String>,
+    transport: Option<TransportInfo>,
+    coalesce: Option<(Duration, usize)>,
+    pending: Vec<String>,
+    /// Flush of coalesced messages after window
+    flush_timer: Option<SpawnHandle>,
+    rpc: Option<Duration>,
+    methods: HashMap<String, Method<A>>,
+    calls: HashMap<u64, (Sender<Result<Value, CallError>>, SpawnHandle, Instant)>,
+    call_id: u64,
 }
 
 impl<A> ActorContext for SockJSContext<A> where A: Session<Context=Self>
@@ -124,9 +159,35 @@ impl<A> SockJSContext<A> where A: Session<Context=Self>
         &self.sid
     }
 
+    /// Address of session manager
+    pub(crate) fn manager(&self) -> &Addr<Syn, SockJSManager<A>> {
+        &self.sm
+    }
+
+    /// Resume token of this session, available if session manager
+    /// is configured with resumable sessions
+    pub fn resume_token(&self) -> Option<&str> {
+        self.resume.as_ref().map(|s| s.as_str())
+    }
+
     /// Send message to peer
     pub fn send<M>(&mut self, message: M) where M: Into<Message> {
-        self.send_frame(Frame::Message(message.into().0));
+        let msg = message.into().0;
+        if let Some((window, max)) = self.coalesce {
+            if self.tx.is_some() {
+                self.pending.push(msg);
+                if self.pending.len() >= max {
+                    self.flush();
+                } else if self.pending.len() == 1 {
+                    self.flush_timer = Some(self.run_later(window, |_, ctx| {
+                        ctx.flush_timer.take();
+                        ctx.flush()
+                    }));
+                }
+                return
+            }
+        }
+        self.send_frame(Frame::Message(msg));
     }
 
     /// Send message to all sessions
@@ -134,12 +195,86 @@ impl<A> SockJSContext<A> where A: Session<Context=Self>
         self.sm.do_send(Broadcast::new(Frame::Message(message.into().0)));
     }
 
+    /// Subscribe to topics matching `pattern`, see `SubscribeTopic`
+    pub fn subscribe<T: Into<String>>(&mut self, pattern: T) {
+        self.sm.do_send(SubscribeTopic {sid: Arc::clone(&self.sid), pattern: pattern.into()});
+    }
+
+    /// Remove topic subscription
+    pub fn unsubscribe<T: Into<String>>(&mut self, pattern: T) {
+        self.sm.do_send(UnsubscribeTopic {sid: Arc::clone(&self.sid), pattern: pattern.into()});
+    }
+
+    /// Publish message to sessions subscribed to `topic`
+    pub fn publish<T, M>(&mut self, topic: T, message: M)
+        where T: Into<String>, M: Into<Message>
+    {
+        self.sm.do_send(Publish::new(topic, message));
+    }
+
     /// Close session
     pub fn close(&mut self) {
         self.send_frame(Frame::Close(CloseCode::GoAway));
     }
 
+    /// Register JSON-RPC method, peer calls it with request object.
+    ///
+    /// Requires session manager configured with `SockJSManager::rpc()`.
+    /// Unknown methods get `Method not found` error response.
+    pub fn method<F>(&mut self, name: &str, f: F)
+        where F: Fn(&mut A, Value, &mut SockJSContext<A>) -> Result<Value, RpcError> + 'static
+    {
+        self.methods.insert(name.to_owned(), Rc::new(f));
+    }
+
+    /// Call peer's JSON-RPC method.
+    ///
+    /// Call fails if peer does not respond within timeout configured with
+    /// `SockJSManager::rpc()`. Pending calls fail once session get closed,
+    /// before `Session::closed()` is called.
+    pub fn call(&mut self, method: &str, params: Value) -> RpcCall {
+        let (tx, rx) = oneshot::channel();
+        if let Some(timeout) = self.rpc {
+            self.call_id += 1;
+            let id = self.call_id;
+            let handle = self.run_later(timeout, move |_, ctx| {
+                if let Some((tx, _, _)) = ctx.calls.remove(&id) {
+                    let _ = tx.send(Err(CallError::Timeout));
+                }
+            });
+            self.calls.insert(id, (tx, handle, Instant::now() + timeout));
+            self.send(Message(rpc::request(id, method, &params)));
+        } else {
+            let _ = tx.send(Err(CallError::Disabled));
+        }
+        RpcCall::new(rx)
+    }
+
+    /// Send coalesced messages
+    fn flush(&mut self) {
+        if let Some(handle) = self.flush_timer.take() {
+            self.cancel_future(handle);
+        }
+        let mut msgs = mem::replace(&mut self.pending, Vec::new());
+        match msgs.len() {
+            0 => (),
+            1 => self.send_frame(Frame::Message(msgs.pop().unwrap())),
+            _ => if self.tx.is_some() {
+                self.send_frame(Frame::MessageVec(encode::array(&msgs)));
+            } else {
+                for msg in msgs {
+                    self.add_to_buf(Frame::Message(msg));
+                }
+            },
+        }
+    }
+
     fn send_frame(&mut self, frm: Frame) {
+        // coalesced messages go first
+        if !self.pending.is_empty() {
+            self.flush();
+        }
+
         let msg = if let Some(ref mut tx) = self.tx {
             match tx.unbounded_send(ChannelItem::Frame(frm)) {
                 Ok(()) => return,
@@ -153,8 +288,17 @@ impl<A> SockJSContext<A> where A: Session<Context=Self>
         } else {
             frm
         };
-        self.add_to_buf(msg);
         self.tx.take();
+        if let Frame::MessageVec(ref msgs) = msg {
+            // coalesced messages, transport is gone
+            if let Ok(msgs) = serde_json::from_str::<Vec<String>>(msgs) {
+                for msg in msgs {
+                    self.add_to_buf(Frame::Message(msg));
+                }
+                return
+            }
+        }
+        self.add_to_buf(msg);
     }
 
     /// Check if transport is connected
@@ -162,6 +306,15 @@ impl<A> SockJSContext<A> where A: Session<Context=Self>
         self.tx.is_some()
     }
 
+    /// Transport connected to the session
+    pub fn transport(&self) -> Option<&TransportInfo> {
+        if self.tx.is_some() {
+            self.transport.as_ref()
+        } else {
+            None
+        }
+    }
+
     fn add_to_buf(&mut self, msg: Frame) {
         let is_msg = if let Some(front) = self.buf.back() {
             front.is_msg()} else { false };
@@ -189,15 +342,23 @@ impl<A> SockJSContext<A> where A: Session<Context=Self>
 
 impl<A> SockJSContext<A> where A: Session<Context=Self>
 {
-    pub(crate) fn start(session: A, sid: Arc<String>, addr: Addr<Syn, SockJSManager<A>>)
+    pub(crate) fn start(session: A, sid: Arc<String>, addr: Addr<Syn, SockJSManager<A>>,
+                        resume: Option<String>, coalesce: Option<(Duration, usize)>,
+                        rpc: Option<Duration>)
                         -> (Addr<Syn, A>, UnboundedSender<SockJSChannel>)
     {
         let (tx, rx) = unbounded();
 
         let mut ctx = SockJSContext {
-            sid, rx,
+            sid, rx, resume, coalesce, rpc,
+            pending: Vec::new(),
+            flush_timer: None,
+            methods: HashMap::new(),
+            calls: HashMap::new(),
+            call_id: 0,
             inner: ContextImpl::new(Some(session)),
             tx: None,
+            transport: None,
             buf: VecDeque::new(),
             sm: addr,
         };
@@ -221,14 +382,22 @@ impl<A> Future for SockJSContext<A> where A: Session<Context=Self>
         match self.inner.poll(ctx) {
             Ok(Async::NotReady) => {
                 // sockjs channel
+                let mut handled = false;
                 loop {
                     match self.rx.poll() {
                         Ok(Async::Ready(Some(msg))) => {
+                            handled = true;
                             match msg {
                                 SockJSChannel::Opened => {
                                     self.inner.actor().opened(ctx);
                                 },
-                                SockJSChannel::Acquired(tx) => {
+                                SockJSChannel::Resumed(state) => {
+                                    self.inner.actor().resumed(ctx, state.0);
+                                },
+                                SockJSChannel::Restored(state) => {
+                                    self.inner.actor().restored(ctx, state);
+                                },
+                                SockJSChannel::Acquired(tx, transport) => {
                                     while let Some(msg) = self.buf.pop_front() {
                                         match msg {
                                             BufItem::Message(msg) => {
@@ -238,8 +407,7 @@ impl<A> Future for SockJSContext<A> where A: Session<Context=Self>
                                             BufItem::Messages(msg) => {
                                                 let _ = tx.unbounded_send(
                                                     ChannelItem::Frame(
-                                                        Frame::MessageVec(
-                                                            serde_json::to_string(&msg).unwrap())));
+                                                        Frame::MessageVec(encode::array(&msg))));
                                             },
                                             BufItem::Frame(msg) => {
                                                 let _ = tx.unbounded_send(ChannelItem::Frame(msg));
@@ -248,14 +416,96 @@ impl<A> Future for SockJSContext<A> where A: Session<Context=Self>
                                     };
                                     let _ = tx.unbounded_send(ChannelItem::Ready);
                                     self.tx = Some(tx);
-                                    self.inner.actor().acquired(ctx);
+                                    self.transport = Some(transport);
+                                    self.inner.actor().acquired(ctx, transport);
                                 }
                                 SockJSChannel::Released => {
                                     self.tx.take();
+                                    self.flush();
+                                    self.transport.take();
                                     self.inner.actor().released(ctx);
                                 },
+                                SockJSChannel::Message(msg) => {
+                                    Handler::<Message>::handle(self.inner.actor(), msg, ctx);
+                                },
+                                SockJSChannel::Binary(msg) => {
+                                    self.inner.actor().binary(ctx, msg);
+                                },
+                                SockJSChannel::Rpc(msg) => match msg {
+                                    RpcMessage::Request{id, method, params} => {
+                                        let method = self.methods.get(&method).cloned();
+                                        let result = match method {
+                                            Some(method) =>
+                                                (*method)(self.inner.actor(), params, ctx),
+                                            None => Err(RpcError::method_not_found()),
+                                        };
+                                        // notifications get no response
+                                        if let Some(id) = id {
+                                            self.send(Message(rpc::response(&id, &result)));
+                                        }
+                                    },
+                                    RpcMessage::Response{id, result} => {
+                                        if let Some((tx, handle, _)) = self.calls.remove(&id) {
+                                            self.cancel_future(handle);
+                                            let _ = tx.send(result.map_err(CallError::Error));
+                                        }
+                                    },
+                                    RpcMessage::Invalid => {
+                                        self.send(Message(rpc::invalid_request()));
+                                    },
+                                    RpcMessage::InvalidResponse => {
+                                        debug!("Invalid JSON-RPC response, session: {}",
+                                               self.sid);
+                                    },
+                                },
+                                SockJSChannel::Save => {
+                                    self.flush();
+                                    let state = self.inner.actor().save(ctx);
+                                    let mut messages = Vec::new();
+                                    for item in &self.buf {
+                                        match *item {
+                                            BufItem::Message(ref msg) =>
+                                                messages.push(msg.clone()),
+                                            BufItem::Messages(ref msgs) =>
+                                                messages.extend(msgs.iter().cloned()),
+                                            BufItem::Frame(_) => (),
+                                        }
+                                    }
+                                    self.sm.do_send(Persist {
+                                        sid: Arc::clone(&self.sid),
+                                        state, messages,
+                                    });
+                                },
+                                SockJSChannel::Expire(now) => {
+                                    let expired: Vec<_> = self.calls.iter()
+                                        .filter(|&(_, call)| call.2 <= now)
+                                        .map(|(id, _)| *id)
+                                        .collect();
+                                    for id in expired {
+                                        if let Some((tx, handle, _)) = self.calls.remove(&id) {
+                                            self.cancel_future(handle);
+                                            let _ = tx.send(Err(CallError::Timeout));
+                                        }
+                                    }
+                                },
                                 SockJSChannel::Closed(reason) => {
                                     self.tx.take();
+                                    self.transport.take();
+                                    if let CloseReason::Expired = reason {
+                                        if let Some(token) = self.resume.take() {
+                                            if let Some(state) =
+                                                self.inner.actor().suspend(ctx)
+                                            {
+                                                self.sm.do_send(Suspend {
+                                                    token,
+                                                    state: SuspendedState(state),
+                                                });
+                                            }
+                                        }
+                                    }
+                                    for (_, (tx, _, _)) in self.calls.drain() {
+                                        let _ = tx.send(Err(CallError::Closed));
+                                    }
                                     self.inner.actor().closed(ctx, reason);
                                     self.stop()
                                 }
@@ -267,6 +517,10 @@ impl<A> Future for SockJSContext<A> where A: Session<Context=Self>
                         Err(_) => {},
                     }
                 }
+                // session callbacks could spawn futures, poll them
+                if handled {
+                    task::current().notify();
+                }
                 Ok(Async::NotReady)
             },
             Ok(Async::Ready(())) => Ok(Async::Ready(())),
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use actix::{Message, Recipient, Syn};
+
+use session::CloseReason;
+use transports::TransportInfo;
+
+/// Session lifecycle event
+#[derive(Clone, Debug)]
+pub enum SessionEvent {
+    /// Session get opened
+    Opened(Arc<String>),
+    /// Transport acquired session
+    Acquired(Arc<String>, TransportInfo),
+    /// Transport released session
+    Released(Arc<String>),
+    /// Session get closed
+    Closed(Arc<String>, CloseReason),
+    /// Session expired
+    Expired(Arc<String>),
+}
+
+impl SessionEvent {
+    /// Session id
+    pub fn sid(&self) -> &Arc<String> {
+        match *self {
+            SessionEvent::Opened(ref sid)
+            | SessionEvent::Acquired(ref sid, _)
+            | SessionEvent::Released(ref sid)
+            | SessionEvent::Closed(ref sid, _)
+            | SessionEvent::Expired(ref sid) => sid,
+        }
+    }
+}
+
+impl Message for SessionEvent {
+    type Result = ();
+}
+
+/// Subscribe to lifecycle events of all sessions of session manager
+pub struct Subscribe(pub Recipient<Syn, SessionEvent>);
+
+impl Message for Subscribe {
+    type Result = ();
+}
@@ -0,0 +1,185 @@
+//! Handler function sessions
+//!
+//! Instead of implementing `Session` actor, session can be handled by
+//! a function that receives `(SessionSink, SessionStream)` pair and returns
+//! a future. Function get called once session is opened, session is closed
+//! once sink is closed or dropped.
+//!
+//! ```rust
+//! # extern crate futures;
+//! # extern crate sockjs;
+//! use futures::{Future, Stream};
+//! use sockjs::{Frame, SockJSManager};
+//! use sockjs::test::TestManager;
+//!
+//! fn main() {
+//!     let echo = SockJSManager::from_fn(|sink, stream| {
+//!         stream.forward(sink).map(|_| ())
+//!     });
+//!     let mut srv = TestManager::new(echo);
+//!
+//!     let tr = srv.connect("session");
+//!     srv.send(&tr, "hello");
+//!     assert_eq!(tr.frames(), vec![Frame::Open, Frame::Message("hello".to_owned())]);
+//! }
+//! ```
+use std::sync::Arc;
+
+use actix::*;
+use futures::{Async, AsyncSink, Future, IntoFuture, Poll, Sink, StartSend, Stream};
+use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+
+use context::SockJSContext;
+use manager::SockJSManager;
+use session::{CloseReason, Message, Session};
+
+type BoxedHandler =
+    Arc<Fn(SessionSink, SessionStream) -> Box<Future<Item=(), Error=()>> + Send + Sync>;
+
+/// Command from sink to session
+#[doc(hidden)]
+#[derive(Debug)]
+pub enum Outgoing {
+    Message(Message),
+    Broadcast(Message),
+    Close,
+}
+
+/// Session that runs handler function
+#[derive(Default)]
+pub struct HandlerSession {
+    handler: Option<BoxedHandler>,
+    tx: Option<UnboundedSender<Message>>,
+}
+
+impl Actor for HandlerSession {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for HandlerSession {
+    fn opened(&mut self, ctx: &mut SockJSContext<Self>) {
+        let handler = if let Some(ref handler) = self.handler {
+            Arc::clone(handler)
+        } else {
+            ctx.close();
+            return
+        };
+        let (tx, rx) = unbounded();
+        let (out_tx, out_rx) = unbounded();
+        self.tx = Some(tx);
+        ctx.add_stream(out_rx);
+
+        let sink = SessionSink {sid: Arc::clone(ctx.sid()), tx: out_tx, closed: false};
+        // handler outlives session, so it can observe end of stream
+        Arbiter::handle().spawn((*handler)(sink, SessionStream {rx}));
+    }
+
+    fn closed(&mut self, _: &mut SockJSContext<Self>, _: CloseReason) {
+        self.tx.take();
+    }
+}
+
+impl Handler<Message> for HandlerSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, _: &mut SockJSContext<Self>) {
+        if let Some(ref tx) = self.tx {
+            let _ = tx.unbounded_send(msg);
+        }
+    }
+}
+
+impl StreamHandler<Outgoing, ()> for HandlerSession {
+    fn handle(&mut self, msg: Outgoing, ctx: &mut SockJSContext<Self>) {
+        match msg {
+            Outgoing::Message(msg) => ctx.send(msg),
+            Outgoing::Broadcast(msg) => ctx.broadcast(msg),
+            Outgoing::Close => ctx.close(),
+        }
+    }
+
+    fn finished(&mut self, ctx: &mut SockJSContext<Self>) {
+        // all sinks are dropped
+        ctx.close()
+    }
+}
+
+impl SockJSManager<HandlerSession> {
+    /// Create session manager that handles every session with function `f`
+    pub fn from_fn<F, R>(f: F) -> Self
+        where F: Fn(SessionSink, SessionStream) -> R + Send + Sync + 'static,
+              R: IntoFuture<Item=(), Error=()>,
+              R::Future: 'static,
+    {
+        let handler: BoxedHandler = Arc::new(move |sink: SessionSink, stream: SessionStream| {
+            Box::new(f(sink, stream).into_future()) as Box<Future<Item=(), Error=()>>
+        });
+        SockJSManager::new(move || HandlerSession {
+            handler: Some(Arc::clone(&handler)),
+            tx: None,
+        })
+    }
+}
+
+/// Outbound half of the session.
+///
+/// Sink fails once session is closed. Closing or dropping all clones
+/// of the sink closes the session.
+#[derive(Clone)]
+pub struct SessionSink {
+    sid: Arc<String>,
+    tx: UnboundedSender<Outgoing>,
+    closed: bool,
+}
+
+impl SessionSink {
+    /// Session id
+    pub fn sid(&self) -> &Arc<String> {
+        &self.sid
+    }
+
+    /// Send message to all sessions
+    pub fn broadcast<M>(&self, message: M) -> Result<(), ()> where M: Into<Message> {
+        self.tx.unbounded_send(Outgoing::Broadcast(message.into())).map_err(|_| ())
+    }
+}
+
+impl Sink for SessionSink {
+    type SinkItem = Message;
+    type SinkError = ();
+
+    fn start_send(&mut self, msg: Message) -> StartSend<Message, ()> {
+        match self.tx.unbounded_send(Outgoing::Message(msg)) {
+            Ok(()) => Ok(AsyncSink::Ready),
+            Err(_) => Err(()),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), ()> {
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), ()> {
+        if !self.closed {
+            self.closed = true;
+            let _ = self.tx.unbounded_send(Outgoing::Close);
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Inbound half of the session, messages from the peer.
+///
+/// Stream ends once session is closed.
+pub struct SessionStream {
+    rx: UnboundedReceiver<Message>,
+}
+
+impl Stream for SessionStream {
+    type Item = Message;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Message>, ()> {
+        self.rx.poll()
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use actix::Message as ActixMessage;
+
+use manager::Broadcast;
+use protocol::Frame;
+
+/// Messages kept in history of channel.
+///
+/// Returns messages of all sessions broadcast channel if `topic` is `None`,
+/// otherwise messages published to `topic`. Messages are in send order.
+#[derive(Debug)]
+pub struct GetHistory {
+    /// Topic, `None` for broadcast channel
+    pub topic: Option<String>,
+}
+
+impl ActixMessage for GetHistory {
+    type Result = Vec<String>;
+}
+
+/// Bounded history of broadcast and published messages, per channel.
+///
+/// Channel `None` is broadcast to all sessions, `Some(topic)` is a topic.
+pub(crate) struct History {
+    max_messages: usize,
+    max_age: Option<Duration>,
+    /// Sequence number of last message, orders messages of all channels
+    seq: u64,
+    channels: HashMap<Option<String>, VecDeque<Entry>>,
+}
+
+struct Entry {
+    seq: u64,
+    time: Instant,
+    msg: Broadcast,
+}
+
+impl History {
+    pub fn new(max_messages: usize, max_age: Option<Duration>) -> History {
+        History {max_messages, max_age, seq: 0, channels: HashMap::new()}
+    }
+
+    /// Record message sent to channel, only messages are recorded
+    pub fn push(&mut self, channel: Option<&str>, msg: &Broadcast) {
+        if let Frame::Message(_) = *msg.msg {
+            if self.max_messages == 0 {
+                return
+            }
+            let messages = self.channels
+                .entry(channel.map(|s| s.to_owned()))
+                .or_insert_with(VecDeque::new);
+            if messages.len() >= self.max_messages {
+                messages.pop_front();
+            }
+            self.seq += 1;
+            messages.push_back(Entry {seq: self.seq, time: Instant::now(), msg: msg.clone()});
+        }
+    }
+
+    /// Drop messages older than max age, as if it is `now`
+    pub fn expire(&mut self, now: Instant) {
+        if let Some(max_age) = self.max_age {
+            for messages in self.channels.values_mut() {
+                while messages.front().map(|m| m.time + max_age <= now).unwrap_or(false) {
+                    messages.pop_front();
+                }
+            }
+            self.channels.retain(|_, messages| !messages.is_empty());
+        }
+    }
+
+    /// Messages of channels matching `filter`, oldest first
+    pub fn messages<F>(&mut self, filter: F) -> Vec<Broadcast>
+        where F: Fn(Option<&str>) -> bool
+    {
+        self.expire(Instant::now());
+        let mut res: Vec<&Entry> = Vec::new();
+        for (channel, messages) in &self.channels {
+            if filter(channel.as_ref().map(|s| s.as_str())) {
+                res.extend(messages.iter());
+            }
+        }
+        res.sort_by_key(|m| m.seq);
+        res.into_iter().map(|m| m.msg.clone()).collect()
+    }
+}
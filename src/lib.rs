@@ -9,6 +9,7 @@ extern crate md5;
 extern crate rand;
 extern crate regex;
 extern crate futures;
+extern crate flate2;
 #[macro_use]
 extern crate lazy_static;
 extern crate percent_encoding;
@@ -25,15 +26,44 @@ extern crate http;
 extern crate actix;
 extern crate actix_web;
 
+#[cfg(feature = "tracing")]
+extern crate tracing;
+
 mod context;
 mod application;
+mod events;
+mod handler;
+mod history;
 mod manager;
 mod session;
 mod utils;
 mod protocol;
+mod presence;
+mod reliable;
+mod rpc;
+mod store;
+mod topics;
+mod trace;
 mod transports;
+pub mod stomp;
+pub mod test;
+
+#[cfg(feature = "client")]
+pub mod client;
 
 pub use application::SockJS;
-pub use context::SockJSContext;
-pub use manager::SockJSManager;
+pub use context::{ChannelItem, SockJSContext};
+pub use events::{SessionEvent, Subscribe};
+pub use handler::{HandlerSession, SessionSink, SessionStream};
+pub use history::GetHistory;
+pub use manager::{Broadcast, Expire, Record, SessionManager, SessionMessage, SockJSManager};
+pub use protocol::{decode, encode, CloseCode, Frame};
+pub use rpc::{CallError, RpcCall, RpcError};
 pub use session::{Message, Session, CloseReason};
+pub use presence::{Presence, PresenceEvent, PresenceStatus, ListPresence, SubscribePresence};
+pub use store::{SessionStore, StoredSession, MemoryStore, FileStore};
+pub use topics::{Publish, SubscribeTopic, UnsubscribeTopic};
+pub use transports::{
+    Deflate, SendResult, Transport, TransportContext, TransportInfo, TransportKind, TransportMode,
+    TransportState,
+};
@@ -1,27 +1,65 @@
-use std::sync::Arc;
+use std::fmt;
+use std::any::Any;
+use std::sync::{Arc, Mutex, Weak};
 use std::ops::Deref;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::time::{Instant, Duration};
 use futures::sync::mpsc::{unbounded, UnboundedSender, UnboundedReceiver};
 
 use actix::*;
+use actix::dev::ToEnvelope;
 use actix::Message as ActixMessage;
-use protocol::Frame;
+use bytes::Bytes;
+use rand;
+use serde_json;
+use protocol::{encode, Frame};
 use context::{SockJSContext, SockJSChannel, ChannelItem};
+use reliable::{Control, Pending, Window};
+use rpc::RpcMessage;
+use store::{SessionStore, StoredSession};
+use presence::{Presence, PresenceEvent, PresenceStatus, ListPresence, SubscribePresence};
+use events::{SessionEvent, Subscribe};
+use topics::{pattern_matches, Publish, SubscribeTopic, Topics, UnsubscribeTopic};
+use history::{GetHistory, History};
+use trace::SessionSpan;
+use utils::notify;
+use transports::TransportInfo;
 use session::{Message, Session, SessionState, SessionError, CloseReason};
 
 #[doc(hidden)]
 pub trait SessionManager<S>: Actor<Context=Context<Self>> +
-    Handler<Acquire> + Handler<Release> + Handler<SessionMessage> {}
+    Handler<Acquire> + Handler<Release> + Handler<SessionMessage> + Handler<SessionBinary> {}
 
 /// Acquire message
 pub struct Acquire {
     sid: Arc<String>,
     addr: Recipient<Syn, Broadcast>,
+    transport: TransportInfo,
+    resume: Option<String>,
+    user: Option<String>,
+    remote: Option<String>,
 }
 impl Acquire {
-    pub fn new(sid: String, addr: Recipient<Syn, Broadcast>) -> Self {
-        Acquire{addr, sid: Arc::new(sid)}
+    pub fn new(sid: String, addr: Recipient<Syn, Broadcast>, transport: TransportInfo) -> Self {
+        Acquire{addr, transport, sid: Arc::new(sid), resume: None, user: None, remote: None}
+    }
+
+    /// Set remote address of the request
+    pub fn remote(mut self, remote: Option<String>) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    /// Set user key of the request
+    pub fn user(mut self, user: Option<String>) -> Self {
+        self.user = user;
+        self
+    }
+
+    /// Set resume token presented by peer
+    pub fn resume(mut self, token: Option<String>) -> Self {
+        self.resume = token;
+        self
     }
 }
 
@@ -35,10 +73,12 @@ pub struct Release {
     pub ses: Record,
 }
 
-/// Session message
+/// Session message, inbound message from the peer
 #[derive(Debug)]
 pub struct SessionMessage {
+    /// Session id
     pub sid: Arc<String>,
+    /// Message
     pub msg: Message,
 }
 
@@ -46,16 +86,120 @@ impl ActixMessage for SessionMessage {
     type Result = Result<(), ()>;
 }
 
+/// Binary message from the peer, raw websocket transport only
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct SessionBinary {
+    pub sid: Arc<String>,
+    pub msg: Bytes,
+}
+
+impl ActixMessage for SessionBinary {
+    type Result = ();
+}
+
+/// State of expired session
+pub struct SuspendedState(pub Box<Any + Send>);
+
+impl fmt::Debug for SuspendedState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SuspendedState")
+    }
+}
+
+/// Suspend message, stores state of expired session
+#[derive(Debug, Message)]
+pub struct Suspend {
+    pub token: String,
+    pub state: SuspendedState,
+}
+
+/// Expire message, expires sessions and pending rpc calls as if it is `now`
+#[derive(Debug, Message)]
+pub struct Expire {
+    pub now: Instant,
+}
+
+/// Persist message, saves session to session store
+#[derive(Debug, Message)]
+pub struct Persist {
+    pub sid: Arc<String>,
+    pub state: Option<Vec<u8>>,
+    pub messages: Vec<String>,
+}
+
+/// Wire format of encoded broadcast message
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Format {
+    Xhr,
+    XhrStreaming,
+    EventSource,
+    HtmlFile,
+    Jsonp,
+    Websocket,
+    RawWebsocket,
+}
+
 /// Broadcast message to all sessions
 #[derive(Debug, Message)]
 pub struct Broadcast {
     pub msg: Arc<Frame>,
 }
 
+/// Encoded messages of live broadcast frames
+struct Encoded {
+    /// Keeps frame allocation, so address is not reused while entry exists
+    frame: Weak<Frame>,
+    /// Message encoded per wire format
+    formats: [Option<Bytes>; 7],
+}
+
+lazy_static! {
+    /// Encoding cache shared by all recipients of a broadcast, keyed by frame address.
+    /// `Broadcast` keeps `msg` as its only field, so cache lives outside of it.
+    static ref ENCODED: Mutex<HashMap<usize, Encoded>> = Mutex::new(HashMap::new());
+}
+
 impl Broadcast {
     pub fn new(frm: Frame) -> Broadcast {
         Broadcast {msg: Arc::new(frm)}
     }
+
+    /// Message encoded to wire format, message get encoded once for all recipients.
+    ///
+    /// Returns `None` if broadcast frame is not a message.
+    pub(crate) fn encoded<F>(&self, format: Format, encode: F) -> Option<Bytes>
+        where F: FnOnce(&str) -> Bytes
+    {
+        if let Frame::Message(ref msg) = *self.msg {
+            let key = &*self.msg as *const Frame as usize;
+            let mut cache = ENCODED.lock().unwrap();
+            let live = cache.get(&key).map(|e| e.frame.upgrade().is_some()).unwrap_or(false);
+            if !live {
+                // drop entries of frames that are gone
+                cache.retain(|_, e| e.frame.upgrade().is_some());
+                cache.insert(key, Encoded {
+                    frame: Arc::downgrade(&self.msg),
+                    formats: Default::default(),
+                });
+            }
+            let slot = &mut cache.get_mut(&key).unwrap().formats[format as usize];
+            if let Some(ref data) = *slot {
+                return Some(data.clone())
+            }
+            let data = encode(msg);
+            *slot = Some(data.clone());
+            Some(data)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Arc<Frame>> for Broadcast {
+    fn from(msg: Arc<Frame>) -> Broadcast {
+        Broadcast {msg}
+    }
 }
 
 impl Clone for Broadcast {
@@ -67,14 +211,16 @@ impl Clone for Broadcast {
 #[derive(Debug)]
 pub enum RecordEntry {
     Frame(Frame),
-    Arc(Arc<Frame>),
+    Broadcast(Broadcast),
+    /// Frame with already numbered messages, reliable mode only
+    Numbered(Frame),
 }
 
 impl AsRef<Frame> for RecordEntry {
     fn as_ref(&self) -> &Frame {
         match *self {
-            RecordEntry::Frame(ref frame) => frame,
-            RecordEntry::Arc(ref frame) => frame.as_ref(),
+            RecordEntry::Frame(ref frame) | RecordEntry::Numbered(ref frame) => frame,
+            RecordEntry::Broadcast(ref msg) => msg.msg.as_ref(),
         }
     }
 }
@@ -84,8 +230,8 @@ impl Deref for RecordEntry {
 
     fn deref(&self) -> &Frame {
         match *self {
-            RecordEntry::Frame(ref frame) => frame,
-            RecordEntry::Arc(ref frame) => frame.as_ref(),
+            RecordEntry::Frame(ref frame) | RecordEntry::Numbered(ref frame) => frame,
+            RecordEntry::Broadcast(ref msg) => msg.msg.as_ref(),
         }
     }
 }
@@ -98,13 +244,13 @@ impl From<Frame> for RecordEntry {
 
 impl From<Arc<Frame>> for RecordEntry {
     fn from(f: Arc<Frame>) -> RecordEntry {
-        RecordEntry::Arc(f)
+        RecordEntry::Broadcast(Broadcast::from(f))
     }
 }
 
 impl From<Broadcast> for RecordEntry {
     fn from(f: Broadcast) -> RecordEntry {
-        RecordEntry::Arc(f.msg)
+        RecordEntry::Broadcast(f)
     }
 }
 
@@ -113,43 +259,143 @@ pub struct Record {
     /// Session id
     pub sid: Arc<String>,
     /// Session state
-    pub state: SessionState,
+    pub(crate) state: SessionState,
     /// Peer messages, buffer for peer messages when transport is not connected
-    pub buffer: VecDeque<RecordEntry>,
+    pub(crate) buffer: VecDeque<RecordEntry>,
     /// Channel to context
     tx: UnboundedSender<SockJSChannel>,
+    /// Replay window, reliable mode only
+    window: Option<Window>,
+    /// Session span, `tracing` feature only
+    pub(crate) span: SessionSpan,
 }
 
 impl Record {
-    fn new(sid: Arc<String>, tx: UnboundedSender<SockJSChannel>) -> Record {
+    fn new(sid: Arc<String>, tx: UnboundedSender<SockJSChannel>, window: Option<Window>)
+           -> Record
+    {
         Record {
-            sid, tx,
+            span: SessionSpan::new(&sid),
+            sid, tx, window,
             state: SessionState::New,
             buffer: VecDeque::new(),
         }
     }
 
+    /// Mark session as closed, transport has to call this once it sends close frame
     pub fn close(&mut self) {
         self.state = SessionState::Closed;
     }
 
-    pub fn interrupted(&mut self) {
+    pub(crate) fn interrupted(&mut self) {
         if self.state == SessionState::Running {
             self.state = SessionState::Interrupted;
         }
     }
 
-    pub fn add<F: Into<RecordEntry>>(&mut self, frm: F) {
+    /// Messages get numbered, encoded broadcast messages can not be used
+    pub(crate) fn reliable(&self) -> bool {
+        self.window.is_some()
+    }
+
+    pub(crate) fn add<F: Into<RecordEntry>>(&mut self, frm: F) {
         self.buffer.push_back(frm.into())
     }
+
+    /// Number messages of the frame in reliable mode.
+    ///
+    /// Returns `None` if frame has to be sent as is.
+    pub(crate) fn prepare(&mut self, frm: &Frame) -> Option<Frame> {
+        if let Some(ref mut window) = self.window {
+            match *frm {
+                Frame::Message(ref msg) => Some(Frame::Message(window.push(msg))),
+                Frame::MessageVec(ref msgs) => {
+                    if let Ok(msgs) = serde_json::from_str::<Vec<String>>(msgs) {
+                        let msgs: Vec<_> = msgs.iter().map(|msg| window.push(msg)).collect();
+                        Some(Frame::MessageVec(encode::array(&msgs)))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Apply control message of peer, replayed messages go to the front of buffer
+    pub(crate) fn control(&mut self, ctl: Control) {
+        if let Some(ref mut window) = self.window {
+            match ctl {
+                Control::Ack(seq) => window.ack(seq),
+                Control::Replay(seq) => {
+                    let msgs = window.replay(seq);
+                    if !msgs.is_empty() {
+                        self.buffer.push_front(
+                            RecordEntry::Numbered(Frame::MessageVec(encode::array(&msgs))));
+                    }
+                }
+            }
+        }
+    }
 }
 
 struct Entry<S: Session> {
     addr: Addr<Syn, S>,
+    /// Channel to context, record may be held by transport
+    tx: UnboundedSender<SockJSChannel>,
     record: Option<Record>,
     transport: Option<Recipient<Syn, Broadcast>>,
     /// heartbeat
     tick: Instant,
+    /// Channel to transport that holds the record
+    channel: Option<UnboundedSender<ChannelItem>>,
+    /// Control messages transport could not get, applied on release
+    control: Pending,
+    /// User key
+    user: Option<String>,
+    /// Presence status, `None` once session left
+    presence: Option<PresenceStatus>,
+    /// Session is closed
+    closed: bool,
+    /// Session as it was last saved to session store
+    stored: Option<StoredSession>,
+}
+
+impl<S: Session> Entry<S> {
+    /// Pass control message to transport that holds the record,
+    /// control is kept until release if transport is gone already
+    fn control(&mut self, ctl: Control) {
+        let ctl = if let Some(ref tx) = self.channel {
+            match tx.unbounded_send(ChannelItem::Control(ctl)) {
+                Ok(_) => return,
+                Err(err) => err.into_inner(),
+            }
+        } else {
+            ctl
+        };
+        self.control.push(ctl);
+    }
+
+    /// Send broadcast message to transport or buffer it in session record
+    fn deliver(&mut self, msg: &Broadcast) {
+        if let Some(ref tr) = self.transport {
+            let _ = tr.send(msg.clone());
+            return
+        }
+        if let Some(ref mut rec) = self.record {
+            rec.add(msg.clone());
+        }
+    }
+
+    fn presence(&self, sid: &Arc<String>, status: PresenceStatus) -> Presence {
+        Presence {
+            status,
+            sid: Arc::clone(sid),
+            user: self.user.clone(),
+        }
+    }
 }
 
 /// Session manager
@@ -157,6 +403,19 @@ pub struct SockJSManager<S: Session> {
     idle: HashSet<Arc<String>>,
     sessions: HashMap<Arc<String>, Entry<S>>,
     factory: Box<Fn() -> S + Sync + Send>,
+    reliable: Option<usize>,
+    resumable: Option<Duration>,
+    coalesce: Option<(Duration, usize)>,
+    rpc: Option<Duration>,
+    suspended: HashMap<String, (Instant, SuspendedState)>,
+    store: Option<Box<SessionStore>>,
+    subscribers: Vec<Recipient<Syn, PresenceEvent>>,
+    listeners: Vec<Recipient<Syn, SessionEvent>>,
+    topics: Topics,
+    /// Exact destinations of STOMP subscriptions
+    destinations: Topics,
+    history: Option<History>,
+    replay: bool,
 }
 
 impl<S: Session> SessionManager<S> for SockJSManager<S> {}
@@ -167,6 +426,18 @@ impl<S: Session + Default> Default for SockJSManager<S> {
             idle: HashSet::new(),
             sessions: HashMap::new(),
             factory: Box::new(S::default),
+            reliable: None,
+            coalesce: None,
+            rpc: None,
+            resumable: None,
+            suspended: HashMap::new(),
+            store: None,
+            subscribers: Vec::new(),
+            listeners: Vec::new(),
+            topics: Topics::default(),
+            destinations: Topics::default(),
+            history: None,
+            replay: false,
         }
     }
 }
@@ -180,33 +451,188 @@ impl<S: Session> SockJSManager<S> {
             factory: Box::new(factory),
             idle: HashSet::new(),
             sessions: HashMap::new(),
+            reliable: None,
+            resumable: None,
+            coalesce: None,
+            rpc: None,
+            suspended: HashMap::new(),
+            store: None,
+            subscribers: Vec::new(),
+            listeners: Vec::new(),
+            topics: Topics::default(),
+            destinations: Topics::default(),
+            history: None,
+            replay: false,
+        }
+    }
+
+    /// Enable reliable delivery with replay window of `size` messages.
+    ///
+    /// Every outbound message get prefixed with sequence number, `"<seq>:<message>"`,
+    /// and is kept in replay window until peer acknowledges it.
+    /// Peer controls replay window with reserved messages, these are not
+    /// delivered to the session:
+    ///
+    /// * `"#ack:<seq>"` confirms receipt of all messages up to `seq`
+    /// * `"#replay:<seq>"` asks to resend all messages after `seq`,
+    ///   messages get replayed with next transport request
+    pub fn reliable(mut self, size: usize) -> Self {
+        self.reliable = Some(size);
+        self
+    }
+
+    /// Enable resumable sessions.
+    ///
+    /// Every new session sends resume token to the peer with reserved
+    /// message `"#resume:<token>"`. Once session expires, state returned by
+    /// `Session::suspend()` is kept for `timeout`. New session that presents
+    /// the token with `resume` query parameter, for example
+    /// `/sockjs/000/session/xhr?resume=<token>`, gets `Session::resumed()`
+    /// call instead of `Session::opened()`.
+    pub fn resumable(mut self, timeout: Duration) -> Self {
+        self.resumable = Some(timeout);
+        self
+    }
+
+    /// Coalesce outbound messages of connected sessions.
+    ///
+    /// Messages sent within `window` get batched into one frame, batch
+    /// is sent immediately once it has `max_messages` messages.
+    pub fn coalesce(mut self, window: Duration, max_messages: usize) -> Self {
+        self.coalesce = Some((window, max_messages));
+        self
+    }
+
+    /// Enable JSON-RPC 2.0 layer, calls to peer fail after `timeout`.
+    ///
+    /// Peer messages that are JSON-RPC 2.0 request or response objects are
+    /// not delivered to the session. Requests get dispatched to methods
+    /// registered with `SockJSContext::method()`, responses complete calls
+    /// made with `SockJSContext::call()`.
+    pub fn rpc(mut self, timeout: Duration) -> Self {
+        self.rpc = Some(timeout);
+        self
+    }
+
+    /// Keep history of messages sent to every channel, broadcast to all
+    /// sessions and every topic.
+    ///
+    /// History keeps last `max_messages` messages of the channel, if `max_age`
+    /// is set messages older than `max_age` get dropped.
+    pub fn history(mut self, max_messages: usize, max_age: Option<Duration>) -> Self {
+        self.history = Some(History::new(max_messages, max_age));
+        self
+    }
+
+    /// Replay history to joining sessions.
+    ///
+    /// New session gets history of broadcast channel, session subscribing
+    /// to topic pattern gets history of all matching topics.
+    pub fn replay_on_join(mut self, val: bool) -> Self {
+        self.replay = val;
+        self
+    }
+
+    /// Set session store.
+    ///
+    /// Sessions get saved when transport releases them and session changed
+    /// since last save, so session survives restart if peer reconnects
+    /// within session expiration time. Store is called from session manager
+    /// actor, so it should be fast.
+    pub fn store<T: SessionStore>(mut self, store: T) -> Self {
+        self.store = Some(Box::new(store));
+        self
+    }
+
+    /// Subscribe session to exact destination
+    pub(crate) fn subscribe_destination(&mut self, destination: String, sid: Arc<String>) {
+        if self.sessions.contains_key(&sid) {
+            self.destinations.subscribe(destination, sid);
+        }
+    }
+
+    pub(crate) fn unsubscribe_destination(&mut self, destination: &str, sid: &Arc<String>) {
+        self.destinations.unsubscribe(destination, sid);
+    }
+
+    /// Deliver message to open sessions subscribed to `destination`.
+    ///
+    /// Unlike topics, message goes to the session actor instead of
+    /// `Entry::deliver()`. STOMP `MESSAGE` frame carries `subscription`,
+    /// `message-id` and `ack` headers of the receiving session, so it can
+    /// not be shared `Broadcast` frame. Session context buffers frames
+    /// while transport is released, same as for regular messages.
+    pub(crate) fn deliver<M>(&self, destination: &str, msg: M)
+        where S: Handler<M>,
+              S::Context: ToEnvelope<Syn, S, M>,
+              M: ActixMessage + Clone + Send + 'static,
+              M::Result: Send,
+    {
+        if let Some(sids) = self.destinations.get(destination) {
+            for sid in sids {
+                if let Some(entry) = self.sessions.get(sid) {
+                    if !entry.closed {
+                        entry.addr.do_send(msg.clone());
+                    }
+                }
+            }
         }
     }
 
     fn hb(&self, ctx: &mut Context<Self>) {
         ctx.run_later(Duration::new(10, 0), |act, ctx| {
             act.hb(ctx);
+            act.expire(Instant::now());
+        });
+    }
 
-            let now = Instant::now();
-            let mut rem = Vec::new();
-            for sid in &act.idle {
-                if let Some(entry) = act.sessions.get(sid) {
-                    if entry.tick + Duration::new(10, 0) < now {
-                        rem.push(Arc::clone(sid));
-                    }
+    /// Remove idle sessions and suspended states that expired by `now`
+    fn expire(&mut self, now: Instant) {
+        if let Some(timeout) = self.resumable {
+            self.suspended.retain(|_, item| item.0 + timeout > now);
+        }
+        if let Some(ref mut history) = self.history {
+            history.expire(now);
+        }
+
+        let mut rem = Vec::new();
+        for sid in &self.idle {
+            if let Some(entry) = self.sessions.get(sid) {
+                if entry.tick + Duration::new(10, 0) < now {
+                    rem.push(Arc::clone(sid));
                 }
             }
+        }
 
-            for sid in rem {
-                act.idle.remove(&sid);
-                if let Some(entry) = act.sessions.remove(&sid) {
-                    if let Some(rec) = entry.record {
-                        let _ = rec.tx.unbounded_send(
-                            SockJSChannel::Closed(CloseReason::Expired));
+        for sid in rem {
+            self.idle.remove(&sid);
+            self.topics.remove(&sid);
+            self.destinations.remove(&sid);
+            if let Some(entry) = self.sessions.remove(&sid) {
+                if let Some(ref mut store) = self.store {
+                    if entry.stored.is_some() {
+                        if let Err(err) = store.remove(&sid) {
+                            error!("Can not remove session {}: {}", sid, err);
+                        }
                     }
                 }
+                if !entry.closed {
+                    notify(&mut self.listeners, SessionEvent::Expired(Arc::clone(&sid)));
+                }
+                if entry.presence.is_some() {
+                    notify(
+                        &mut self.subscribers,
+                        PresenceEvent::Leave(entry.presence(&sid, PresenceStatus::Away)));
+                }
+                if let Some(rec) = entry.record {
+                    if !entry.closed {
+                        rec.span.expired();
+                    }
+                    let _ = rec.tx.unbounded_send(
+                        SockJSChannel::Closed(CloseReason::Expired));
+                }
             }
-        });
+        }
     }
 }
 
@@ -224,29 +650,100 @@ impl<S: Session> Handler<Acquire> for SockJSManager<S> {
 
     fn handle(&mut self, msg: Acquire, ctx: &mut Context<Self>) -> Self::Result {
         if let Some(entry) = self.sessions.get_mut(&msg.sid) {
-            if let Some(rec) = entry.record.take() {
+            if let Some(mut rec) = entry.record.take() {
+                rec.span.acquired(msg.transport.name, msg.remote.as_ref().map(|s| s.as_str()));
                 let (tx, rx) = unbounded();
-                let _ = rec.tx.unbounded_send(SockJSChannel::Acquired(tx));
+                entry.channel = Some(tx.clone());
+                let _ = rec.tx.unbounded_send(SockJSChannel::Acquired(tx, msg.transport));
                 self.idle.remove(&msg.sid);
                 entry.transport = Some(msg.addr);
+                if rec.state == SessionState::Running &&
+                    entry.presence == Some(PresenceStatus::Away)
+                {
+                    entry.presence = Some(PresenceStatus::Online);
+                    notify(
+                        &mut self.subscribers,
+                        PresenceEvent::Join(entry.presence(&msg.sid, PresenceStatus::Online)));
+                }
+                if !entry.closed {
+                    notify(&mut self.listeners,
+                           SessionEvent::Acquired(Arc::clone(&msg.sid), msg.transport));
+                }
                 return Ok((rec, rx))
             } else {
                 return Err(SessionError::Acquired)
             }
         }
+        let state = if let Some(ref token) = msg.resume {
+            self.suspended.remove(token).map(|item| item.1)
+        } else {
+            None
+        };
+        let stored = if state.is_some() {
+            None
+        } else if let Some(ref mut store) = self.store {
+            store.load(&msg.sid).unwrap_or_else(|err| {
+                error!("Can not load session {}: {}", msg.sid, err);
+                None
+            })
+        } else {
+            None
+        };
+        let token = self.resumable.map(
+            |_| format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>()));
+
         let (addr, tx) = SockJSContext::start(
-            (*self.factory)(), Arc::clone(&msg.sid), ctx.address());
-        self.sessions.insert(
-            Arc::clone(&msg.sid),
-            Entry{addr,
-                  record: None,
-                  transport: Some(msg.addr),
-                  tick: Instant::now(),
-            });
-        let rec = Record::new(msg.sid, tx);
+            (*self.factory)(), Arc::clone(&msg.sid), ctx.address(), token.clone(), self.coalesce,
+            self.rpc);
+        let entry = Entry{addr,
+                          tx: tx.clone(),
+                          record: None,
+                          transport: Some(msg.addr),
+                          tick: Instant::now(),
+                          channel: None,
+                          control: Pending::default(),
+                          user: msg.user,
+                          presence: Some(PresenceStatus::Online),
+                          closed: false,
+                          stored: stored.clone(),
+        };
+        notify(&mut self.listeners, SessionEvent::Opened(Arc::clone(&msg.sid)));
+        notify(&mut self.listeners,
+               SessionEvent::Acquired(Arc::clone(&msg.sid), msg.transport));
+        notify(
+            &mut self.subscribers,
+            PresenceEvent::Join(entry.presence(&msg.sid, PresenceStatus::Online)));
+        self.sessions.insert(Arc::clone(&msg.sid), entry);
+        let mut rec = Record::new(msg.sid, tx, self.reliable.map(Window::new));
+        rec.span.acquired(msg.transport.name, msg.remote.as_ref().map(|s| s.as_str()));
+        if let Some(token) = token {
+            rec.add(Frame::Message(format!("#resume:{}", token)));
+        }
         let (tx, rx) = unbounded();
-        let _ = rec.tx.unbounded_send(SockJSChannel::Opened);
-        let _ = rec.tx.unbounded_send(SockJSChannel::Acquired(tx));
+        if let Some(state) = state {
+            let _ = rec.tx.unbounded_send(SockJSChannel::Resumed(state));
+        } else if let Some(stored) = stored {
+            // peer already opened this session
+            rec.state = SessionState::Running;
+            for msg in stored.messages {
+                rec.add(Frame::Message(msg));
+            }
+            let _ = rec.tx.unbounded_send(SockJSChannel::Restored(stored.state));
+        } else {
+            if self.replay {
+                if let Some(ref mut history) = self.history {
+                    for msg in history.messages(|channel| channel.is_none()) {
+                        rec.add(msg);
+                    }
+                }
+            }
+            rec.span.opened();
+            let _ = rec.tx.unbounded_send(SockJSChannel::Opened);
+        }
+        if let Some(entry) = self.sessions.get_mut(&rec.sid) {
+            entry.channel = Some(tx.clone());
+        }
+        let _ = rec.tx.unbounded_send(SockJSChannel::Acquired(tx, msg.transport));
         Ok((rec, rx))
     }
 }
@@ -255,18 +752,67 @@ impl<S: Session> Handler<Acquire> for SockJSManager<S> {
 impl<S: Session> Handler<Release> for SockJSManager<S> {
     type Result = ();
 
-    fn handle(&mut self, msg: Release, _: &mut Context<Self>) {
+    fn handle(&mut self, mut msg: Release, _: &mut Context<Self>) {
         if let Some(entry) = self.sessions.get_mut(&msg.ses.sid) {
             self.idle.insert(Arc::clone(&msg.ses.sid));
-            let _ = match msg.ses.state {
-                SessionState::Closed =>
-                    msg.ses.tx.unbounded_send(
-                        SockJSChannel::Closed(CloseReason::Normal)),
-                SessionState::Interrupted =>
-                    msg.ses.tx.unbounded_send(
-                        SockJSChannel::Closed(CloseReason::Interrupted)),
-                _ => msg.ses.tx.unbounded_send(SockJSChannel::Released)
+            entry.channel.take();
+            for ctl in entry.control.take() {
+                msg.ses.control(ctl);
+            }
+            let closed = entry.closed;
+            let sid = Arc::clone(&msg.ses.sid);
+            msg.ses.span.released();
+            let ev = match msg.ses.state {
+                SessionState::Closed => {
+                    entry.closed = true;
+                    let _ = msg.ses.tx.unbounded_send(
+                        SockJSChannel::Closed(CloseReason::Normal));
+                    SessionEvent::Closed(sid, CloseReason::Normal)
+                },
+                SessionState::Interrupted => {
+                    entry.closed = true;
+                    let _ = msg.ses.tx.unbounded_send(
+                        SockJSChannel::Closed(CloseReason::Interrupted));
+                    SessionEvent::Closed(sid, CloseReason::Interrupted)
+                },
+                _ => {
+                    let _ = msg.ses.tx.unbounded_send(SockJSChannel::Released);
+                    SessionEvent::Released(sid)
+                }
             };
+            if !closed {
+                if let SessionEvent::Closed(_, reason) = ev {
+                    msg.ses.span.closed(reason);
+                }
+                notify(&mut self.listeners, ev);
+            }
+            let ev = match msg.ses.state {
+                SessionState::Closed | SessionState::Interrupted =>
+                    entry.presence.take().map(|_| PresenceEvent::Leave(
+                        entry.presence(&msg.ses.sid, PresenceStatus::Away))),
+                SessionState::Running => {
+                    if entry.presence == Some(PresenceStatus::Online) {
+                        entry.presence = Some(PresenceStatus::Away);
+                        Some(PresenceEvent::Away(
+                            entry.presence(&msg.ses.sid, PresenceStatus::Away)))
+                    } else {
+                        None
+                    }
+                },
+                SessionState::New => None,
+            };
+            if let Some(ev) = ev {
+                notify(&mut self.subscribers, ev);
+            }
+            if let Some(ref mut store) = self.store {
+                if msg.ses.state == SessionState::Running {
+                    let _ = msg.ses.tx.unbounded_send(SockJSChannel::Save);
+                } else if entry.stored.take().is_some() {
+                    if let Err(err) = store.remove(&msg.ses.sid) {
+                        error!("Can not remove session {}: {}", msg.ses.sid, err);
+                    }
+                }
+            }
             entry.tick = Instant::now();
             entry.record = Some(msg.ses);
             entry.transport.take();
@@ -280,7 +826,24 @@ impl<S: Session> Handler<SessionMessage> for SockJSManager<S> {
 
     fn handle(&mut self, msg: SessionMessage, _: &mut Context<Self>) -> Self::Result {
         if let Some(entry) = self.sessions.get_mut(&msg.sid) {
-            entry.addr.do_send(msg.msg);
+            if self.reliable.is_some() {
+                if let Some(ctl) = Control::parse(&msg.msg.0) {
+                    if let Some(ref mut rec) = entry.record {
+                        rec.control(ctl);
+                    } else {
+                        entry.control(ctl);
+                    }
+                    return Ok(())
+                }
+            }
+            if self.rpc.is_some() {
+                if let Some(msg) = RpcMessage::parse(&msg.msg.0) {
+                    let _ = entry.tx.unbounded_send(SockJSChannel::Rpc(msg));
+                    return Ok(())
+                }
+            }
+            // same channel as binary and rpc messages, so peer's order is kept
+            let _ = entry.tx.unbounded_send(SockJSChannel::Message(msg.msg));
             Ok(())
         } else {
             Err(())
@@ -288,19 +851,201 @@ impl<S: Session> Handler<SessionMessage> for SockJSManager<S> {
     }
 }
 
+#[doc(hidden)]
+impl<S: Session> Handler<SessionBinary> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: SessionBinary, _: &mut Context<Self>) {
+        if let Some(entry) = self.sessions.get(&msg.sid) {
+            let _ = entry.tx.unbounded_send(SockJSChannel::Binary(msg.msg));
+        }
+    }
+}
+
+#[doc(hidden)]
+impl<S: Session> Handler<Suspend> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Suspend, _: &mut Context<Self>) {
+        if self.resumable.is_some() {
+            self.suspended.insert(msg.token, (Instant::now(), msg.state));
+        }
+    }
+}
+
+impl<S: Session> Handler<Expire> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Expire, _: &mut Context<Self>) {
+        self.expire(msg.now);
+
+        // rpc calls time out with context timers, expire them as well
+        if self.rpc.is_some() {
+            for entry in self.sessions.values() {
+                let _ = entry.tx.unbounded_send(SockJSChannel::Expire(msg.now));
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+impl<S: Session> Handler<Persist> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Persist, _: &mut Context<Self>) {
+        if let Some(ref mut store) = self.store {
+            // messages buffered in record are older than session's messages
+            let mut messages = Vec::new();
+            let entry = if let Some(entry) = self.sessions.get_mut(&msg.sid) {
+                if let Some(ref rec) = entry.record {
+                    for frm in &rec.buffer {
+                        match **frm {
+                            Frame::Message(ref m) => messages.push(m.clone()),
+                            Frame::MessageVec(ref m) => {
+                                if let Ok(msgs) = serde_json::from_str::<Vec<String>>(m) {
+                                    messages.extend(msgs);
+                                }
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+                entry
+            } else {
+                return
+            };
+            messages.extend(msg.messages);
+
+            // store is written only if session changed since last save
+            let session = StoredSession{state: msg.state, messages};
+            if entry.stored.as_ref() == Some(&session) {
+                return
+            }
+            if let Err(err) = store.save(&msg.sid, &session) {
+                error!("Can not save session {}: {}", msg.sid, err);
+            } else {
+                entry.stored = Some(session);
+            }
+        }
+    }
+}
+
+impl<S: Session> Handler<Subscribe> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _: &mut Context<Self>) {
+        self.listeners.push(msg.0);
+    }
+}
+
+impl<S: Session> Handler<SubscribePresence> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubscribePresence, _: &mut Context<Self>) {
+        self.subscribers.push(msg.0);
+    }
+}
+
+impl<S: Session> Handler<ListPresence> for SockJSManager<S> {
+    type Result = MessageResult<ListPresence>;
+
+    fn handle(&mut self, _: ListPresence, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(
+            self.sessions.iter()
+                .filter_map(|(sid, entry)| entry.presence.map(|status| {
+                    if entry.transport.is_some() {
+                        entry.presence(sid, status)
+                    } else {
+                        entry.presence(sid, PresenceStatus::Away)
+                    }
+                }))
+                .collect())
+    }
+}
+
 #[doc(hidden)]
 impl<S: Session> Handler<Broadcast> for SockJSManager<S> {
     type Result = ();
 
     fn handle(&mut self, msg: Broadcast, _: &mut Context<Self>) {
+        if let Some(ref mut history) = self.history {
+            history.push(None, &msg);
+        }
         for entry in self.sessions.values_mut() {
-            if let Some(ref tr) = entry.transport {
-                let _ = tr.send(msg.clone());
-                continue
+            entry.deliver(&msg);
+        }
+    }
+}
+
+impl<S: Session> Handler<SubscribeTopic> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubscribeTopic, _: &mut Context<Self>) {
+        if let Some(entry) = self.sessions.get_mut(&msg.sid) {
+            // closed session does not get messages anymore
+            if entry.closed {
+                return
+            }
+            if self.replay {
+                if let Some(ref mut history) = self.history {
+                    // topics of existing subscriptions are delivered already
+                    let pattern = &msg.pattern;
+                    let existing = self.topics.patterns(&msg.sid);
+                    let messages = history.messages(|channel| match channel {
+                        Some(t) => pattern_matches(pattern, t) &&
+                            !existing.iter().any(|p| pattern_matches(p, t)),
+                        None => false,
+                    });
+                    for msg in messages {
+                        entry.deliver(&msg);
+                    }
+                }
+            }
+        } else {
+            return
+        }
+        self.topics.subscribe(msg.pattern, msg.sid);
+    }
+}
+
+impl<S: Session> Handler<UnsubscribeTopic> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: UnsubscribeTopic, _: &mut Context<Self>) {
+        self.topics.unsubscribe(&msg.pattern, &msg.sid);
+    }
+}
+
+impl<S: Session> Handler<Publish> for SockJSManager<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Publish, _: &mut Context<Self>) {
+        if let Some(ref mut history) = self.history {
+            history.push(Some(&msg.topic), &msg.msg);
+        }
+        for sid in self.topics.subscribers(&msg.topic) {
+            if let Some(entry) = self.sessions.get_mut(&sid) {
+                if !entry.closed {
+                    entry.deliver(&msg.msg);
+                }
             }
-            if let Some(ref mut rec) = entry.record {
-                rec.add(Arc::clone(&msg.msg));
+        }
+    }
+}
+
+impl<S: Session> Handler<GetHistory> for SockJSManager<S> {
+    type Result = MessageResult<GetHistory>;
+
+    fn handle(&mut self, msg: GetHistory, _: &mut Context<Self>) -> Self::Result {
+        let mut res = Vec::new();
+        if let Some(ref mut history) = self.history {
+            let topic = msg.topic.as_ref().map(|t| t.as_str());
+            for msg in history.messages(|channel| channel == topic) {
+                if let Frame::Message(ref m) = *msg.msg {
+                    res.push(m.clone());
+                }
             }
         }
+        MessageResult(res)
     }
 }
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use actix::{Message, Recipient, Syn};
+
+/// Presence status of the session
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PresenceStatus {
+    /// Transport is connected
+    Online,
+    /// Session is temporarily without transport
+    Away,
+}
+
+/// Presence of the session
+#[derive(Clone, Debug)]
+pub struct Presence {
+    /// Session id
+    pub sid: Arc<String>,
+    /// User key, see `SockJS::presence_key()`
+    pub user: Option<String>,
+    /// Presence status
+    pub status: PresenceStatus,
+}
+
+/// Presence event
+#[derive(Clone, Debug)]
+pub enum PresenceEvent {
+    /// Session get opened or transport acquired session that was away
+    Join(Presence),
+    /// Transport released session, session waits for next transport request
+    Away(Presence),
+    /// Session get closed, interrupted or expired
+    Leave(Presence),
+}
+
+impl Message for PresenceEvent {
+    type Result = ();
+}
+
+/// Subscribe to presence events of session manager
+pub struct SubscribePresence(pub Recipient<Syn, PresenceEvent>);
+
+impl Message for SubscribePresence {
+    type Result = ();
+}
+
+/// List presence of all sessions of session manager.
+///
+/// Sessions between two polling requests are reported as `Away`.
+pub struct ListPresence;
+
+impl Message for ListPresence {
+    type Result = Vec<Presence>;
+}
+
+/// User key of the request, computed by `SockJS::presence_key()`
+pub(crate) struct UserKey(pub String);
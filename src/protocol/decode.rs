@@ -0,0 +1,135 @@
+//! Decoding of inbound messages
+//!
+//! Peers send messages as json string or json array of strings,
+//! `jsonp_send` additionally accepts form-urlencoded `d` field.
+//! Decoders are pure functions, transports map `DecodeError` to
+//! http response or websocket close frame. Client decodes frames
+//! sent by the server with `frame()` and `close()`.
+use std::borrow::Cow;
+
+use percent_encoding::percent_decode;
+use serde_json;
+
+use super::{CloseCode, Frame};
+
+/// Inbound message decoding error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodeError {
+    /// Empty payload or missing `d` field
+    PayloadExpected,
+    /// Payload is not json encoded string or array of strings
+    BrokenJson,
+}
+
+impl DecodeError {
+    /// Reason sent to the peer
+    pub fn reason(&self) -> &'static str {
+        match *self {
+            DecodeError::PayloadExpected => "Payload expected.",
+            DecodeError::BrokenJson => "Broken JSON encoding.",
+        }
+    }
+}
+
+/// Decode body of `xhr_send` request, json array of messages
+pub fn xhr_send(body: &[u8]) -> Result<Vec<String>, DecodeError> {
+    if body.is_empty() {
+        return Err(DecodeError::PayloadExpected)
+    }
+    serde_json::from_slice(body).map_err(|_| DecodeError::BrokenJson)
+}
+
+/// Decode body of `jsonp_send` request.
+///
+/// Form-urlencoded body carries json array in `d` field, otherwise
+/// body is json array.
+pub fn jsonp_send(body: &[u8], form: bool) -> Result<Vec<String>, DecodeError> {
+    if !form {
+        return xhr_send(body)
+    }
+
+    let data = match form_field(body, b"d") {
+        Some(data) => data,
+        None => return Err(DecodeError::PayloadExpected),
+    };
+    if data.is_empty() {
+        return Err(DecodeError::PayloadExpected)
+    }
+    match form_decode(data) {
+        Some(data) => serde_json::from_str(&data).map_err(|_| DecodeError::BrokenJson),
+        None => Err(DecodeError::PayloadExpected),
+    }
+}
+
+/// Decode text of websocket message, json string or json array of messages.
+///
+/// Empty message decodes to empty array.
+pub fn websocket(text: &str) -> Result<Vec<String>, DecodeError> {
+    let text = text.trim_left();
+    if text.is_empty() {
+        Ok(Vec::new())
+    } else if text.starts_with('[') {
+        serde_json::from_str(text).map_err(|_| DecodeError::BrokenJson)
+    } else {
+        serde_json::from_str(text).map(|msg| vec![msg]).map_err(|_| DecodeError::BrokenJson)
+    }
+}
+
+/// Raw value of first form field with `name`
+fn form_field<'a>(body: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    for pair in body.split(|b| *b == b'&') {
+        let mut kv = pair.splitn(2, |b| *b == b'=');
+        if kv.next() == Some(name) {
+            if let Some(value) = kv.next() {
+                return Some(value)
+            }
+        }
+    }
+    None
+}
+
+/// Decode form-urlencoded value, `+` is space
+fn form_decode(value: &[u8]) -> Option<String> {
+    let value: Cow<[u8]> = if value.contains(&b'+') {
+        Cow::Owned(value.iter().map(|b| if *b == b'+' { b' ' } else { *b }).collect())
+    } else {
+        Cow::Borrowed(value)
+    };
+    percent_decode(&value).decode_utf8().ok().map(|s| s.into_owned())
+}
+
+/// Decode frame sent by the server, trailing newline is ignored.
+///
+/// `a[...]` frame decodes to `Frame::MessageVec`, messages of the array
+/// are decoded with `xhr_send()`. Close frame decodes to `Frame::Close`
+/// only if close code is known, use `close()` for arbitrary close codes.
+/// Xhr-streaming prelude, line of `h` bytes, decodes to heartbeat.
+pub fn frame(text: &str) -> Result<Frame, DecodeError> {
+    let text = text.trim_right_matches(|c| c == '\r' || c == '\n');
+    match text.as_bytes().first() {
+        None => Err(DecodeError::PayloadExpected),
+        Some(&b'o') if text.len() == 1 => Ok(Frame::Open),
+        Some(&b'h') if text.bytes().all(|b| b == b'h') => Ok(Frame::Heartbeat),
+        Some(&b'a') if text[1..].starts_with('[') => Ok(Frame::MessageVec(text[1..].to_owned())),
+        Some(&b'c') => {
+            let (code, reason) = close(text)?;
+            let known = [CloseCode::Interrupted, CloseCode::GoAway,
+                         CloseCode::Acquired, CloseCode::InternalError];
+            known.iter()
+                .find(|known| known.num() == code && known.reason() == reason)
+                .map(|known| Frame::Close(*known))
+                .ok_or(DecodeError::BrokenJson)
+        }
+        _ => Err(DecodeError::BrokenJson),
+    }
+}
+
+/// Decode close frame sent by the server, `c[code,"reason"]`
+pub fn close(text: &str) -> Result<(usize, String), DecodeError> {
+    let text = text.trim_right_matches(|c| c == '\r' || c == '\n');
+    if text.starts_with('c') {
+        serde_json::from_str(&text[1..]).map_err(|_| DecodeError::BrokenJson)
+    } else {
+        Err(DecodeError::BrokenJson)
+    }
+}
@@ -0,0 +1,56 @@
+//! Encoding of outbound messages
+//!
+//! Messages are json encoded, additionally characters that browsers
+//! and proxies mangle get escaped as `\uXXXX`. Inbound decoders in
+//! `decode` module accept everything encoders produce.
+use serde_json;
+
+/// Json encoded string
+pub fn string(msg: &str) -> String {
+    // serializing strings can not fail
+    escape(serde_json::to_string(msg).unwrap())
+}
+
+/// Json encoded array of messages, payload of `Frame::MessageVec`
+/// and body of `xhr_send` request
+pub fn array<T: AsRef<str>>(msgs: &[T]) -> String {
+    let mut res = String::from("[");
+    for (idx, msg) in msgs.iter().enumerate() {
+        if idx > 0 {
+            res.push(',');
+        }
+        res.push_str(&string(msg.as_ref()));
+    }
+    res.push(']');
+    res
+}
+
+/// Message frame, `a["msg"]`
+pub fn message(msg: &str) -> String {
+    format!("a[{}]", string(msg))
+}
+
+/// Escape characters json encoder leaves as is
+fn escape(json: String) -> String {
+    if !json.chars().any(escapable) {
+        return json
+    }
+    let mut res = String::with_capacity(json.len() + 12);
+    for ch in json.chars() {
+        if escapable(ch) {
+            res.push_str(&format!("\\u{:04x}", ch as u32));
+        } else {
+            res.push(ch);
+        }
+    }
+    res
+}
+
+/// Characters sockjs protocol requires to be escaped by the server
+fn escapable(ch: char) -> bool {
+    match ch {
+        '\u{200c}'...'\u{200f}' | '\u{2028}'...'\u{202f}' |
+        '\u{2060}'...'\u{206f}' | '\u{fff0}'...'\u{ffff}' => true,
+        _ => false,
+    }
+}
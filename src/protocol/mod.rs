@@ -1,13 +1,23 @@
 use bytes::Bytes;
 use session::SessionError;
 
-#[derive(Debug, Message)]
+pub mod decode;
+pub mod encode;
+
+/// Sockjs frame
+#[derive(Clone, Debug, PartialEq, Message)]
 pub enum Frame {
+    /// Open frame, `o`
     Open,
+    /// Close frame, `c[code,"reason"]`
     Close(CloseCode),
+    /// Single message
     Message(String),
+    /// Binary message
     MessageBlob(Bytes),
+    /// Json encoded array of messages
     MessageVec(String),
+    /// Heartbeat frame, `h`
     Heartbeat,
 }
 
@@ -38,7 +48,8 @@ impl From<Bytes> for Frame {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+/// Close code of sockjs close frame
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum CloseCode {
     Interrupted,
     GoAway,
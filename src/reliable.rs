@@ -0,0 +1,101 @@
+use std::cmp;
+use std::collections::VecDeque;
+
+/// Reliable mode control message sent by peer
+#[derive(Debug, PartialEq)]
+pub enum Control {
+    /// Peer received all messages up to sequence number
+    Ack(u64),
+    /// Peer asks for all messages after sequence number
+    Replay(u64),
+}
+
+impl Control {
+    pub fn parse(msg: &str) -> Option<Control> {
+        if msg.starts_with("#ack:") {
+            msg[5..].parse().ok().map(Control::Ack)
+        } else if msg.starts_with("#replay:") {
+            msg[8..].parse().ok().map(Control::Replay)
+        } else {
+            None
+        }
+    }
+}
+
+/// Control messages received while transport releases the record.
+///
+/// Only highest ack and latest replay are kept, so pending controls
+/// do not grow with number of messages.
+#[derive(Debug, Default)]
+pub(crate) struct Pending {
+    ack: Option<u64>,
+    replay: Option<u64>,
+}
+
+impl Pending {
+    pub fn push(&mut self, ctl: Control) {
+        match ctl {
+            Control::Ack(seq) => self.ack = Some(cmp::max(seq, self.ack.unwrap_or(0))),
+            Control::Replay(seq) => self.replay = Some(seq),
+        }
+    }
+
+    /// Pending controls, ack goes first
+    pub fn take(&mut self) -> Vec<Control> {
+        let mut res = Vec::new();
+        if let Some(seq) = self.ack.take() {
+            res.push(Control::Ack(seq));
+        }
+        if let Some(seq) = self.replay.take() {
+            res.push(Control::Replay(seq));
+        }
+        res
+    }
+}
+
+/// Replay window of numbered outbound messages
+#[derive(Debug)]
+pub(crate) struct Window {
+    seq: u64,
+    capacity: usize,
+    messages: VecDeque<(u64, String)>,
+}
+
+impl Window {
+    pub fn new(capacity: usize) -> Window {
+        Window {
+            capacity,
+            seq: 0,
+            messages: VecDeque::new(),
+        }
+    }
+
+    /// Number message and keep it for replay, returns wire representation
+    pub fn push(&mut self, msg: &str) -> String {
+        self.seq += 1;
+        let msg = format!("{}:{}", self.seq, msg);
+        if self.capacity > 0 {
+            if self.messages.len() >= self.capacity {
+                self.messages.pop_front();
+            }
+            self.messages.push_back((self.seq, msg.clone()));
+        }
+        msg
+    }
+
+    /// Drop messages acknowledged by peer
+    pub fn ack(&mut self, seq: u64) {
+        while self.messages.front().map(|m| m.0 <= seq).unwrap_or(false) {
+            self.messages.pop_front();
+        }
+    }
+
+    /// Messages with sequence number greater than `seq`
+    pub fn replay(&self, seq: u64) -> Vec<String> {
+        self.messages
+            .iter()
+            .filter(|m| m.0 > seq)
+            .map(|m| m.1.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,203 @@
+//! JSON-RPC 2.0 over session messages
+use std::fmt;
+
+use futures::{Async, Future, Poll};
+use futures::sync::oneshot;
+use serde_json::{self, Value};
+
+/// Message is not a valid request object
+const INVALID_REQUEST: i64 = -32600;
+/// Method does not exist
+const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters
+const INVALID_PARAMS: i64 = -32602;
+/// Internal error
+const INTERNAL_ERROR: i64 = -32603;
+
+/// Error object of JSON-RPC response
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    /// Error code
+    pub code: i64,
+    /// Short description of the error
+    pub message: String,
+    /// Additional information about the error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    /// Create error object with `code` and `message`
+    pub fn new<T: Into<String>>(code: i64, message: T) -> RpcError {
+        RpcError {code, message: message.into(), data: None}
+    }
+
+    /// Set additional information
+    pub fn data(mut self, data: Value) -> RpcError {
+        self.data = Some(data);
+        self
+    }
+
+    /// Method does not exist, sent for unknown methods
+    pub fn method_not_found() -> RpcError {
+        RpcError::new(METHOD_NOT_FOUND, "Method not found")
+    }
+
+    /// Invalid method parameters
+    pub fn invalid_params() -> RpcError {
+        RpcError::new(INVALID_PARAMS, "Invalid params")
+    }
+
+    /// Internal error
+    pub fn internal_error() -> RpcError {
+        RpcError::new(INTERNAL_ERROR, "Internal error")
+    }
+
+    fn invalid_request() -> RpcError {
+        RpcError::new(INVALID_REQUEST, "Invalid Request")
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+/// Error of call to peer method
+#[derive(Clone, Debug, PartialEq)]
+pub enum CallError {
+    /// Peer responded with error
+    Error(RpcError),
+    /// Peer did not respond within timeout
+    Timeout,
+    /// Session get closed before peer responded
+    Closed,
+    /// Session manager is not configured with rpc, see `SockJSManager::rpc()`
+    Disabled,
+}
+
+/// Pending call to peer method, resolves with result of the call
+pub struct RpcCall {
+    rx: oneshot::Receiver<Result<Value, CallError>>,
+}
+
+impl RpcCall {
+    pub(crate) fn new(rx: oneshot::Receiver<Result<Value, CallError>>) -> RpcCall {
+        RpcCall {rx}
+    }
+}
+
+impl Future for RpcCall {
+    type Item = Value;
+    type Error = CallError;
+
+    fn poll(&mut self) -> Poll<Value, CallError> {
+        match self.rx.poll() {
+            Ok(Async::Ready(Ok(value))) => Ok(Async::Ready(value)),
+            Ok(Async::Ready(Err(err))) => Err(err),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => Err(CallError::Closed),
+        }
+    }
+}
+
+/// JSON-RPC message received from peer
+#[doc(hidden)]
+#[derive(Debug, PartialEq)]
+pub enum RpcMessage {
+    /// Method call, request without id is a notification
+    Request {
+        id: Option<Value>,
+        method: String,
+        params: Value,
+    },
+    /// Response to the call made with `SockJSContext::call()`
+    Response {
+        id: u64,
+        result: Result<Value, RpcError>,
+    },
+    /// Malformed request object, object with `method` member
+    Invalid,
+    /// Malformed response object, peer does not get answer to invalid response
+    InvalidResponse,
+}
+
+impl RpcMessage {
+    /// Parse peer message, returns `None` if message is not JSON-RPC 2.0 object
+    pub fn parse(msg: &str) -> Option<RpcMessage> {
+        if !msg.trim_left().starts_with('{') {
+            return None
+        }
+        let mut obj = match serde_json::from_str::<Value>(msg) {
+            Ok(Value::Object(obj)) => obj,
+            _ => return None,
+        };
+        if obj.get("jsonrpc").and_then(|v| v.as_str()) != Some("2.0") {
+            return None
+        }
+
+        let id = obj.remove("id");
+        if let Some(method) = obj.remove("method") {
+            return match method {
+                Value::String(method) => Some(RpcMessage::Request {
+                    id, method,
+                    params: obj.remove("params").unwrap_or(Value::Null),
+                }),
+                _ => Some(RpcMessage::Invalid),
+            }
+        }
+
+        let id = match id.as_ref().and_then(|id| id.as_u64()) {
+            Some(id) => id,
+            None => return Some(RpcMessage::InvalidResponse),
+        };
+        if let Some(result) = obj.remove("result") {
+            Some(RpcMessage::Response {id, result: Ok(result)})
+        } else if let Some(error) = obj.remove("error") {
+            match serde_json::from_value(error) {
+                Ok(error) => Some(RpcMessage::Response {id, result: Err(error)}),
+                Err(_) => Some(RpcMessage::InvalidResponse),
+            }
+        } else {
+            Some(RpcMessage::InvalidResponse)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    jsonrpc: &'static str,
+    method: &'a str,
+    params: &'a Value,
+    id: u64,
+}
+
+#[derive(Serialize)]
+struct Response<'a> {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<&'a Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a RpcError>,
+    id: &'a Value,
+}
+
+/// Encode request object
+pub(crate) fn request(id: u64, method: &str, params: &Value) -> String {
+    serde_json::to_string(&Request {jsonrpc: "2.0", method, params, id}).unwrap()
+}
+
+/// Encode response object
+pub(crate) fn response(id: &Value, result: &Result<Value, RpcError>) -> String {
+    let (result, error) = match *result {
+        Ok(ref value) => (Some(value), None),
+        Err(ref err) => (None, Some(err)),
+    };
+    serde_json::to_string(&Response {jsonrpc: "2.0", result, error, id}).unwrap()
+}
+
+/// Encode response to malformed request, only requests get answered
+pub(crate) fn invalid_request() -> String {
+    response(&Value::Null, &Err(RpcError::invalid_request()))
+}
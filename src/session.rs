@@ -1,7 +1,11 @@
+use std::any::Any;
+
 use actix::*;
+use bytes::Bytes;
 
 use protocol::Frame;
 use context::SockJSContext;
+use transports::TransportInfo;
 
 /// Session state
 #[derive(PartialEq, Debug)]
@@ -46,7 +50,7 @@ pub enum SessionError {
     InternalError,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 /// Reason for closing session
 pub enum CloseReason {
     /// Session closed session
@@ -65,11 +69,45 @@ pub trait Session: Actor<Context=SockJSContext<Self>> + Default + Handler<Messag
     fn opened(&mut self, ctx: &mut SockJSContext<Self>) {}
 
     /// Method get called when transport acquires this session
-    fn acquired(&mut self, ctx: &mut SockJSContext<Self>) {}
+    fn acquired(&mut self, ctx: &mut SockJSContext<Self>, transport: TransportInfo) {}
 
     /// Method get called when transport releases this session
     fn released(&mut self, ctx: &mut SockJSContext<Self>) {}
 
     /// Method get called when session get closed
     fn closed(&mut self, ctx: &mut SockJSContext<Self>, reason: CloseReason) {}
+
+    /// Method get called when session expires and session manager
+    /// is configured with resumable sessions.
+    ///
+    /// Returned state is passed to `resumed()` of new session that
+    /// presents resume token of this session.
+    fn suspend(&mut self, ctx: &mut SockJSContext<Self>) -> Option<Box<Any + Send>> {
+        None
+    }
+
+    /// Method get called instead of `opened()` when peer resumes
+    /// expired session. By default it calls `opened()`.
+    fn resumed(&mut self, ctx: &mut SockJSContext<Self>, state: Box<Any + Send>) {
+        self.opened(ctx)
+    }
+
+    /// Serialize session state for session store.
+    ///
+    /// Method get called every time transport releases session
+    /// and session manager is configured with session store.
+    fn save(&mut self, ctx: &mut SockJSContext<Self>) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Method get called instead of `opened()` when session get loaded
+    /// from session store. By default it calls `opened()`.
+    fn restored(&mut self, ctx: &mut SockJSContext<Self>, state: Option<Vec<u8>>) {
+        self.opened(ctx)
+    }
+
+    /// Method get called when peer sends binary message.
+    ///
+    /// Only raw websocket transport supports binary messages.
+    fn binary(&mut self, ctx: &mut SockJSContext<Self>, msg: Bytes) {}
 }
@@ -0,0 +1,160 @@
+//! STOMP 1.2 frame encoding
+use std::fmt;
+
+/// Frame parsing error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameError {
+    /// Header line without `:`
+    InvalidHeader,
+    /// Undefined escape sequence in header
+    InvalidEscape,
+    /// Frame is not terminated by NUL
+    MissingNul,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match *self {
+            FrameError::InvalidHeader => "Invalid header",
+            FrameError::InvalidEscape => "Invalid header escape sequence",
+            FrameError::MissingNul => "Frame is not terminated",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// STOMP frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct StompFrame {
+    /// Frame command, for example `SEND`
+    pub command: String,
+    /// Frame headers in wire order
+    pub headers: Vec<(String, String)>,
+    /// Frame body
+    pub body: String,
+}
+
+impl StompFrame {
+    /// Create frame without headers and body
+    pub fn new<T: Into<String>>(command: T) -> StompFrame {
+        StompFrame {command: command.into(), headers: Vec::new(), body: String::new()}
+    }
+
+    /// Add header
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set body
+    pub fn body<T: Into<String>>(mut self, body: T) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Value of header, first header wins if header is repeated
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|h| h.0 == name).map(|h| h.1.as_str())
+    }
+
+    /// Parse frame.
+    ///
+    /// Returns `Ok(None)` for heart-beat, message of end-of-lines only.
+    pub fn parse(msg: &str) -> Result<Option<StompFrame>, FrameError> {
+        let msg = msg.trim_left_matches(|c| c == '\r' || c == '\n');
+        if msg.is_empty() {
+            return Ok(None)
+        }
+
+        // headers end with empty line, end-of-line is `\n` or `\r\n`
+        let (head, body) = match (msg.find("\n\n"), msg.find("\n\r\n")) {
+            (Some(lf), Some(crlf)) if crlf < lf => (&msg[..crlf], &msg[crlf + 3..]),
+            (Some(lf), _) => (&msg[..lf], &msg[lf + 2..]),
+            (None, Some(crlf)) => (&msg[..crlf], &msg[crlf + 3..]),
+            (None, None) => return Err(FrameError::MissingNul),
+        };
+        let body = match body.rfind('\0') {
+            Some(pos) if body[pos + 1..].chars().all(|c| c == '\r' || c == '\n') => &body[..pos],
+            _ => return Err(FrameError::MissingNul),
+        };
+
+        let mut lines = head.split('\n').map(|line| line.trim_right_matches('\r'));
+        // leading end-of-lines are trimmed, so command is not empty
+        let command = lines.next().unwrap_or("");
+        // CONNECT and CONNECTED frames do not escape headers
+        let escaped = command != "CONNECT" && command != "CONNECTED";
+
+        let mut frame = StompFrame::new(command);
+        for line in lines {
+            let pos = match line.find(':') {
+                Some(pos) => pos,
+                None => return Err(FrameError::InvalidHeader),
+            };
+            let (name, value) = (&line[..pos], &line[pos + 1..]);
+            if escaped {
+                frame.headers.push((unescape(name)?, unescape(value)?));
+            } else {
+                frame.headers.push((name.to_owned(), value.to_owned()));
+            }
+        }
+
+        // body ends at first NUL or after content-length bytes
+        let len = frame.get("content-length").and_then(|len| len.parse::<usize>().ok());
+        frame.body = match len {
+            Some(len) if len <= body.len() && body.is_char_boundary(len) => &body[..len],
+            _ => match body.find('\0') {
+                Some(pos) => &body[..pos],
+                None => body,
+            },
+        }.to_owned();
+        Ok(Some(frame))
+    }
+}
+
+impl fmt::Display for StompFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let escaped = self.command != "CONNECT" && self.command != "CONNECTED";
+        writeln!(f, "{}", self.command)?;
+        for &(ref name, ref value) in &self.headers {
+            if escaped {
+                writeln!(f, "{}:{}", escape(name), escape(value))?;
+            } else {
+                writeln!(f, "{}:{}", name, value)?;
+            }
+        }
+        write!(f, "\n{}\0", self.body)
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            '\r' => res.push_str("\\r"),
+            '\n' => res.push_str("\\n"),
+            ':' => res.push_str("\\c"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+fn unescape(s: &str) -> Result<String, FrameError> {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('\\') => res.push('\\'),
+                Some('r') => res.push('\r'),
+                Some('n') => res.push('\n'),
+                Some('c') => res.push(':'),
+                _ => return Err(FrameError::InvalidEscape),
+            }
+        } else {
+            res.push(c);
+        }
+    }
+    Ok(res)
+}
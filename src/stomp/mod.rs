@@ -0,0 +1,434 @@
+//! STOMP 1.2 broker over sockjs sessions
+//!
+//! `StompSession` speaks STOMP with clients such as stomp.js, one frame per
+//! sockjs message. Clients subscribe to destinations, `SEND` frames get
+//! published to all subscribers of the destination in the session manager.
+//! `SEND` frames to application destinations, prefixed with
+//! `StompApp::prefix()`, are passed to `StompApp::send()` instead.
+//!
+//! Published messages are not broadcasts: every subscribed session builds
+//! its own `MESSAGE` frame with subscription id and message id, and tracks
+//! messages that wait for `ACK`.
+//!
+//! ```rust
+//! # extern crate sockjs;
+//! use sockjs::SockJSManager;
+//! use sockjs::stomp::{StompApp, StompBroker, StompFrame, StompPublish, StompSession};
+//!
+//! #[derive(Default)]
+//! struct Chat;
+//!
+//! impl StompApp for Chat {
+//!     fn send(&mut self, frame: StompFrame, broker: &StompBroker) -> Result<(), String> {
+//!         // "/app/chat" messages get published to "/topic/chat" subscribers
+//!         broker.publish(StompPublish::new("/topic/chat", frame.body));
+//!         Ok(())
+//!     }
+//! }
+//!
+//! fn main() {
+//!     let manager = SockJSManager::<StompSession<Chat>>::default();
+//! #   drop(manager);
+//! }
+//! ```
+use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+
+use actix::*;
+use actix::Message as ActixMessage;
+
+use context::SockJSContext;
+use manager::SockJSManager;
+use session::{Message, Session};
+
+mod frame;
+
+pub use self::frame::{FrameError, StompFrame};
+
+/// Headers set by broker, these are not copied from `SEND` frame to `MESSAGE` frame
+const RESERVED: &[&str] = &[
+    "destination", "receipt", "transaction", "content-length",
+    "subscription", "message-id", "ack"];
+
+/// Maximum number of messages waiting for `ACK` or `NACK`.
+///
+/// Client that falls behind gets `ERROR` frame and session get closed.
+pub const MAX_UNACKED: usize = 1000;
+
+/// Application hook of STOMP broker
+#[allow(unused_variables)]
+pub trait StompApp: Default + 'static {
+    /// Prefix of application destinations
+    fn prefix(&self) -> &str {
+        "/app/"
+    }
+
+    /// Method get called for `CONNECT` frame, error rejects the client
+    fn connect(&mut self, frame: &StompFrame) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Method get called for `SEND` frame to application destination.
+    ///
+    /// Error is sent to the client with `ERROR` frame and session get closed.
+    fn send(&mut self, frame: StompFrame, broker: &StompBroker) -> Result<(), String> {
+        Err(format!("Unknown destination {}", frame.get("destination").unwrap_or("")))
+    }
+}
+
+/// Broker without application destinations
+impl StompApp for () {}
+
+/// Message published to destination subscribers
+#[derive(Clone, Debug)]
+pub struct StompPublish {
+    destination: Arc<String>,
+    headers: Arc<Vec<(String, String)>>,
+    body: Arc<String>,
+}
+
+impl StompPublish {
+    pub fn new<D: Into<String>, B: Into<String>>(destination: D, body: B) -> StompPublish {
+        StompPublish {
+            destination: Arc::new(destination.into()),
+            headers: Arc::new(Vec::new()),
+            body: Arc::new(body.into()),
+        }
+    }
+
+    /// Add header of `MESSAGE` frame, for example `content-type`
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Self {
+        Arc::make_mut(&mut self.headers).push((name.into(), value.into()));
+        self
+    }
+}
+
+impl ActixMessage for StompPublish {
+    type Result = ();
+}
+
+/// Subscribe session to destination in session manager
+#[doc(hidden)]
+pub struct StompSubscribe {
+    sid: Arc<String>,
+    destination: String,
+}
+
+impl ActixMessage for StompSubscribe {
+    type Result = ();
+}
+
+/// Remove last subscription of session to destination
+#[doc(hidden)]
+pub struct StompUnsubscribe {
+    sid: Arc<String>,
+    destination: String,
+}
+
+impl ActixMessage for StompUnsubscribe {
+    type Result = ();
+}
+
+/// Publishes messages to destination subscribers of session manager
+pub struct StompBroker {
+    sid: Arc<String>,
+    sm: Recipient<Syn, StompPublish>,
+}
+
+impl StompBroker {
+    /// Session id of the client
+    pub fn sid(&self) -> &Arc<String> {
+        &self.sid
+    }
+
+    /// Publish message to subscribers of the destination
+    pub fn publish(&self, msg: StompPublish) {
+        let _ = self.sm.do_send(msg);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AckMode {
+    Auto,
+    /// Ack is cumulative
+    Client,
+    ClientIndividual,
+}
+
+struct Subscription {
+    destination: String,
+    ack: AckMode,
+}
+
+/// STOMP session.
+///
+/// Messages of `client` and `client-individual` subscriptions wait for
+/// `ACK` or `NACK`, at most `MAX_UNACKED` messages. `NACK` is accepted
+/// the same way as `ACK`: message is dropped, broker does not redeliver
+/// nacked messages. Transactions are not supported.
+pub struct StompSession<A: StompApp> {
+    app: A,
+    connected: bool,
+    subscriptions: HashMap<String, Subscription>,
+    seq: u64,
+    /// Message ids and subscription ids of messages that wait for ack
+    unacked: VecDeque<(String, String)>,
+}
+
+impl<A: StompApp> Default for StompSession<A> {
+    fn default() -> StompSession<A> {
+        StompSession {
+            app: A::default(),
+            connected: false,
+            subscriptions: HashMap::new(),
+            seq: 0,
+            unacked: VecDeque::new(),
+        }
+    }
+}
+
+impl<A: StompApp> Actor for StompSession<A> {
+    type Context = SockJSContext<Self>;
+}
+
+impl<A: StompApp> Session for StompSession<A> {}
+
+impl<A: StompApp> StompSession<A> {
+    /// Application of this session
+    pub fn app(&mut self) -> &mut A {
+        &mut self.app
+    }
+
+    /// Handle frame, returns `true` if session has to be closed
+    fn frame(&mut self, frame: StompFrame, ctx: &mut SockJSContext<Self>) -> Result<bool, String> {
+        let command = frame.command.clone();
+        if !self.connected {
+            return match command.as_str() {
+                "CONNECT" | "STOMP" => self.connect(&frame, ctx).map(|_| false),
+                _ => Err("Not connected".to_owned()),
+            }
+        }
+        match command.as_str() {
+            "SEND" => self.send(frame, ctx).map(|_| false),
+            "SUBSCRIBE" => self.subscribe(&frame, ctx).map(|_| false),
+            "UNSUBSCRIBE" => self.unsubscribe(&frame, ctx).map(|_| false),
+            "ACK" | "NACK" => self.ack(&frame).map(|_| false),
+            "BEGIN" | "COMMIT" | "ABORT" => Err("Transactions are not supported".to_owned()),
+            "DISCONNECT" => Ok(true),
+            "CONNECT" | "STOMP" => Err("Already connected".to_owned()),
+            _ => Err(format!("Unknown command {}", command)),
+        }
+    }
+
+    fn connect(&mut self, frame: &StompFrame, ctx: &mut SockJSContext<Self>)
+               -> Result<(), String>
+    {
+        if let Some(versions) = frame.get("accept-version") {
+            if !versions.split(',').any(|v| v.trim() == "1.2") {
+                return Err("Supported protocol versions are 1.2".to_owned())
+            }
+        }
+        self.app.connect(frame)?;
+        self.connected = true;
+        let connected = StompFrame::new("CONNECTED")
+            .header("version", "1.2")
+            .header("heart-beat", "0,0")
+            .header("session", ctx.sid().as_str());
+        ctx.send(connected.to_string());
+        Ok(())
+    }
+
+    fn send(&mut self, frame: StompFrame, ctx: &mut SockJSContext<Self>) -> Result<(), String> {
+        let app = {
+            let destination = required(&frame, "destination")?;
+            destination.starts_with(self.app.prefix())
+        };
+        if app {
+            let broker = StompBroker {
+                sid: Arc::clone(ctx.sid()),
+                sm: ctx.manager().clone().recipient(),
+            };
+            return self.app.send(frame, &broker)
+        }
+
+        let mut msg = StompPublish::new(required(&frame, "destination")?, frame.body.as_str());
+        for &(ref name, ref value) in &frame.headers {
+            if !RESERVED.contains(&name.as_str()) {
+                msg = msg.header(name.as_str(), value.as_str());
+            }
+        }
+        ctx.manager().do_send(msg);
+        Ok(())
+    }
+
+    fn subscribe(&mut self, frame: &StompFrame, ctx: &mut SockJSContext<Self>)
+                 -> Result<(), String>
+    {
+        let id = required(frame, "id")?;
+        let destination = required(frame, "destination")?;
+        let ack = match frame.get("ack") {
+            None | Some("auto") => AckMode::Auto,
+            Some("client") => AckMode::Client,
+            Some("client-individual") => AckMode::ClientIndividual,
+            Some(ack) => return Err(format!("Unknown ack mode {}", ack)),
+        };
+        if self.subscriptions.contains_key(id) {
+            return Err(format!("Subscription {} already exists", id))
+        }
+        if !self.subscriptions.values().any(|sub| sub.destination == destination) {
+            ctx.manager().do_send(StompSubscribe {
+                sid: Arc::clone(ctx.sid()),
+                destination: destination.to_owned(),
+            });
+        }
+        self.subscriptions.insert(
+            id.to_owned(), Subscription {destination: destination.to_owned(), ack});
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, frame: &StompFrame, ctx: &mut SockJSContext<Self>)
+                   -> Result<(), String>
+    {
+        let id = required(frame, "id")?;
+        let sub = match self.subscriptions.remove(id) {
+            Some(sub) => sub,
+            None => return Err(format!("Unknown subscription {}", id)),
+        };
+        self.unacked.retain(|m| m.1 != id);
+        if !self.subscriptions.values().any(|s| s.destination == sub.destination) {
+            ctx.manager().do_send(StompUnsubscribe {
+                sid: Arc::clone(ctx.sid()),
+                destination: sub.destination,
+            });
+        }
+        Ok(())
+    }
+
+    fn ack(&mut self, frame: &StompFrame) -> Result<(), String> {
+        let id = required(frame, "id")?;
+        let pos = match self.unacked.iter().position(|m| m.0 == id) {
+            Some(pos) => pos,
+            None => return Err(format!("Unknown message {}", id)),
+        };
+        let sid = self.unacked[pos].1.clone();
+        let ack = self.subscriptions.get(&sid).map(|sub| sub.ack);
+        if ack == Some(AckMode::Client) {
+            // acknowledges all previous messages of the subscription
+            let mut idx = 0;
+            self.unacked.retain(|m| {
+                idx += 1;
+                idx > pos + 1 || m.1 != sid
+            });
+        } else {
+            self.unacked.remove(pos);
+        }
+        Ok(())
+    }
+
+    /// Send `ERROR` frame and close session
+    fn error(&mut self, msg: &str, receipt: Option<String>, ctx: &mut SockJSContext<Self>) {
+        let mut frame = StompFrame::new("ERROR").header("message", msg);
+        if let Some(receipt) = receipt {
+            frame = frame.header("receipt-id", receipt);
+        }
+        ctx.send(frame.to_string());
+        ctx.close();
+    }
+}
+
+fn required<'a>(frame: &'a StompFrame, name: &str) -> Result<&'a str, String> {
+    match frame.get(name) {
+        Some(value) => Ok(value),
+        None => Err(format!("Missing {} header", name)),
+    }
+}
+
+impl<A: StompApp> Handler<Message> for StompSession<A> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        let frame = match StompFrame::parse(&msg.0) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return,
+            Err(err) => return self.error(&err.to_string(), None, ctx),
+        };
+        let receipt = frame.get("receipt").map(|r| r.to_owned());
+        match self.frame(frame, ctx) {
+            Ok(close) => {
+                if let Some(receipt) = receipt {
+                    ctx.send(StompFrame::new("RECEIPT").header("receipt-id", receipt).to_string());
+                }
+                if close {
+                    self.connected = false;
+                    ctx.close();
+                }
+            }
+            Err(err) => self.error(&err, receipt, ctx),
+        }
+    }
+}
+
+impl<A: StompApp> Handler<StompPublish> for StompSession<A> {
+    type Result = ();
+
+    fn handle(&mut self, msg: StompPublish, ctx: &mut SockJSContext<Self>) {
+        if !self.connected {
+            return
+        }
+        let mut overflow = false;
+        for (id, sub) in &self.subscriptions {
+            if sub.destination != *msg.destination {
+                continue
+            }
+            self.seq += 1;
+            let mid = self.seq.to_string();
+            let mut frame = StompFrame::new("MESSAGE")
+                .header("subscription", id.as_str())
+                .header("message-id", mid.as_str())
+                .header("destination", msg.destination.as_str());
+            if sub.ack != AckMode::Auto {
+                if self.unacked.len() >= MAX_UNACKED {
+                    overflow = true;
+                    break
+                }
+                frame = frame.header("ack", mid.as_str());
+                self.unacked.push_back((mid, id.clone()));
+            }
+            frame.headers.extend(
+                msg.headers.iter().filter(|h| h.0 != "content-length").cloned());
+            frame = frame.header("content-length", msg.body.len().to_string());
+            frame.body = msg.body.as_str().to_owned();
+            ctx.send(frame.to_string());
+        }
+        if overflow {
+            self.connected = false;
+            self.error("Too many unacknowledged messages", None, ctx);
+        }
+    }
+}
+
+/// Publish message to sessions subscribed to the destination
+impl<A: StompApp> Handler<StompPublish> for SockJSManager<StompSession<A>> {
+    type Result = ();
+
+    fn handle(&mut self, msg: StompPublish, _: &mut Context<Self>) {
+        let destination = Arc::clone(&msg.destination);
+        self.deliver(&destination, msg);
+    }
+}
+
+impl<A: StompApp> Handler<StompSubscribe> for SockJSManager<StompSession<A>> {
+    type Result = ();
+
+    fn handle(&mut self, msg: StompSubscribe, _: &mut Context<Self>) {
+        self.subscribe_destination(msg.destination, msg.sid);
+    }
+}
+
+impl<A: StompApp> Handler<StompUnsubscribe> for SockJSManager<StompSession<A>> {
+    type Result = ();
+
+    fn handle(&mut self, msg: StompUnsubscribe, _: &mut Context<Self>) {
+        self.unsubscribe_destination(&msg.destination, &msg.sid);
+    }
+}
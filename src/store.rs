@@ -0,0 +1,116 @@
+use std::io::{self, Read, Write};
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use serde_json;
+
+/// Persisted session
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StoredSession {
+    /// Session state, see `Session::save()`
+    pub state: Option<Vec<u8>>,
+    /// Pending outbound messages
+    pub messages: Vec<String>,
+}
+
+/// Session persistence store
+///
+/// Session manager saves session when transport releases it and session
+/// changed since last save, and removes it once session get closed or expired.
+/// Store methods get called from session manager actor, blocking stores
+/// delay all sessions of the manager.
+/// Unknown sessions get loaded from the store on first request.
+pub trait SessionStore: 'static {
+    /// Save session
+    fn save(&mut self, sid: &str, session: &StoredSession) -> io::Result<()>;
+
+    /// Load session
+    fn load(&mut self, sid: &str) -> io::Result<Option<StoredSession>>;
+
+    /// Remove session
+    fn remove(&mut self, sid: &str) -> io::Result<()>;
+}
+
+/// In-memory session store
+///
+/// Clones of the store share same sessions, so sessions survive
+/// restart of session manager within a process.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    sessions: Arc<Mutex<HashMap<String, StoredSession>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn save(&mut self, sid: &str, session: &StoredSession) -> io::Result<()> {
+        self.sessions.lock().unwrap().insert(sid.to_owned(), session.clone());
+        Ok(())
+    }
+
+    fn load(&mut self, sid: &str) -> io::Result<Option<StoredSession>> {
+        Ok(self.sessions.lock().unwrap().get(sid).cloned())
+    }
+
+    fn remove(&mut self, sid: &str) -> io::Result<()> {
+        self.sessions.lock().unwrap().remove(sid);
+        Ok(())
+    }
+}
+
+/// File backed session store, keeps one json file per session
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// Create store in `dir` directory, directory get created if it does not exist
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<FileStore> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileStore { dir })
+    }
+
+    fn path(&self, sid: &str) -> io::Result<PathBuf> {
+        if sid.is_empty() || sid.contains(|c| c == '/' || c == '\\' || c == '.') {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid session id"))
+        } else {
+            Ok(self.dir.join(format!("{}.json", sid)))
+        }
+    }
+}
+
+impl SessionStore for FileStore {
+    fn save(&mut self, sid: &str, session: &StoredSession) -> io::Result<()> {
+        let path = self.path(sid)?;
+        let tmp = path.with_extension("tmp");
+        File::create(&tmp)?.write_all(&serde_json::to_vec(session)?)?;
+        fs::rename(tmp, path)
+    }
+
+    fn load(&mut self, sid: &str) -> io::Result<Option<StoredSession>> {
+        match File::open(self.path(sid)?) {
+            Ok(mut file) => {
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)?;
+                Ok(Some(serde_json::from_slice(&data)?))
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn remove(&mut self, sid: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(sid)?) {
+            Ok(()) => Ok(()),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! In-process test harness for sessions
+//!
+//! `TestManager` runs `SockJSManager` in its own actix `System` and
+//! connects fake transports to sessions, so `Session` implementations
+//! can be tested without http server and sockets. Fake transport is
+//! a regular `Transport` that records frames instead of writing them.
+//!
+//! ```rust
+//! # extern crate actix;
+//! # extern crate sockjs;
+//! use actix::*;
+//! use sockjs::{Frame, Message, Session, SockJSContext, SockJSManager};
+//! use sockjs::test::TestManager;
+//!
+//! #[derive(Default)]
+//! struct Echo;
+//!
+//! impl Actor for Echo {
+//!     type Context = SockJSContext<Self>;
+//! }
+//!
+//! impl Session for Echo {}
+//!
+//! impl Handler<Message> for Echo {
+//!     type Result = ();
+//!
+//!     fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+//!         ctx.send(msg);
+//!     }
+//! }
+//!
+//! fn main() {
+//!     let mut srv = TestManager::new(SockJSManager::<Echo>::default());
+//!
+//!     let tr = srv.connect("session");
+//!     srv.send(&tr, "hello");
+//!     assert_eq!(tr.frames(), vec![Frame::Open, Frame::Message("hello".to_owned())]);
+//! }
+//! ```
+use std::rc::Rc;
+use std::sync::Arc;
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use actix::*;
+use actix::Message as ActixMessage;
+use actix_web::{Body, HttpContext};
+use actix_web::test::TestRequest;
+use futures::{task, Async, Future, Poll};
+use serde_json;
+
+use context::ChannelItem;
+use manager::{Broadcast, Expire, Record, SessionMessage, SockJSManager};
+use protocol::{CloseCode, Frame};
+use session::{Message, Session};
+use transports::{SendResult, Transport, TransportContext, TransportMode, TransportState};
+
+/// Session manager running in test actix system
+pub struct TestManager<S: Session> {
+    sys: SystemRunner,
+    addr: Addr<Syn, SockJSManager<S>>,
+}
+
+impl<S: Session> TestManager<S> {
+    /// Start test system and session manager
+    pub fn new(manager: SockJSManager<S>) -> TestManager<S> {
+        let sys = System::new("sockjs-test");
+        let addr: Addr<Syn, _> = manager.start();
+        TestManager {sys, addr}
+    }
+
+    /// Address of session manager
+    pub fn manager(&self) -> &Addr<Syn, SockJSManager<S>> {
+        &self.addr
+    }
+
+    /// Run future to completion on test system
+    pub fn block_on<F, I, E>(&mut self, fut: F) -> Result<I, E>
+        where F: Future<Item=I, Error=E>
+    {
+        self.sys.run_until_complete(fut)
+    }
+
+    /// Run event loop until pending messages get delivered
+    pub fn settle(&mut self) {
+        let _ = self.sys.run_until_complete(Turns(SETTLE_TURNS));
+    }
+
+    /// Connect transport to the session, session get opened if it does not exist
+    pub fn connect(&mut self, sid: &str) -> TestTransport {
+        self.start(sid, None)
+    }
+
+    /// Connect transport to the new session presenting resume token
+    pub fn resume(&mut self, sid: &str, token: &str) -> TestTransport {
+        self.start(sid, Some(token.to_owned()))
+    }
+
+    fn start(&mut self, sid: &str, resume: Option<String>) -> TestTransport {
+        let req = match resume {
+            Some(token) => TestRequest::with_state(self.addr.clone())
+                .uri(&format!("/?resume={}", token)),
+            None => TestRequest::with_state(self.addr.clone()),
+        }.finish();
+
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let released = Rc::new(Cell::new(false));
+        let mut ctx = HttpContext::from_request(req);
+        let addr: Addr<Syn, _> = ctx.address();
+        let mut conn = Connection {
+            s: PhantomData,
+            state: TransportState::new(),
+            frames: Rc::clone(&frames),
+            released: Rc::clone(&released),
+        };
+        conn.init_transport(sid.to_owned(), &mut ctx);
+        Arbiter::handle().spawn(Response(ctx.actor(conn).into()));
+        self.settle();
+
+        TestTransport {frames, released,
+                       sid: sid.to_owned(),
+                       addr: addr.recipient()}
+    }
+
+    /// Send peer message to the session of transport
+    pub fn send<M: Into<Message>>(&mut self, tr: &TestTransport, msg: M) {
+        self.addr.do_send(SessionMessage{sid: Arc::new(tr.sid.clone()), msg: msg.into()});
+        self.settle();
+    }
+
+    /// Broadcast message to all sessions
+    pub fn broadcast<M: Into<Message>>(&mut self, msg: M) {
+        self.addr.do_send(Broadcast::new(Frame::from(msg.into())));
+        self.settle();
+    }
+
+    /// Release session, session stays open until it expires
+    pub fn disconnect(&mut self, tr: TestTransport) {
+        let _ = tr.addr.do_send(Disconnect{interrupted: false});
+        self.settle();
+    }
+
+    /// Drop transport connection, session get closed as interrupted
+    pub fn interrupt(&mut self, tr: TestTransport) {
+        let _ = tr.addr.do_send(Disconnect{interrupted: true});
+        self.settle();
+    }
+
+    /// Expire sessions and rpc calls as if `elapsed` time passed since last release
+    pub fn advance(&mut self, elapsed: Duration) {
+        self.addr.do_send(Expire{now: Instant::now() + elapsed});
+        self.settle();
+    }
+}
+
+/// Fake transport connected to a session
+pub struct TestTransport {
+    sid: String,
+    frames: Rc<RefCell<Vec<Frame>>>,
+    released: Rc<Cell<bool>>,
+    addr: Recipient<Syn, Disconnect>,
+}
+
+impl TestTransport {
+    /// Session id
+    pub fn sid(&self) -> &str {
+        &self.sid
+    }
+
+    /// Take frames received since last call
+    pub fn frames(&self) -> Vec<Frame> {
+        self.frames.borrow_mut().drain(..).collect()
+    }
+
+    /// Take messages received since last call, other frames get dropped
+    pub fn messages(&self) -> Vec<String> {
+        let mut msgs = Vec::new();
+        for frame in self.frames() {
+            match frame {
+                Frame::Message(msg) => msgs.push(msg),
+                Frame::MessageVec(vec) => {
+                    if let Ok(vec) = serde_json::from_str::<Vec<String>>(&vec) {
+                        msgs.extend(vec);
+                    }
+                }
+                _ => (),
+            }
+        }
+        msgs
+    }
+
+    /// Transport released the session, for example after close frame
+    pub fn released(&self) -> bool {
+        self.released.get()
+    }
+}
+
+#[doc(hidden)]
+pub struct Disconnect {
+    interrupted: bool,
+}
+
+impl ActixMessage for Disconnect {
+    type Result = ();
+}
+
+/// Completes after number of event loop turns
+struct Turns(usize);
+
+impl Future for Turns {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        if self.0 == 0 {
+            Ok(Async::Ready(()))
+        } else {
+            self.0 -= 1;
+            task::current().notify();
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Polls response body of transport, as http server would
+struct Response(Body);
+
+impl Future for Response {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        if let Body::Actor(ref mut ctx) = self.0 {
+            loop {
+                match ctx.poll() {
+                    Ok(Async::Ready(Some(_))) => continue,
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(None)) | Err(_) => break,
+                }
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Transport actor, records frames instead of writing them
+struct Connection<S: Session> {
+    s: PhantomData<S>,
+    state: TransportState,
+    frames: Rc<RefCell<Vec<Frame>>>,
+    released: Rc<Cell<bool>>,
+}
+
+impl<S: Session> Actor for Connection<S> {
+    type Context = TransportContext<Self, SockJSManager<S>>;
+
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.release(ctx);
+        self.released.set(true);
+        Running::Stop
+    }
+}
+
+impl<S: Session> Transport<S, SockJSManager<S>> for Connection<S> {
+    const NAME: &'static str = "test";
+    const MODE: TransportMode = TransportMode::Streaming;
+
+    fn send(&mut self, ctx: &mut Self::Context, msg: &Frame, record: &mut Record)
+            -> SendResult
+    {
+        self.frames.borrow_mut().push(msg.clone());
+        if let Frame::Close(_) = *msg {
+            // response ends with close frame
+            record.close();
+            ctx.stop();
+            SendResult::Stop
+        } else {
+            SendResult::Continue
+        }
+    }
+
+    fn send_close(&mut self, ctx: &mut Self::Context, code: CloseCode) {
+        self.frames.borrow_mut().push(Frame::Close(code));
+        ctx.stop();
+    }
+
+    fn send_heartbeat(&mut self, _: &mut Self::Context) {
+        self.frames.borrow_mut().push(Frame::Heartbeat);
+    }
+
+    fn transport_state(&mut self) -> &mut TransportState {
+        &mut self.state
+    }
+}
+
+impl<S: Session> Handler<ChannelItem> for Connection<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChannelItem, ctx: &mut Self::Context) {
+        self.handle_message(msg, ctx)
+    }
+}
+
+impl<S: Session> Handler<Broadcast> for Connection<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
+        self.handle_broadcast(msg, ctx)
+    }
+}
+
+impl<S: Session> Handler<Disconnect> for Connection<S> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Self::Context) {
+        if msg.interrupted {
+            if let Some(ref mut rec) = self.state.rec {
+                rec.interrupted();
+            }
+        }
+        self.release(ctx);
+    }
+}
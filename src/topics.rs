@@ -0,0 +1,162 @@
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+
+use actix::Message as ActixMessage;
+
+use manager::Broadcast;
+use protocol::Frame;
+use session::Message;
+
+/// Subscribe session to topics matching `pattern`.
+///
+/// Topics are dot separated, for example `orders.eu.created`. In pattern
+/// `*` matches exactly one segment and `#` matches zero or more segments,
+/// `orders.*.created` or `orders.#`.
+#[derive(Debug)]
+pub struct SubscribeTopic {
+    /// Session id
+    pub sid: Arc<String>,
+    /// Topic pattern
+    pub pattern: String,
+}
+
+impl ActixMessage for SubscribeTopic {
+    type Result = ();
+}
+
+/// Remove subscription of session
+#[derive(Debug)]
+pub struct UnsubscribeTopic {
+    /// Session id
+    pub sid: Arc<String>,
+    /// Topic pattern
+    pub pattern: String,
+}
+
+impl ActixMessage for UnsubscribeTopic {
+    type Result = ();
+}
+
+/// Publish message to sessions subscribed to the topic.
+///
+/// Message is delivered once to every session with matching subscription.
+#[derive(Debug)]
+pub struct Publish {
+    /// Topic
+    pub topic: String,
+    /// Message, shared by all recipients
+    pub msg: Broadcast,
+}
+
+impl Publish {
+    /// Create message for `topic`
+    pub fn new<T: Into<String>, M: Into<Message>>(topic: T, msg: M) -> Publish {
+        Publish {
+            topic: topic.into(),
+            msg: Broadcast::new(Frame::Message(msg.into().0)),
+        }
+    }
+}
+
+impl ActixMessage for Publish {
+    type Result = ();
+}
+
+/// Topic subscriptions of sessions
+#[derive(Default)]
+pub(crate) struct Topics {
+    patterns: HashMap<String, HashSet<Arc<String>>>,
+}
+
+impl Topics {
+    pub fn subscribe(&mut self, pattern: String, sid: Arc<String>) {
+        self.patterns.entry(pattern).or_insert_with(HashSet::new).insert(sid);
+    }
+
+    pub fn unsubscribe(&mut self, pattern: &str, sid: &Arc<String>) {
+        let empty = if let Some(sids) = self.patterns.get_mut(pattern) {
+            sids.remove(sid);
+            sids.is_empty()
+        } else {
+            false
+        };
+        if empty {
+            self.patterns.remove(pattern);
+        }
+    }
+
+    /// Remove all subscriptions of session
+    pub fn remove(&mut self, sid: &Arc<String>) {
+        for sids in self.patterns.values_mut() {
+            sids.remove(sid);
+        }
+        self.patterns.retain(|_, sids| !sids.is_empty());
+    }
+
+    /// Patterns session is subscribed to
+    pub fn patterns(&self, sid: &Arc<String>) -> Vec<&str> {
+        self.patterns.iter()
+            .filter(|&(_, sids)| sids.contains(sid))
+            .map(|(pattern, _)| pattern.as_str())
+            .collect()
+    }
+
+    /// Sessions subscribed to exactly `pattern`
+    pub fn get(&self, pattern: &str) -> Option<&HashSet<Arc<String>>> {
+        self.patterns.get(pattern)
+    }
+
+    /// Sessions subscribed to `topic`
+    pub fn subscribers(&self, topic: &str) -> HashSet<Arc<String>> {
+        let topic: Vec<_> = topic.split('.').collect();
+        let mut res = HashSet::new();
+        for (pattern, sids) in &self.patterns {
+            let pattern: Vec<_> = pattern.split('.').collect();
+            if matches(&pattern, &topic) {
+                res.extend(sids.iter().cloned());
+            }
+        }
+        res
+    }
+}
+
+/// Check if `topic` matches subscription `pattern`
+pub(crate) fn pattern_matches(pattern: &str, topic: &str) -> bool {
+    let pattern: Vec<_> = pattern.split('.').collect();
+    let topic: Vec<_> = topic.split('.').collect();
+    matches(&pattern, &topic)
+}
+
+/// Match segments in one pass over topic, `O(pattern * topic)` for any
+/// number of `#` wildcards. `states[i]` marks that first `i` pattern segments
+/// match consumed topic segments.
+fn matches(pattern: &[&str], topic: &[&str]) -> bool {
+    let mut states = vec![false; pattern.len() + 1];
+    states[0] = true;
+    skip_hashes(pattern, &mut states);
+    for t in topic {
+        let mut next = vec![false; pattern.len() + 1];
+        for (idx, p) in pattern.iter().enumerate() {
+            if !states[idx] {
+                continue
+            }
+            if *p == "#" {
+                next[idx] = true;
+            } else if *p == "*" || p == t {
+                next[idx + 1] = true;
+            }
+        }
+        skip_hashes(pattern, &mut next);
+        states = next;
+    }
+    states[pattern.len()]
+}
+
+/// `#` matches zero segments, state before it reaches state after it
+fn skip_hashes(pattern: &[&str], states: &mut [bool]) {
+    for (idx, p) in pattern.iter().enumerate() {
+        if states[idx] && *p == "#" {
+            states[idx + 1] = true;
+        }
+    }
+}
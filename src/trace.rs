@@ -0,0 +1,116 @@
+//! Session lifecycle spans, enabled with `tracing` feature
+//!
+//! Every session gets `sockjs_session` span with `sid` and `transport`
+//! fields. Each transport request that acquires the session opens child
+//! `sockjs_request` span with `transport` and `remote` fields, which ends
+//! when request releases the session, so session can be followed across
+//! many polling requests. Remote address belongs to request, consecutive
+//! requests of one session may come from different addresses.
+//!
+//! `tracing` crate requires newer compiler than minimum supported
+//! Rust version 1.21, so feature is not available with Rust 1.21.
+pub(crate) use self::imp::SessionSpan;
+
+#[cfg(feature = "tracing")]
+mod imp {
+    use tracing::{field, Level, Span};
+
+    use protocol::Frame;
+    use session::CloseReason;
+
+    pub(crate) struct SessionSpan {
+        session: Span,
+        request: Span,
+    }
+
+    impl SessionSpan {
+        pub fn new(sid: &str) -> SessionSpan {
+            SessionSpan {
+                session: ::tracing::span!(
+                    Level::INFO, "sockjs_session",
+                    sid = sid, transport = field::Empty),
+                request: Span::none(),
+            }
+        }
+
+        pub fn opened(&self) {
+            ::tracing::info!(parent: &self.session, "opened");
+        }
+
+        /// Transport request acquired session
+        pub fn acquired(&mut self, transport: &str, remote: Option<&str>) {
+            self.session.record("transport", &transport);
+            self.request = ::tracing::span!(
+                parent: &self.session, Level::DEBUG, "sockjs_request",
+                transport = transport, remote = field::Empty);
+            if let Some(remote) = remote {
+                self.request.record("remote", &remote);
+            }
+            ::tracing::debug!(parent: &self.request, "acquired");
+        }
+
+        /// Frame sent by transport
+        pub fn frame(&self, frame: &Frame) {
+            ::tracing::trace!(parent: &self.request, frame = kind(frame), "send");
+        }
+
+        /// Transport request released session, request span ends
+        pub fn released(&mut self) {
+            ::tracing::debug!(parent: &self.request, "released");
+            self.request = Span::none();
+        }
+
+        pub fn closed(&self, reason: CloseReason) {
+            ::tracing::info!(parent: &self.session, reason = ?reason, "closed");
+        }
+
+        pub fn expired(&self) {
+            ::tracing::info!(parent: &self.session, "expired");
+        }
+    }
+
+    /// Short name of the frame, payload is not recorded
+    fn kind(frame: &Frame) -> &'static str {
+        match *frame {
+            Frame::Open => "open",
+            Frame::Close(_) => "close",
+            Frame::Heartbeat => "heartbeat",
+            Frame::Message(_) => "message",
+            Frame::MessageVec(_) => "messages",
+            Frame::MessageBlob(_) => "blob",
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod imp {
+    use protocol::Frame;
+    use session::CloseReason;
+
+    pub(crate) struct SessionSpan;
+
+    impl SessionSpan {
+        #[inline]
+        pub fn new(_sid: &str) -> SessionSpan {
+            SessionSpan
+        }
+
+        #[inline]
+        pub fn opened(&self) {}
+
+        #[inline]
+        pub fn acquired(&mut self, _transport: &str, _remote: Option<&str>) {}
+
+        #[inline]
+        pub fn frame(&self, _frame: &Frame) {}
+
+        #[inline]
+        pub fn released(&mut self) {}
+
+        #[inline]
+        pub fn closed(&self, _reason: CloseReason) {}
+
+        #[inline]
+        pub fn expired(&self) {}
+    }
+}
@@ -0,0 +1,311 @@
+//! permessage-deflate websocket extension, RFC 7692
+use std::cmp;
+
+use actix_web::HttpRequest;
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use http::header;
+
+/// Tail of sync flushed deflate block, stripped from compressed messages
+const TAIL: [u8; 4] = [0, 0, 0xff, 0xff];
+
+/// permessage-deflate settings of websocket transports
+#[derive(Clone, Debug)]
+pub struct Deflate {
+    server_max_window_bits: u8,
+    client_max_window_bits: u8,
+    threshold: usize,
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+}
+
+impl Default for Deflate {
+    fn default() -> Deflate {
+        Deflate {
+            server_max_window_bits: 15,
+            client_max_window_bits: 15,
+            threshold: 128,
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+        }
+    }
+}
+
+impl Deflate {
+    pub fn new() -> Deflate {
+        Deflate::default()
+    }
+
+    /// Set LZ77 window size of the server, `9..15` bits. By default 15 bits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if window size is out of range.
+    pub fn server_max_window_bits(mut self, bits: u8) -> Self {
+        assert!(bits >= 9 && bits <= 15, "Window bits must be in 9..15 range");
+        self.server_max_window_bits = bits;
+        self
+    }
+
+    /// Set LZ77 window size requested from the client, `8..15` bits.
+    /// By default 15 bits.
+    ///
+    /// Clients that do not support window size negotiation are served
+    /// without compression if window size is less than 15 bits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if window size is out of range.
+    pub fn client_max_window_bits(mut self, bits: u8) -> Self {
+        assert!(bits >= 8 && bits <= 15, "Window bits must be in 8..15 range");
+        self.client_max_window_bits = bits;
+        self
+    }
+
+    /// Messages smaller than threshold are sent uncompressed. By default 128 bytes.
+    pub fn threshold(mut self, size: usize) -> Self {
+        self.threshold = size;
+        self
+    }
+
+    /// Reset server's compression context after each message,
+    /// trades compression ratio for memory.
+    pub fn server_no_context_takeover(mut self, val: bool) -> Self {
+        self.server_no_context_takeover = val;
+        self
+    }
+
+    /// Ask client to reset compression context after each message.
+    pub fn client_no_context_takeover(mut self, val: bool) -> Self {
+        self.client_no_context_takeover = val;
+        self
+    }
+
+    /// Select first acceptable extension offer of the client
+    pub(crate) fn negotiate<S>(&self, req: &HttpRequest<S>) -> Option<DeflateParams> {
+        for hdr in req.headers().get_all(header::SEC_WEBSOCKET_EXTENSIONS) {
+            if let Ok(hdr) = hdr.to_str() {
+                for offer in hdr.split(',') {
+                    if let Some(params) = self.accept(offer) {
+                        return Some(params);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn accept(&self, offer: &str) -> Option<DeflateParams> {
+        let mut items = offer.split(';').map(|s| s.trim());
+        if items.next() != Some("permessage-deflate") {
+            return None;
+        }
+
+        let mut seen = Vec::new();
+        let mut server_bits = None;
+        let mut client_bits = None;
+        let mut server_no_context_takeover = self.server_no_context_takeover;
+        let mut client_no_context_takeover = self.client_no_context_takeover;
+        for item in items {
+            let mut kv = item.splitn(2, '=');
+            let name = kv.next().unwrap_or("").trim();
+            let value = kv.next().map(|v| v.trim().trim_matches('"'));
+            if seen.contains(&name) {
+                return None;
+            }
+            seen.push(name);
+
+            match (name, value) {
+                ("server_no_context_takeover", None) => server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => client_no_context_takeover = true,
+                // zlib does not support 8 bits window for raw deflate streams
+                ("server_max_window_bits", Some(value)) => match parse_bits(value) {
+                    Some(bits) if bits > 8 => server_bits = Some(bits),
+                    _ => return None,
+                },
+                ("client_max_window_bits", None) => client_bits = Some(15),
+                ("client_max_window_bits", Some(value)) => match parse_bits(value) {
+                    Some(bits) => client_bits = Some(bits),
+                    None => return None,
+                },
+                _ => return None,
+            }
+        }
+
+        // client window can be limited only if client supports it
+        let client_bits = match client_bits {
+            Some(bits) => Some(cmp::min(bits, self.client_max_window_bits)),
+            None if self.client_max_window_bits < 15 => return None,
+            None => None,
+        };
+
+        Some(DeflateParams {
+            client_bits,
+            server_no_context_takeover,
+            client_no_context_takeover,
+            server_bits_requested: server_bits.is_some(),
+            server_bits: cmp::min(server_bits.unwrap_or(15), self.server_max_window_bits),
+            threshold: self.threshold,
+        })
+    }
+}
+
+fn parse_bits(value: &str) -> Option<u8> {
+    match value.parse::<u8>() {
+        Ok(bits) if bits >= 8 && bits <= 15 => Some(bits),
+        _ => None,
+    }
+}
+
+/// Negotiated permessage-deflate parameters
+#[derive(Debug)]
+pub(crate) struct DeflateParams {
+    server_bits: u8,
+    server_bits_requested: bool,
+    client_bits: Option<u8>,
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+    threshold: usize,
+}
+
+impl DeflateParams {
+    /// Value of `Sec-WebSocket-Extensions` response header
+    pub fn header(&self) -> String {
+        let mut hdr = "permessage-deflate".to_owned();
+        if self.server_no_context_takeover {
+            hdr.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            hdr.push_str("; client_no_context_takeover");
+        }
+        if self.server_bits_requested || self.server_bits < 15 {
+            hdr.push_str(&format!("; server_max_window_bits={}", self.server_bits));
+        }
+        if let Some(bits) = self.client_bits {
+            if bits < 15 {
+                hdr.push_str(&format!("; client_max_window_bits={}", bits));
+            }
+        }
+        hdr
+    }
+
+    pub fn deflater(&self) -> Deflater {
+        Deflater {
+            compress: Compress::new_with_window_bits(
+                Compression::default(), false, self.server_bits),
+            threshold: self.threshold,
+            no_context_takeover: self.server_no_context_takeover,
+        }
+    }
+
+    pub fn inflater(&self) -> Inflater {
+        Inflater {
+            decompress: Decompress::new(false),
+            no_context_takeover: self.client_no_context_takeover,
+        }
+    }
+}
+
+/// Compressor of outbound messages
+pub(crate) struct Deflater {
+    compress: Compress,
+    threshold: usize,
+    no_context_takeover: bool,
+}
+
+impl Deflater {
+    /// Compress message, returns `None` if message has to be sent as is
+    pub fn deflate(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        if data.len() < self.threshold {
+            return None;
+        }
+
+        let mut out = Vec::with_capacity(data.len() / 2 + 64);
+        let start = self.compress.total_in();
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            if out.len() == out.capacity() {
+                out.reserve(1024);
+            }
+            if self.compress
+                .compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+                .is_err()
+            {
+                // peer's context is not affected, message is sent as is
+                self.compress.reset();
+                return None;
+            }
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == data.len() && out.len() < out.capacity() {
+                break;
+            }
+        }
+
+        if out.ends_with(&TAIL) {
+            let len = out.len() - TAIL.len();
+            out.truncate(len);
+        }
+        if self.no_context_takeover {
+            self.compress.reset();
+        }
+        Some(out)
+    }
+}
+
+/// Decompressor of inbound messages
+pub(crate) struct Inflater {
+    decompress: Decompress,
+    no_context_takeover: bool,
+}
+
+/// Decompression error of inbound message
+#[derive(Debug, PartialEq)]
+pub(crate) enum InflateError {
+    /// Decompressed message is bigger than max size
+    Overflow,
+    /// Compressed data is broken
+    Corrupt,
+}
+
+impl Inflater {
+    /// Decompress message
+    pub fn inflate(&mut self, data: &[u8], max_size: usize) -> Result<Vec<u8>, InflateError> {
+        let mut input = Vec::with_capacity(data.len() + TAIL.len());
+        input.extend_from_slice(data);
+        input.extend_from_slice(&TAIL);
+
+        let mut out = Vec::with_capacity(data.len() * 2 + 64);
+        let start_in = self.decompress.total_in();
+        loop {
+            let consumed = (self.decompress.total_in() - start_in) as usize;
+            let produced = out.len();
+            if out.len() == out.capacity() {
+                if out.len() > max_size {
+                    return Err(InflateError::Overflow);
+                }
+                out.reserve(4096);
+            }
+            if self.decompress
+                .decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+                .is_err()
+            {
+                return Err(InflateError::Corrupt);
+            }
+            let total = (self.decompress.total_in() - start_in) as usize;
+            if total == input.len() && out.len() < out.capacity() {
+                break;
+            }
+            if total == consumed && out.len() == produced && out.len() < out.capacity() {
+                // no progress, truncated stream
+                return Err(InflateError::Corrupt);
+            }
+        }
+
+        if out.len() > max_size {
+            return Err(InflateError::Overflow);
+        }
+        if self.no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(out)
+    }
+}
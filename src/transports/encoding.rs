@@ -0,0 +1,155 @@
+//! Content encoding of streaming transports
+use std::io::Write;
+use std::mem;
+
+use actix::Actor;
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::ContentEncoding;
+use actix_web::{Binary, HttpContext, HttpRequest};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use http::header;
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    /// Select encoding accepted by the client, gzip is preferred
+    fn for_request<S>(req: &HttpRequest<S>) -> Option<Encoder> {
+        let accept = match req.headers().get(header::ACCEPT_ENCODING).map(|h| h.to_str()) {
+            Some(Ok(accept)) => accept,
+            _ => return None,
+        };
+
+        let mut deflate = false;
+        for item in accept.split(',') {
+            let mut params = item.split(';');
+            let name = params.next().unwrap_or("").trim();
+            let rejected = params.any(|param| {
+                let param = param.trim();
+                param.starts_with("q=") && param[2..].parse::<f32>().ok() == Some(0.0)
+            });
+            match name {
+                _ if rejected => (),
+                "gzip" | "x-gzip" => {
+                    return Some(Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::default())))
+                }
+                "deflate" => deflate = true,
+                _ => (),
+            }
+        }
+        if deflate {
+            Some(Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::default())))
+        } else {
+            None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Encoder::Gzip(_) => "gzip",
+            Encoder::Deflate(_) => "deflate",
+        }
+    }
+
+    // writing into vec never fails
+    fn write(&mut self, data: &[u8]) {
+        let _ = match *self {
+            Encoder::Gzip(ref mut enc) => enc.write_all(data),
+            Encoder::Deflate(ref mut enc) => enc.write_all(data),
+        };
+    }
+
+    /// Sync flush, returns compressed data
+    fn flush(&mut self) -> Vec<u8> {
+        match *self {
+            Encoder::Gzip(ref mut enc) => {
+                let _ = enc.flush();
+                mem::replace(enc.get_mut(), Vec::new())
+            }
+            Encoder::Deflate(ref mut enc) => {
+                let _ = enc.flush();
+                mem::replace(enc.get_mut(), Vec::new())
+            }
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Encoder::Gzip(enc) => enc.finish().unwrap_or_default(),
+            Encoder::Deflate(enc) => enc.finish().unwrap_or_default(),
+        }
+    }
+}
+
+/// Body writer of streaming transports.
+///
+/// Body get compressed if compression is enabled and client accepts it,
+/// compressor get flushed after each frame.
+pub(crate) struct BodyWriter {
+    encoder: Option<Encoder>,
+    written: usize,
+}
+
+impl BodyWriter {
+    /// Create body writer for the request, sets `Content-Encoding` and
+    /// `Vary` response headers
+    pub fn new<S>(req: &HttpRequest<S>, resp: &mut HttpResponseBuilder, compress: bool)
+                  -> BodyWriter
+    {
+        let encoder = if compress {
+            // response depends on accepted encodings even if it is not compressed
+            resp.header(header::VARY, "Accept-Encoding");
+            Encoder::for_request(req)
+        } else {
+            None
+        };
+        if let Some(ref encoder) = encoder {
+            // body is already compressed
+            resp.content_encoding(ContentEncoding::Identity)
+                .header(header::CONTENT_ENCODING, encoder.name());
+        }
+        BodyWriter { encoder, written: 0 }
+    }
+
+    pub fn write<A, S, B>(&mut self, ctx: &mut HttpContext<A, S>, data: B)
+        where A: Actor<Context=HttpContext<A, S>>, B: Into<Binary>
+    {
+        let data = data.into();
+        if let Some(ref mut encoder) = self.encoder {
+            encoder.write(data.as_ref());
+        } else {
+            self.written += data.len();
+            ctx.write(data);
+        }
+    }
+
+    /// Flush frame, returns number of bytes written since last flush
+    pub fn flush<A, S>(&mut self, ctx: &mut HttpContext<A, S>) -> usize
+        where A: Actor<Context=HttpContext<A, S>>
+    {
+        if let Some(ref mut encoder) = self.encoder {
+            let data = encoder.flush();
+            if !data.is_empty() {
+                self.written += data.len();
+                ctx.write(data);
+            }
+        }
+        mem::replace(&mut self.written, 0)
+    }
+
+    /// Finish compressed stream and response body
+    pub fn write_eof<A, S>(&mut self, ctx: &mut HttpContext<A, S>)
+        where A: Actor<Context=HttpContext<A, S>>
+    {
+        if let Some(encoder) = self.encoder.take() {
+            let data = encoder.finish();
+            if !data.is_empty() {
+                ctx.write(data);
+            }
+        }
+        ctx.write_eof();
+    }
+}
@@ -3,17 +3,22 @@ use std::marker::PhantomData;
 
 use actix::*;
 use actix_web::*;
+use bytes::Bytes;
 use http::header;
-use serde_json;
 
 use context::ChannelItem;
-use protocol::{Frame, CloseCode};
+use protocol::{encode, Frame, CloseCode};
 use utils::SockjsHeaders;
 use session::Session;
-use manager::{Broadcast, Record, SessionManager};
+use manager::{Broadcast, Format, Record, SessionManager};
 
-use super::{Transport, SendResult, Flags};
+use super::encoding::BodyWriter;
+use super::{Transport, TransportMode, SendResult, TransportState};
 
+/// Encode message frame
+fn message(msg: &str) -> Bytes {
+    Bytes::from(format!("data: {}\r\n\r\n", encode::message(msg)))
+}
 
 pub struct EventSource<S, SM>
     where S: Session, SM: SessionManager<S>,
@@ -21,9 +26,9 @@ pub struct EventSource<S, SM>
     s: PhantomData<S>,
     sm: PhantomData<SM>,
     size: usize,
-    rec: Option<Record>,
     maxsize: usize,
-    flags: Flags,
+    state: TransportState,
+    body: BodyWriter,
 }
 
 impl<S, SM> EventSource<S, SM>
@@ -36,7 +41,8 @@ impl<S, SM> EventSource<S, SM>
         });
     }
 
-    pub fn init(req: HttpRequest<Addr<Syn, SM>>, maxsize: usize) -> Result<HttpResponse>
+    pub fn init(req: HttpRequest<Addr<Syn, SM>>, maxsize: usize, compress: bool)
+                -> Result<HttpResponse>
     {
         let session = req.match_info().get("session").unwrap().to_owned();
         let mut resp = HttpResponse::Ok()
@@ -46,14 +52,11 @@ impl<S, SM> EventSource<S, SM>
             .sockjs_no_cache()
             .sockjs_session_cookie(&req)
             .take();
+        let mut body = BodyWriter::new(&req, &mut resp, compress);
 
-        let mut ctx = HttpContext::new(
-            req, EventSource{s: PhantomData,
-                             sm: PhantomData,
-                             size: 0, rec: None,
-                             flags: Flags::empty(),
-                             maxsize});
-        ctx.write("\r\n");
+        let mut ctx = HttpContext::from_request(req);
+        body.write(&mut ctx, "\r\n");
+        body.flush(&mut ctx);
 
         // init transport, but aftre prelude only
         ctx.drain().map(move |_, _, ctx| {
@@ -63,7 +66,23 @@ impl<S, SM> EventSource<S, SM>
             });
         }).wait(&mut ctx);
 
-        Ok(resp.body(ctx))
+        Ok(resp.body(ctx.actor(EventSource{s: PhantomData,
+                                           sm: PhantomData,
+                                           size: 0,
+                                           state: TransportState::new(),
+                                           maxsize, body})))
+    }
+
+    /// Flush written frame, stops transport if response is too big
+    fn flush(&mut self, ctx: &mut HttpContext<Self, Addr<Syn, SM>>) -> SendResult {
+        self.size += self.body.flush(ctx);
+
+        if self.size > self.maxsize {
+            self.body.write_eof(ctx);
+            SendResult::Stop
+        } else {
+            SendResult::Continue
+        }
     }
 }
 
@@ -83,67 +102,65 @@ impl<S, SM> Actor for EventSource<S, SM>
 impl<S, SM> Transport<S, SM> for EventSource<S, SM>
     where S: Session, SM: SessionManager<S>,
 {
+    const NAME: &'static str = "eventsource";
+    const MODE: TransportMode = TransportMode::Streaming;
+
     fn send(&mut self, ctx: &mut Self::Context, msg: &Frame, rec: &mut Record)
             -> SendResult
     {
-        self.size += match *msg {
+        match *msg {
             Frame::Heartbeat => {
-                ctx.write("data: h\r\n\r\n");
-                11
+                self.body.write(ctx, "data: h\r\n\r\n");
             },
             Frame::Message(ref s) => {
-                let blob = serde_json::to_string(&s).unwrap();
-                let size = blob.len();
-                ctx.write("data: a[");
-                ctx.write(blob);
-                ctx.write("]\r\n\r\n");
-                size + 13
+                self.body.write(ctx, message(s));
             }
             Frame::MessageVec(ref s) => {
-                let size = s.len();
-                ctx.write("data: a");
-                ctx.write(s);
-                ctx.write("\r\n\r\n");
-                size + 11
+                self.body.write(ctx, "data: a");
+                self.body.write(ctx, s);
+                self.body.write(ctx, "\r\n\r\n");
             }
             Frame::MessageBlob(_) => {
                 unimplemented!()
             }
             Frame::Open => {
-                ctx.write("data: o\r\n\r\n");
-                11
+                self.body.write(ctx, "data: o\r\n\r\n");
             },
             Frame::Close(code) => {
                 rec.close();
-                ctx.write(format!("data: c[{}, {:?}]\r\n\r\n", code.num(), code.reason()));
-                ctx.write_eof();
+                self.body.write(
+                    ctx, format!("data: c[{}, {:?}]\r\n\r\n", code.num(), code.reason()));
+                self.body.write_eof(ctx);
                 return SendResult::Stop
             }
         };
+        self.flush(ctx)
+    }
 
-        if self.size > self.maxsize {
-            ctx.write_eof();
-            SendResult::Stop
+    fn write_broadcast(&mut self, ctx: &mut Self::Context, msg: &Broadcast)
+                       -> Option<SendResult>
+    {
+        if let Some(data) = msg.encoded(Format::EventSource, message) {
+            self.body.write(ctx, data);
+            Some(self.flush(ctx))
         } else {
-            SendResult::Continue
+            None
         }
     }
 
     fn send_close(&mut self, ctx: &mut Self::Context, code: CloseCode) {
         let blob = format!("data: c[{}, {:?}]\r\n\r\n", code.num(), code.reason());
-        ctx.write(blob);
+        self.body.write(ctx, blob);
+        self.body.flush(ctx);
     }
 
     fn send_heartbeat(&mut self, ctx: &mut Self::Context) {
-        ctx.write("data: h\r\n\r\n");
-    }
-
-    fn session_record(&mut self) -> &mut Option<Record> {
-        &mut self.rec
+        self.body.write(ctx, "data: h\r\n\r\n");
+        self.body.flush(ctx);
     }
 
-    fn flags(&mut self) -> &mut Flags {
-        &mut self.flags
+    fn transport_state(&mut self) -> &mut TransportState {
+        &mut self.state
     }
 }
 
@@ -4,16 +4,17 @@ use std::marker::PhantomData;
 use actix::*;
 use actix_web::*;
 use actix_web::http::Method;
-use serde_json;
+use bytes::Bytes;
 use regex::Regex;
 
 use context::ChannelItem;
-use protocol::{Frame, CloseCode};
+use protocol::{encode, Frame, CloseCode};
 use utils::SockjsHeaders;
 use session::Session;
-use manager::{Broadcast, Record, SessionManager};
+use manager::{Broadcast, Format, Record, SessionManager};
 
-use super::{Transport, SendResult, Flags};
+use super::encoding::BodyWriter;
+use super::{Transport, TransportMode, SendResult, TransportState};
 
 const PRELUDE1: &str = r#"
 <!doctype html>
@@ -33,6 +34,16 @@ const PRELUDE2: &str = r#";
 
 const PRELUDE3: &[u8] = &[b' '; 1024];
 
+/// Wrap frame into script tag
+fn script(s: &str) -> Bytes {
+    Bytes::from(format!("<script>\np({});\n</script>\r\n", encode::string(s)))
+}
+
+/// Encode message frame
+fn message(msg: &str) -> Bytes {
+    script(&encode::message(msg))
+}
+
 
 pub struct HTMLFile<S, SM>
     where S: Session, SM: SessionManager<S>,
@@ -41,8 +52,8 @@ pub struct HTMLFile<S, SM>
     sm: PhantomData<SM>,
     size: usize,
     maxsize: usize,
-    rec: Option<Record>,
-    flags: Flags,
+    state: TransportState,
+    body: BodyWriter,
 }
 
 impl<S, SM> HTMLFile<S, SM>
@@ -57,14 +68,27 @@ impl<S, SM> HTMLFile<S, SM>
     }
 
     fn write(&mut self, s: &str, ctx: &mut HttpContext<Self, Addr<Syn, SM>>) {
-        let b = serde_json::to_string(s).unwrap();
-        self.size += b.len() + 25;
-        ctx.write("<script>\np(");
-        ctx.write(b);
-        ctx.write(");\n</script>\r\n");
+        self.write_script(script(s), ctx);
+    }
+
+    fn write_script(&mut self, data: Bytes, ctx: &mut HttpContext<Self, Addr<Syn, SM>>) {
+        self.body.write(ctx, data);
+        self.size += self.body.flush(ctx);
     }
 
-    pub fn init(req: HttpRequest<Addr<Syn, SM>>, maxsize: usize) -> Result<HttpResponse> {
+    /// Stop transport if response is too big
+    fn check_size(&mut self, ctx: &mut HttpContext<Self, Addr<Syn, SM>>) -> SendResult {
+        if self.size > self.maxsize {
+            self.body.write_eof(ctx);
+            SendResult::Stop
+        } else {
+            SendResult::Continue
+        }
+    }
+
+    pub fn init(req: HttpRequest<Addr<Syn, SM>>, maxsize: usize, compress: bool)
+                -> Result<HttpResponse>
+    {
         lazy_static! {
             static ref CHECK: Regex = Regex::new(r"^[a-zA-Z0-9_\.]+$").unwrap();
         }
@@ -86,17 +110,14 @@ impl<S, SM> HTMLFile<S, SM>
                 .sockjs_no_cache()
                 .sockjs_session_cookie(&req)
                 .take();
+            let mut body = BodyWriter::new(&req, &mut resp, compress);
 
-            let mut ctx = HttpContext::new(
-                req, HTMLFile{s: PhantomData,
-                              sm: PhantomData,
-                              size: 0, rec: None,
-                              maxsize,
-                              flags: Flags::empty()});
-            ctx.write(PRELUDE1);
-            ctx.write(callback);
-            ctx.write(PRELUDE2);
-            ctx.write(PRELUDE3);
+            let mut ctx = HttpContext::from_request(req);
+            body.write(&mut ctx, PRELUDE1);
+            body.write(&mut ctx, callback);
+            body.write(&mut ctx, PRELUDE2);
+            body.write(&mut ctx, PRELUDE3);
+            body.flush(&mut ctx);
 
             // init transport, but aftre prelude only
             ctx.drain()
@@ -107,7 +128,11 @@ impl<S, SM> HTMLFile<S, SM>
                     });
                 }).wait(&mut ctx);
 
-            Ok(resp.body(ctx))
+            Ok(resp.body(ctx.actor(HTMLFile{s: PhantomData,
+                                            sm: PhantomData,
+                                            size: 0,
+                                            maxsize, body,
+                                            state: TransportState::new()})))
         } else {
             Ok(HttpResponse::InternalServerError()
                .body("\"callback\" parameter required"))
@@ -131,6 +156,9 @@ impl<S, SM> Actor for HTMLFile<S, SM>
 impl<S, SM> Transport<S, SM> for HTMLFile<S, SM>
     where S: Session, SM: SessionManager<S>,
 {
+    const NAME: &'static str = "htmlfile";
+    const MODE: TransportMode = TransportMode::Streaming;
+
     fn send(&mut self, ctx: &mut Self::Context, msg: &Frame, rec: &mut Record)
             -> SendResult
     {
@@ -139,8 +167,7 @@ impl<S, SM> Transport<S, SM> for HTMLFile<S, SM>
                 self.write("h", ctx);
             },
             Frame::Message(ref s) => {
-                let blob = format!("a[{}]", serde_json::to_string(&s).unwrap());
-                self.write(&blob, ctx);
+                self.write_script(message(s), ctx);
             }
             Frame::MessageVec(ref s) => {
                 self.write(s, ctx);
@@ -155,16 +182,21 @@ impl<S, SM> Transport<S, SM> for HTMLFile<S, SM>
                 rec.close();
                 let blob = format!("c[{},{:?}]", code.num(), code.reason());
                 self.write(&blob, ctx);
-                ctx.write_eof();
+                self.body.write_eof(ctx);
                 return SendResult::Stop
             }
         };
+        self.check_size(ctx)
+    }
 
-        if self.size > self.maxsize {
-            ctx.write_eof();
-            SendResult::Stop
+    fn write_broadcast(&mut self, ctx: &mut Self::Context, msg: &Broadcast)
+                       -> Option<SendResult>
+    {
+        if let Some(data) = msg.encoded(Format::HtmlFile, message) {
+            self.write_script(data, ctx);
+            Some(self.check_size(ctx))
         } else {
-            SendResult::Continue
+            None
         }
     }
 
@@ -176,12 +208,8 @@ impl<S, SM> Transport<S, SM> for HTMLFile<S, SM>
         self.write("h", ctx);
     }
 
-    fn session_record(&mut self) -> &mut Option<Record> {
-        &mut self.rec
-    }
-
-    fn flags(&mut self) -> &mut Flags {
-        &mut self.flags
+    fn transport_state(&mut self) -> &mut TransportState {
+        &mut self.state
     }
 }
 
@@ -201,10 +229,10 @@ impl<S, SM> Handler<Broadcast> for HTMLFile<S, SM>
     type Result = ();
 
     fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
-        if let Some(mut rec) = self.rec.take() {
-            self.send(ctx, &msg.msg, &mut rec);
-            self.rec = Some(rec);
-        } else if let Some(ref mut rec) = self.rec {
+        if let Some(mut rec) = self.state.rec.take() {
+            self.send_broadcast(ctx, &msg, &mut rec);
+            self.state.rec = Some(rec);
+        } else if let Some(ref mut rec) = self.state.rec {
             rec.add(msg);
         }
     }
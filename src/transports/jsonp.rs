@@ -5,19 +5,22 @@ use std::sync::Arc;
 use actix::*;
 use actix_web::http::Method;
 use actix_web::*;
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use futures::future::{ok, Either, Future};
-use percent_encoding::percent_decode;
 use regex::Regex;
-use serde_json;
 
 use context::ChannelItem;
-use manager::{Broadcast, Record, SessionManager, SessionMessage};
-use protocol::{CloseCode, Frame};
+use manager::{Broadcast, Format, Record, SessionManager, SessionMessage};
+use protocol::{decode, encode, CloseCode, Frame};
 use session::{Message, Session};
-use utils::SockjsHeaders;
+use utils::{payload_error, SockjsHeaders};
 
-use super::{Flags, SendResult, Transport, MAXSIZE};
+use super::{SendResult, Transport, TransportMode, TransportState};
+
+/// Encode message frame as callback argument
+fn message(msg: &str) -> Bytes {
+    Bytes::from(encode::string(&encode::message(msg)))
+}
 
 pub struct JSONPolling<S, SM>
 where
@@ -26,9 +29,8 @@ where
 {
     s: PhantomData<S>,
     sm: PhantomData<SM>,
-    rec: Option<Record>,
     callback: String,
-    flags: Flags,
+    state: TransportState,
 }
 
 // Http actor implementation
@@ -54,9 +56,15 @@ where
         ctx.write(format!(
             "/**/{}({});\r\n",
             self.callback,
-            serde_json::to_string(s).unwrap()
+            encode::string(s)
         ))
     }
+
+    fn write_arg(&self, arg: Bytes, ctx: &mut HttpContext<Self, Addr<Syn, SM>>) {
+        ctx.write(format!("/**/{}(", self.callback));
+        ctx.write(arg);
+        ctx.write(");\r\n");
+    }
 }
 
 // Transport implementation
@@ -65,13 +73,16 @@ where
     S: Session,
     SM: SessionManager<S>,
 {
+    const NAME: &'static str = "jsonp";
+    const MODE: TransportMode = TransportMode::Polling;
+
     fn send(&mut self, ctx: &mut Self::Context, msg: &Frame, record: &mut Record) -> SendResult {
         match *msg {
             Frame::Heartbeat => {
                 self.write("h", ctx);
             }
             Frame::Message(ref s) => {
-                self.write(&format!("a[{:?}]", s), ctx);
+                self.write_arg(message(s), ctx);
             }
             Frame::MessageVec(ref s) => {
                 self.write(&format!("a{}", s), ctx);
@@ -90,6 +101,18 @@ where
         SendResult::Stop
     }
 
+    fn write_broadcast(&mut self, ctx: &mut Self::Context, msg: &Broadcast)
+                       -> Option<SendResult>
+    {
+        if let Some(data) = msg.encoded(Format::Jsonp, message) {
+            self.write_arg(data, ctx);
+            ctx.write_eof();
+            Some(SendResult::Stop)
+        } else {
+            None
+        }
+    }
+
     fn send_heartbeat(&mut self, ctx: &mut Self::Context) {
         self.write("h\n", ctx);
         ctx.write_eof();
@@ -100,12 +123,8 @@ where
         ctx.write_eof();
     }
 
-    fn session_record(&mut self) -> &mut Option<Record> {
-        &mut self.rec
-    }
-
-    fn flags(&mut self) -> &mut Flags {
-        &mut self.flags
+    fn transport_state(&mut self) -> &mut TransportState {
+        &mut self.state
     }
 }
 
@@ -141,8 +160,7 @@ where
             let mut transport = JSONPolling {
                 s: PhantomData,
                 sm: PhantomData,
-                rec: None,
-                flags: Flags::empty(),
+                state: TransportState::new(),
                 callback,
             };
             // init transport
@@ -182,6 +200,8 @@ where
 #[allow(non_snake_case)]
 pub fn JSONPollingSend<S, SM>(
     req: HttpRequest<Addr<Syn, SM>>,
+    max_body_size: usize,
+    max_message_size: usize,
 ) -> Either<HttpResponse, Box<Future<Item = HttpResponse, Error = Error>>>
 where
     S: Session,
@@ -194,12 +214,14 @@ where
                 .finish(),
         )
     } else {
-        Either::B(read(req))
+        Either::B(read(req, max_body_size, max_message_size))
     }
 }
 
 pub fn read<S, SM>(
     req: HttpRequest<Addr<Syn, SM>>,
+    max_body_size: usize,
+    max_message_size: usize,
 ) -> Box<Future<Item = HttpResponse, Error = Error>>
 where
     S: Session,
@@ -210,82 +232,59 @@ where
     Box::new(
         req.clone()
             .body()
-            .limit(MAXSIZE)
-            .map_err(error::ErrorBadRequest)
+            .limit(max_body_size)
+            .map_err(payload_error)
             .and_then(move |buf| {
                 let sid = Arc::new(sid);
 
-                // empty message
-                if buf.is_empty() {
-                    Either::A(ok(
-                        HttpResponse::InternalServerError().body("Payload expected.")
-                    ))
+                let form = req.content_type() == "application/x-www-form-urlencoded";
+                let mut msgs = match decode::jsonp_send(&buf, form) {
+                    Ok(msgs) => msgs,
+                    Err(err) => {
+                        return Either::A(ok(
+                            HttpResponse::InternalServerError().body(err.reason())
+                        ))
+                    }
+                };
+
+                if msgs.iter().any(|msg| msg.len() > max_message_size) {
+                    return Either::A(ok(
+                        HttpResponse::PayloadTooLarge().body("Message is too large.")
+                    ));
+                }
+
+                // do nothing
+                if msgs.is_empty() {
+                    Either::A(ok(HttpResponse::Ok()
+                        .content_type("text/plain; charset=UTF-8")
+                        .sockjs_no_cache()
+                        .sockjs_session_cookie(&req)
+                        .body("ok")))
                 } else {
-                    // deserialize json
-                    let mut msgs: Vec<String> =
-                        if req.content_type() == "application/x-www-form-urlencoded" {
-                            if buf.len() <= 2 || &buf[..2] != b"d=" {
-                                return Either::A(ok(
-                                    HttpResponse::InternalServerError().body("Payload expected.")
-                                ));
-                            }
-
-                            if let Ok(data) = percent_decode(&buf[2..]).decode_utf8() {
-                                match serde_json::from_slice(data.as_ref().as_ref()) {
-                                    Ok(msgs) => msgs,
-                                    Err(_) => {
-                                        return Either::A(ok(HttpResponse::InternalServerError()
-                                            .body("Broken JSON encoding.")));
-                                    }
-                                }
-                            } else {
-                                return Either::A(ok(
-                                    HttpResponse::InternalServerError().body("Payload expected.")
-                                ));
-                            }
-                        } else {
-                            match serde_json::from_slice(&buf) {
-                                Ok(msgs) => msgs,
-                                Err(_) => {
-                                    return Either::A(ok(HttpResponse::InternalServerError()
-                                        .body("Broken JSON encoding.")));
-                                }
-                            }
-                        };
-
-                    // do nothing
-                    if msgs.is_empty() {
-                        Either::A(ok(HttpResponse::Ok()
-                            .content_type("text/plain; charset=UTF-8")
-                            .sockjs_no_cache()
-                            .sockjs_session_cookie(&req)
-                            .body("ok")))
-                    } else {
-                        let last = msgs.pop().unwrap();
-                        for msg in msgs {
-                            req.state().do_send(SessionMessage {
-                                sid: Arc::clone(&sid),
-                                msg: Message(msg),
-                            });
-                        }
-
-                        Either::B(
-                            req.state()
-                                .send(SessionMessage {
-                                    sid: Arc::clone(&sid),
-                                    msg: Message(last),
-                                })
-                                .from_err()
-                                .and_then(move |res| match res {
-                                    Ok(_) => Ok(HttpResponse::Ok()
-                                        .content_type("text/plain; charset=UTF-8")
-                                        .sockjs_no_cache()
-                                        .sockjs_session_cookie(&req)
-                                        .body("ok")),
-                                    Err(_) => Err(error::ErrorNotFound("not found")),
-                                }),
-                        )
+                    let last = msgs.pop().unwrap();
+                    for msg in msgs {
+                        req.state().do_send(SessionMessage {
+                            sid: Arc::clone(&sid),
+                            msg: Message(msg),
+                        });
                     }
+
+                    Either::B(
+                        req.state()
+                            .send(SessionMessage {
+                                sid: Arc::clone(&sid),
+                                msg: Message(last),
+                            })
+                            .from_err()
+                            .and_then(move |res| match res {
+                                Ok(_) => Ok(HttpResponse::Ok()
+                                    .content_type("text/plain; charset=UTF-8")
+                                    .sockjs_no_cache()
+                                    .sockjs_session_cookie(&req)
+                                    .body("ok")),
+                                Err(_) => Err(error::ErrorNotFound("not found")),
+                            }),
+                    )
                 }
             }),
     )
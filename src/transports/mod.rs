@@ -2,9 +2,10 @@ use actix::*;
 use actix_web::*;
 
 use context::ChannelItem;
+use presence::UserKey;
 use protocol::{Frame, CloseCode};
 use session::{Session, SessionState};
-use manager::{Acquire, Release, Broadcast, Record, SessionManager};
+use manager::{Acquire, Release, Broadcast, Record, RecordEntry, SessionManager};
 
 mod xhr;
 mod xhrsend;
@@ -14,6 +15,9 @@ mod jsonp;
 mod htmlfile;
 mod websocket;
 mod rawwebsocket;
+mod deflate;
+mod encoding;
+mod wsframe;
 
 pub use self::xhr::Xhr;
 pub use self::xhrsend::XhrSend;
@@ -23,8 +27,95 @@ pub use self::htmlfile::HTMLFile;
 pub use self::websocket::Websocket;
 pub use self::rawwebsocket::RawWebsocket;
 pub use self::jsonp::{JSONPolling, JSONPollingSend};
+pub use self::deflate::Deflate;
 
 pub const MAXSIZE: usize = 131_072;  // 128K bytes
+// websocket frame holds json encoded array of messages, frame limit
+// leaves room for encoding of message of `MAXSIZE` bytes
+pub const MAX_FRAME_SIZE: usize = 262_144;  // 256K bytes
+
+/// Sockjs transport
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TransportKind {
+    /// `websocket`
+    Websocket,
+    /// Raw websocket, served at `/websocket` path
+    RawWebsocket,
+    /// `xhr_streaming`
+    XhrStreaming,
+    /// `xhr`
+    Xhr,
+    /// `xhr_send`
+    XhrSend,
+    /// `eventsource`
+    EventSource,
+    /// `htmlfile`
+    HtmlFile,
+    /// `jsonp`
+    Jsonp,
+    /// `jsonp_send`
+    JsonpSend,
+}
+
+impl TransportKind {
+    /// All sockjs transports
+    pub fn all() -> &'static [TransportKind] {
+        const ALL: [TransportKind; 9] = [
+            TransportKind::Websocket,
+            TransportKind::RawWebsocket,
+            TransportKind::XhrStreaming,
+            TransportKind::Xhr,
+            TransportKind::XhrSend,
+            TransportKind::EventSource,
+            TransportKind::HtmlFile,
+            TransportKind::Jsonp,
+            TransportKind::JsonpSend,
+        ];
+        &ALL
+    }
+
+    /// Transport name
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TransportKind::Websocket => "websocket",
+            TransportKind::RawWebsocket => "raw_websocket",
+            TransportKind::XhrStreaming => "xhr_streaming",
+            TransportKind::Xhr => "xhr",
+            TransportKind::XhrSend => "xhr_send",
+            TransportKind::EventSource => "eventsource",
+            TransportKind::HtmlFile => "htmlfile",
+            TransportKind::Jsonp => "jsonp",
+            TransportKind::JsonpSend => "jsonp_send",
+        }
+    }
+
+    /// Transport by name
+    pub fn from_name(name: &str) -> Option<TransportKind> {
+        TransportKind::all().iter().find(|kind| kind.name() == name).cloned()
+    }
+}
+
+/// Kind of transport
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransportMode {
+    /// Websocket, sockjs framed or raw
+    Websocket,
+    /// Streaming http response (XhrStreaming, EventSource, HTMLFile)
+    Streaming,
+    /// One http response per poll (Xhr, JSONPolling)
+    Polling,
+}
+
+/// Transport connected to the session
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TransportInfo {
+    /// Transport name, for example `xhr_streaming`
+    pub name: &'static str,
+    /// Kind of transport
+    pub mode: TransportMode,
+    /// Negotiated websocket subprotocol, raw websocket transport only
+    pub protocol: Option<&'static str>,
+}
 
 bitflags! {
     pub struct Flags: u8 {
@@ -33,7 +124,8 @@ bitflags! {
     }
 }
 
-type TransportContext<T, SM> = HttpContext<T, Addr<Syn, SM>>;
+/// Context of http transport actor
+pub type TransportContext<T, SM> = HttpContext<T, Addr<Syn, SM>>;
 
 /// Result of `Transport::send` method
 #[derive(PartialEq)]
@@ -44,19 +136,53 @@ pub enum SendResult {
     Stop,
 }
 
-trait Transport<S, SM>: Actor<Context=TransportContext<Self, SM>> +
+/// Transport state, holds session record while transport owns the session
+pub struct TransportState {
+    pub(crate) flags: Flags,
+    pub(crate) rec: Option<Record>,
+}
+
+impl TransportState {
+    pub fn new() -> TransportState {
+        TransportState {flags: Flags::empty(), rec: None}
+    }
+}
+
+impl Default for TransportState {
+    fn default() -> TransportState {
+        TransportState::new()
+    }
+}
+
+/// Http transport
+///
+/// Transport actor runs in `HttpContext` and acquires session with
+/// `init_transport()`. `Handler<ChannelItem>` and `Handler<Broadcast>`
+/// implementations should delegate to `handle_message()` and
+/// `handle_broadcast()`, and `Actor::stopping()` should call `release()`.
+///
+/// Custom transports get registered with `SockJS::transport()`.
+pub trait Transport<S, SM>: Actor<Context=TransportContext<Self, SM>> +
     Handler<ChannelItem> + Handler<Broadcast>
     where S: Session, SM: SessionManager<S>,
 {
-    /// Session flags
-    fn flags(&mut self) -> &mut Flags;
+    /// Transport name
+    const NAME: &'static str;
+
+    /// Kind of transport
+    const MODE: TransportMode;
+
+    /// Transport state
+    fn transport_state(&mut self) -> &mut TransportState;
 
-    /// Set record
-    fn session_record(&mut self) -> &mut Option<Record>;
+    /// Transport info passed to the session, see `SockJSContext::transport()`
+    fn info(&self) -> TransportInfo {
+        TransportInfo{name: Self::NAME, mode: Self::MODE, protocol: None}
+    }
 
     /// Stop transport and release session
     fn release(&mut self, ctx: &mut TransportContext<Self, SM>) {
-        if let Some(mut rec) = self.session_record().take() {
+        if let Some(mut rec) = self.transport_state().rec.take() {
             if !ctx.connected() {
                 rec.interrupted();
             }
@@ -65,50 +191,67 @@ trait Transport<S, SM>: Actor<Context=TransportContext<Self, SM>> +
         ctx.stop();
     }
 
+    /// Handle broadcast message
     fn handle_broadcast(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
-        if let Some(mut rec) = self.session_record().take() {
-            if self.flags().contains(Flags::READY) {
-                rec.add(msg.msg);
-                *self.session_record() = Some(rec);
-            } else if SendResult::Stop == self.send(ctx, &msg.msg, &mut rec) {
-                *self.session_record() = Some(rec);
+        if let Some(mut rec) = self.transport_state().rec.take() {
+            if !self.transport_state().flags.contains(Flags::READY) {
+                // keep order with frames buffered by session context
+                rec.add(msg);
+                self.transport_state().rec = Some(rec);
+            } else if SendResult::Stop == self.send_broadcast(ctx, &msg, &mut rec) {
+                self.transport_state().rec = Some(rec);
                 self.release(ctx);
             } else {
-                *self.session_record() = Some(rec);
+                self.transport_state().rec = Some(rec);
             }
         }
     }
 
+    /// Handle message from session context
     fn handle_message(&mut self, msg: ChannelItem, ctx: &mut Self::Context) {
         match msg {
             ChannelItem::Frame(msg) => {
-                if let Some(mut rec) = self.session_record().take() {
-                    if self.flags().contains(Flags::READY) {
-                        if SendResult::Stop == self.send(ctx, &msg, &mut rec) {
-                            *self.session_record() = Some(rec);
+                if let Some(mut rec) = self.transport_state().rec.take() {
+                    if self.transport_state().flags.contains(Flags::READY) {
+                        if SendResult::Stop == self.send_frame(ctx, &msg, &mut rec) {
+                            self.transport_state().rec = Some(rec);
                             self.release(ctx);
                         } else {
-                            *self.session_record() = Some(rec);
+                            self.transport_state().rec = Some(rec);
                         }
                     } else {
                         rec.add(msg);
-                        *self.session_record() = Some(rec);
+                        self.transport_state().rec = Some(rec);
                     }
                 }
             }
             ChannelItem::Ready => {
-                if let Some(mut rec) = self.session_record().take() {
+                if let Some(mut rec) = self.transport_state().rec.take() {
                     if SendResult::Stop == self.send_buffered(ctx, &mut rec) {
-                        *self.session_record() = Some(rec);
+                        self.transport_state().rec = Some(rec);
                         self.release(ctx);
                     } else {
-                        *self.session_record() = Some(rec);
+                        self.transport_state().rec = Some(rec);
                     }
                 }
-                if self.flags().contains(Flags::RELEASE) {
+                if self.transport_state().flags.contains(Flags::RELEASE) {
                     self.release(ctx)
                 } else {
-                    self.flags().insert(Flags::READY);
+                    self.transport_state().flags.insert(Flags::READY);
+                }
+            }
+            ChannelItem::Control(ctl) => {
+                if let Some(mut rec) = self.transport_state().rec.take() {
+                    // replayed messages get sent right away
+                    rec.control(ctl);
+                    if self.transport_state().flags.contains(Flags::READY) &&
+                        SendResult::Stop == self.send_buffered(ctx, &mut rec)
+                    {
+                        self.transport_state().rec = Some(rec);
+                        self.release(ctx);
+                    } else {
+                        self.transport_state().rec = Some(rec);
+                    }
                 }
             }
         }
@@ -118,18 +261,64 @@ trait Transport<S, SM>: Actor<Context=TransportContext<Self, SM>> +
     fn send(&mut self, ctx: &mut TransportContext<Self, SM>, msg: &Frame, record: &mut Record)
             -> SendResult;
 
+    /// Send sockjs frame, messages get numbered in reliable mode.
+    ///
+    /// Every frame written by transport passes this method or
+    /// `send_broadcast()`, frame gets recorded in session span here.
+    fn send_frame(&mut self, ctx: &mut TransportContext<Self, SM>,
+                  msg: &Frame, record: &mut Record) -> SendResult {
+        record.span.frame(msg);
+        if let Some(msg) = record.prepare(msg) {
+            self.send(ctx, &msg, record)
+        } else {
+            self.send(ctx, msg, record)
+        }
+    }
+
+    /// Send broadcast message, message written by `write_broadcast()`
+    /// is not numbered so reliable sessions get it with `send_frame()`
+    fn send_broadcast(&mut self, ctx: &mut TransportContext<Self, SM>,
+                      msg: &Broadcast, record: &mut Record) -> SendResult {
+        if !record.reliable() {
+            if let Some(res) = self.write_broadcast(ctx, msg) {
+                record.span.frame(&msg.msg);
+                return res
+            }
+        }
+        self.send_frame(ctx, &msg.msg, record)
+    }
+
+    /// Write broadcast message encoded with `Broadcast`'s shared cache,
+    /// so message is encoded once for all sessions.
+    ///
+    /// Returns `None` if message has to be sent as regular frame.
+    #[allow(unused_variables)]
+    fn write_broadcast(&mut self, ctx: &mut TransportContext<Self, SM>, msg: &Broadcast)
+                       -> Option<SendResult> {
+        None
+    }
+
     /// Send close frame
     fn send_close(&mut self, ctx: &mut TransportContext<Self, SM>, code: CloseCode);
 
     /// Send heartbeat
     fn send_heartbeat(&mut self, ctx: &mut TransportContext<Self, SM>);
 
-    /// Send sockjs frame
+    /// Send buffered frames of the session record
     fn send_buffered(&mut self, ctx: &mut TransportContext<Self, SM>, record: &mut Record)
                      -> SendResult {
         while !record.buffer.is_empty() {
             if let Some(msg) = record.buffer.pop_front() {
-                if let SendResult::Stop = self.send(ctx, msg.as_ref(), record) {
+                let res = match msg {
+                    RecordEntry::Numbered(ref msg) => {
+                        // numbered already, `send_frame()` would number it again
+                        record.span.frame(msg);
+                        self.send(ctx, msg, record)
+                    }
+                    RecordEntry::Broadcast(ref msg) => self.send_broadcast(ctx, msg, record),
+                    RecordEntry::Frame(ref msg) => self.send_frame(ctx, msg, record),
+                };
+                if let SendResult::Stop = res {
                     return SendResult::Stop
                 }
             }
@@ -137,10 +326,19 @@ trait Transport<S, SM>: Actor<Context=TransportContext<Self, SM>> +
         SendResult::Continue
     }
 
+    /// Acquire session, buffered messages get sent once session is acquired
     fn init_transport(&mut self, session: String, ctx: &mut TransportContext<Self, SM>) {
         // acquire session
         let addr: Addr<Syn, _> = ctx.address();
-        ctx.state().send(Acquire::new(session, addr.recipient()))
+        let resume = ctx.request().query().get("resume").map(|s| s.to_owned());
+        let user = ctx.request().extensions().get::<UserKey>().map(|key| key.0.clone());
+        let remote = ctx.request().connection_info().remote().map(|s| s.to_owned());
+        let info = self.info();
+        let acquire = Acquire::new(session, addr.recipient(), info)
+            .resume(resume)
+            .user(user)
+            .remote(remote);
+        ctx.state().send(acquire)
             .into_actor(self)
             .map(|res, act, ctx| {
                 match res {
@@ -152,34 +350,36 @@ trait Transport<S, SM>: Actor<Context=TransportContext<Self, SM>> +
                             SessionState::Running => {
                                 if let SendResult::Stop = act.send_buffered(ctx, &mut rec.0) {
                                     // release immidietly
-                                    act.flags().insert(Flags::RELEASE);
+                                    act.transport_state().flags.insert(Flags::RELEASE);
                                 }
-                                *act.session_record() = Some(rec.0);
+                                act.transport_state().rec = Some(rec.0);
                                 ctx.add_message_stream(rec.1);
                             },
                             SessionState::New => {
                                 rec.0.state = SessionState::Running;
-                                if let SendResult::Stop = act.send(ctx, &Frame::Open, &mut rec.0)
-                                {
+                                let res = act.send_frame(ctx, &Frame::Open, &mut rec.0);
+                                if let SendResult::Stop = res {
                                     // release is send stops
-                                    act.flags().insert(Flags::RELEASE);
+                                    act.transport_state().flags.insert(Flags::RELEASE);
                                 } else if let SendResult::Stop =
                                     act.send_buffered(ctx, &mut rec.0) // send buffered messages
                                 {
                                     // release immidietly
-                                    act.flags().insert(Flags::RELEASE);
+                                    act.transport_state().flags.insert(Flags::RELEASE);
                                 }
-                                *act.session_record() = Some(rec.0);
+                                act.transport_state().rec = Some(rec.0);
                                 ctx.add_message_stream(rec.1);
                             },
 
                             SessionState::Interrupted => {
-                                act.send(ctx, &Frame::Close(CloseCode::Interrupted), &mut rec.0);
+                                act.send_frame(
+                                    ctx, &Frame::Close(CloseCode::Interrupted), &mut rec.0);
                                 ctx.state().do_send(Release{ses: rec.0});
                             },
 
                             SessionState::Closed => {
-                                act.send(ctx, &Frame::Close(CloseCode::GoAway), &mut rec.0);
+                                act.send_frame(
+                                    ctx, &Frame::Close(CloseCode::GoAway), &mut rec.0);
                                 ctx.state().do_send(Release{ses: rec.0});
                             }
                         }
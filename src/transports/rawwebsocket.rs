@@ -3,14 +3,17 @@ use std::sync::Arc;
 
 use actix::*;
 use actix_web::*;
+use http::header;
 use rand;
 
 use context::ChannelItem;
-use manager::{Acquire, Broadcast, Record, Release, SessionManager, SessionMessage};
+use manager::{Broadcast, Format, Record, Release, SessionBinary, SessionManager, SessionMessage};
 use protocol::{CloseCode, Frame};
-use session::{Message, Session, SessionState};
+use session::{Message, Session};
 
-use super::{Flags, SendResult};
+use super::deflate::Deflate;
+use super::wsframe::{reader, text_frame, WsError, WsMessage, WsWriter, CLOSE_NORMAL, CLOSE_SIZE};
+use super::{SendResult, Transport, TransportInfo, TransportMode, TransportState};
 
 pub struct RawWebsocket<S, SM>
 where
@@ -19,8 +22,10 @@ where
 {
     s: PhantomData<S>,
     sm: PhantomData<SM>,
-    rec: Option<Record>,
-    flags: Flags,
+    state: TransportState,
+    max_message_size: usize,
+    protocol: Option<&'static str>,
+    ws: WsWriter,
 }
 
 impl<S, SM> RawWebsocket<S, SM>
@@ -28,20 +33,40 @@ where
     S: Session,
     SM: SessionManager<S>,
 {
-    pub fn init(req: HttpRequest<Addr<Syn, SM>>) -> Result<HttpResponse> {
+    pub fn init(
+        req: HttpRequest<Addr<Syn, SM>>,
+        max_frame_size: usize,
+        max_message_size: usize,
+        protocols: &[&'static str],
+        deflate: Option<&Deflate>,
+    ) -> Result<HttpResponse> {
         let mut resp = ws::handshake(&req)?;
 
+        // first subprotocol offered by the client and supported by the server
+        let protocol = select_protocol(&req, protocols);
+        if let Some(protocol) = protocol {
+            resp.header(header::SEC_WEBSOCKET_PROTOCOL, protocol);
+        }
+
+        // permessage-deflate
+        let deflate = deflate.and_then(|deflate| deflate.negotiate(&req));
+        if let Some(ref params) = deflate {
+            resp.header(header::SEC_WEBSOCKET_EXTENSIONS, params.header().as_str());
+        }
+
         // session
         let sid = format!("{}", rand::random::<u32>());
 
-        let mut ctx = ws::WebsocketContext::from_request(req.clone());
-        ctx.add_stream(ws::WsStream::new(req));
+        let mut ctx = HttpContext::from_request(req.clone());
+        ctx.add_stream(reader(req, max_frame_size, deflate.as_ref()));
 
         let mut tr = RawWebsocket {
             s: PhantomData,
             sm: PhantomData,
-            rec: None,
-            flags: Flags::empty(),
+            state: TransportState::new(),
+            max_message_size,
+            protocol,
+            ws: WsWriter::new(deflate.as_ref()),
         };
         // init transport
         tr.init_transport(sid, &mut ctx);
@@ -49,179 +74,97 @@ where
         Ok(resp.body(ctx.actor(tr)))
     }
 
-    fn send(
-        &mut self,
-        ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>,
-        msg: &Frame,
-        record: &mut Record,
-    ) -> SendResult {
+    /// Close connection, inbound message is bigger than `max_message_size`
+    fn message_too_large(&mut self, ctx: &mut HttpContext<Self, Addr<Syn, SM>>) {
+        ctx.write(self.ws.close(CLOSE_SIZE, "Message is too large"));
+        if let Some(mut rec) = self.state.rec.take() {
+            rec.interrupted();
+            ctx.state().do_send(Release { ses: rec });
+        }
+        ctx.stop();
+    }
+}
+
+impl<S, SM> Transport<S, SM> for RawWebsocket<S, SM>
+where
+    S: Session,
+    SM: SessionManager<S>,
+{
+    const NAME: &'static str = "raw_websocket";
+    const MODE: TransportMode = TransportMode::Websocket;
+
+    fn send(&mut self, ctx: &mut Self::Context, msg: &Frame, record: &mut Record) -> SendResult {
         match *msg {
             Frame::Heartbeat => {
-                ctx.ping("");
+                ctx.write(self.ws.ping(b""));
             }
             Frame::Message(ref s) | Frame::MessageVec(ref s) => {
-                ctx.text(s);
+                ctx.write(self.ws.text(s));
             }
             Frame::MessageBlob(ref b) => {
-                ctx.binary(b.clone());
+                ctx.write(self.ws.binary(b));
             }
             Frame::Open => (),
             Frame::Close(_) => {
                 record.close();
-                ctx.close(Some(ws::CloseReason {
-                    code: ws::CloseCode::Normal,
-                    description: Some("Go away!".to_owned()),
-                }));
+                ctx.write(self.ws.close(CLOSE_NORMAL, "Go away!"));
             }
         };
 
         SendResult::Continue
     }
 
-    fn send_close(&mut self, ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>, _: CloseCode) {
-        ctx.close(Some(ws::CloseReason {
-            code: ws::CloseCode::Normal,
-            description: Some("Go away!".to_owned()),
-        }));
+    /// Send broadcast message, uncompressed websocket frame
+    /// is shared by all sessions
+    fn write_broadcast(&mut self, ctx: &mut Self::Context, msg: &Broadcast)
+                       -> Option<SendResult>
+    {
+        if self.ws.compressed() {
+            return None
+        }
+        let encoded = msg.encoded(Format::RawWebsocket, |msg| text_frame(msg.as_bytes()));
+        if let Some(data) = encoded {
+            ctx.write(data);
+            Some(SendResult::Continue)
+        } else {
+            None
+        }
     }
 
-    fn session_record(&mut self) -> &mut Option<Record> {
-        &mut self.rec
+    fn send_close(&mut self, ctx: &mut Self::Context, _: CloseCode) {
+        ctx.write(self.ws.close(CLOSE_NORMAL, "Go away!"));
     }
 
-    fn flags(&mut self) -> &mut Flags {
-        &mut self.flags
+    fn send_heartbeat(&mut self, ctx: &mut Self::Context) {
+        ctx.write(self.ws.ping(b""));
     }
 
-    /// Stop transport and release session
-    fn release(&mut self, ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>) {
-        if let Some(mut rec) = self.session_record().take() {
-            if !ctx.connected() {
-                rec.interrupted();
-            }
-            ctx.state().do_send(Release { ses: rec });
-        }
-        ctx.stop();
+    fn transport_state(&mut self) -> &mut TransportState {
+        &mut self.state
     }
 
-    fn handle_message(
-        &mut self,
-        msg: ChannelItem,
-        ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>,
-    ) {
-        match msg {
-            ChannelItem::Frame(msg) => {
-                if let Some(mut rec) = self.session_record().take() {
-                    if self.flags().contains(Flags::READY) {
-                        if SendResult::Stop == self.send(ctx, &msg, &mut rec) {
-                            *self.session_record() = Some(rec);
-                            self.release(ctx);
-                        } else {
-                            *self.session_record() = Some(rec);
-                        }
-                    } else {
-                        rec.add(msg);
-                        *self.session_record() = Some(rec);
-                    }
-                }
-            }
-            ChannelItem::Ready => {
-                if let Some(mut rec) = self.session_record().take() {
-                    if SendResult::Stop == self.send_buffered(ctx, &mut rec) {
-                        *self.session_record() = Some(rec);
-                        self.release(ctx);
-                    } else {
-                        *self.session_record() = Some(rec);
-                    }
-                }
-                if self.flags().contains(Flags::RELEASE) {
-                    self.release(ctx)
-                } else {
-                    self.flags().insert(Flags::READY);
-                }
-            }
+    fn info(&self) -> TransportInfo {
+        TransportInfo {
+            name: Self::NAME,
+            mode: Self::MODE,
+            protocol: self.protocol,
         }
     }
+}
 
-    /// Send sockjs frame
-    fn send_buffered(
-        &mut self,
-        ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>,
-        record: &mut Record,
-    ) -> SendResult {
-        while !record.buffer.is_empty() {
-            if let Some(msg) = record.buffer.pop_front() {
-                if let SendResult::Stop = self.send(ctx, msg.as_ref(), record) {
-                    return SendResult::Stop;
-                }
+fn select_protocol<S>(req: &HttpRequest<S>, protocols: &[&'static str]) -> Option<&'static str> {
+    if let Some(Ok(offered)) = req.headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .map(|hdr| hdr.to_str())
+    {
+        for name in offered.split(',') {
+            let name = name.trim();
+            if let Some(protocol) = protocols.iter().find(|p| **p == name) {
+                return Some(*protocol);
             }
         }
-        SendResult::Continue
-    }
-
-    fn init_transport(
-        &mut self,
-        session: String,
-        ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>,
-    ) {
-        // acquire session
-        let addr: Addr<Syn, _> = ctx.address();
-        ctx.state().send(Acquire::new(session, addr.recipient()))
-            .into_actor(self)
-            .map(|res, act, ctx| {
-                match res {
-                    Ok(mut rec) => {
-                        // copy messages into buffer
-                        trace!("STATE: {:?}", rec.0.state);
-
-                        match rec.0.state {
-                            SessionState::Running => {
-                                if let SendResult::Stop = act.send_buffered(ctx, &mut rec.0) {
-                                    // release immidietly
-                                    act.flags().insert(Flags::RELEASE);
-                                }
-                                *act.session_record() = Some(rec.0);
-                                ctx.add_message_stream(rec.1);
-                            },
-                            SessionState::New => {
-                                rec.0.state = SessionState::Running;
-                                if let SendResult::Stop = act.send(ctx, &Frame::Open, &mut rec.0)
-                                {
-                                    // release is send stops
-                                    act.flags().insert(Flags::RELEASE);
-                                } else if let SendResult::Stop =
-                                    act.send_buffered(ctx, &mut rec.0) // send buffered messages
-                                {
-                                    // release immidietly
-                                    act.flags().insert(Flags::RELEASE);
-                                }
-                                *act.session_record() = Some(rec.0);
-                                ctx.add_message_stream(rec.1);
-                            },
-
-                            SessionState::Interrupted => {
-                                act.send(ctx, &Frame::Close(CloseCode::Interrupted), &mut rec.0);
-                                ctx.state().do_send(Release{ses: rec.0});
-                            },
-
-                            SessionState::Closed => {
-                                act.send(ctx, &Frame::Close(CloseCode::GoAway), &mut rec.0);
-                                ctx.state().do_send(Release{ses: rec.0});
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        act.send_close(ctx, err.into());
-                        ctx.stop();
-                    }
-                }
-            })
-            // session manager is dead?
-            .map_err(|_, act, ctx| {
-                act.send_close(ctx, CloseCode::InternalError);
-            })
-            .wait(ctx);
     }
+    None
 }
 
 // Http actor implementation
@@ -230,10 +173,10 @@ where
     S: Session,
     SM: SessionManager<S>,
 {
-    type Context = ws::WebsocketContext<Self, Addr<Syn, SM>>;
+    type Context = HttpContext<Self, Addr<Syn, SM>>;
 
     fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
-        if let Some(mut rec) = self.rec.take() {
+        if let Some(mut rec) = self.state.rec.take() {
             rec.close();
             ctx.state().do_send(Release { ses: rec });
         }
@@ -261,33 +204,38 @@ where
     type Result = ();
 
     fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
-        if let Some(mut rec) = self.rec.take() {
-            self.send(ctx, &msg.msg, &mut rec);
-            self.rec = Some(rec);
+        if let Some(mut rec) = self.state.rec.take() {
+            self.send_broadcast(ctx, &msg, &mut rec);
+            self.state.rec = Some(rec);
         }
     }
 }
 
-impl<S, SM> StreamHandler<ws::Message, ws::ProtocolError> for RawWebsocket<S, SM>
+impl<S, SM> StreamHandler<WsMessage, WsError> for RawWebsocket<S, SM>
 where
     S: Session,
     SM: SessionManager<S>,
 {
-    fn error(&mut self, _: ws::ProtocolError, ctx: &mut Self::Context) -> Running {
-        if let Some(rec) = self.rec.take() {
+    fn error(&mut self, err: WsError, ctx: &mut Self::Context) -> Running {
+        if let Some((code, reason)) = err.close() {
+            ctx.write(self.ws.close(code, reason));
+        }
+        if let Some(mut rec) = self.state.rec.take() {
+            rec.interrupted();
             ctx.state().do_send(Release { ses: rec });
         }
-        self.release(ctx);
         Running::Stop
     }
 
-    fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
+    fn handle(&mut self, msg: WsMessage, ctx: &mut Self::Context) {
         // process websocket messages
         match msg {
-            ws::Message::Ping(msg) => ctx.pong(&msg),
-            ws::Message::Text(text) => {
-                if !text.is_empty() {
-                    if let Some(ref rec) = self.rec {
+            WsMessage::Ping(msg) => ctx.write(self.ws.pong(&msg)),
+            WsMessage::Text(text) => {
+                if text.len() > self.max_message_size {
+                    self.message_too_large(ctx);
+                } else if !text.is_empty() {
+                    if let Some(ref rec) = self.state.rec {
                         ctx.state().do_send(SessionMessage {
                             sid: Arc::clone(&rec.sid),
                             msg: Message(text),
@@ -295,10 +243,25 @@ where
                     }
                 }
             }
-            ws::Message::Binary(_) => {
-                error!("Not supported!");
+            WsMessage::Binary(bin) => {
+                if bin.len() > self.max_message_size {
+                    self.message_too_large(ctx);
+                } else if let Some(ref rec) = self.state.rec {
+                    ctx.state().do_send(SessionBinary {
+                        sid: Arc::clone(&rec.sid),
+                        msg: bin,
+                    });
+                }
+            }
+            WsMessage::Close(code) => {
+                ctx.write(self.ws.close_reply(code));
+                if let Some(mut rec) = self.state.rec.take() {
+                    rec.close();
+                    ctx.state().do_send(Release { ses: rec });
+                }
+                ctx.stop();
             }
-            _ => (),
+            WsMessage::Pong => (),
         }
     }
 }
@@ -3,14 +3,23 @@ use std::sync::Arc;
 
 use actix::*;
 use actix_web::*;
-use serde_json;
+use http::header;
 
 use context::ChannelItem;
-use manager::{Acquire, Broadcast, Record, Release, SessionManager, SessionMessage};
-use protocol::{CloseCode, Frame};
-use session::{Message, Session, SessionState};
-
-use super::{Flags, SendResult};
+use manager::{Broadcast, Format, Record, Release, SessionManager, SessionMessage};
+use protocol::{decode, encode, CloseCode, Frame};
+use session::{Message, Session};
+
+use super::deflate::Deflate;
+use super::wsframe::{
+    reader, text_frame, WsError, WsMessage, WsWriter, CLOSE_INVALID, CLOSE_SIZE,
+};
+use super::{SendResult, Transport, TransportMode, TransportState};
+
+/// Encode message frame
+fn message(msg: &str) -> String {
+    encode::message(msg)
+}
 
 pub struct Websocket<S, SM>
 where
@@ -19,8 +28,9 @@ where
 {
     s: PhantomData<S>,
     sm: PhantomData<SM>,
-    rec: Option<Record>,
-    flags: Flags,
+    state: TransportState,
+    max_message_size: usize,
+    ws: WsWriter,
 }
 
 impl<S, SM> Websocket<S, SM>
@@ -28,196 +38,99 @@ where
     S: Session,
     SM: SessionManager<S>,
 {
-    pub fn init(req: HttpRequest<Addr<Syn, SM>>) -> Result<HttpResponse> {
+    pub fn init(
+        req: HttpRequest<Addr<Syn, SM>>,
+        max_frame_size: usize,
+        max_message_size: usize,
+        deflate: Option<&Deflate>,
+    ) -> Result<HttpResponse> {
         let mut resp = ws::handshake(&req)?;
         let session = req.match_info().get("session").unwrap().to_owned();
 
-        let mut ctx = ws::WebsocketContext::from_request(req.clone());
-        ctx.add_stream(ws::WsStream::new(req));
+        // permessage-deflate
+        let deflate = deflate.and_then(|deflate| deflate.negotiate(&req));
+        if let Some(ref params) = deflate {
+            resp.header(header::SEC_WEBSOCKET_EXTENSIONS, params.header().as_str());
+        }
+
+        let mut ctx = HttpContext::from_request(req.clone());
+        ctx.add_stream(reader(req, max_frame_size, deflate.as_ref()));
 
         // init transport
         let mut tr = Websocket {
             s: PhantomData,
             sm: PhantomData,
-            rec: None,
-            flags: Flags::empty(),
+            state: TransportState::new(),
+            max_message_size,
+            ws: WsWriter::new(deflate.as_ref()),
         };
         tr.init_transport(session, &mut ctx);
 
         Ok(resp.body(ctx.actor(tr)))
     }
+}
+
+impl<S, SM> Transport<S, SM> for Websocket<S, SM>
+where
+    S: Session,
+    SM: SessionManager<S>,
+{
+    const NAME: &'static str = "websocket";
+    const MODE: TransportMode = TransportMode::Websocket;
 
-    fn send(
-        &mut self,
-        ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>,
-        msg: &Frame,
-        record: &mut Record,
-    ) -> SendResult {
+    fn send(&mut self, ctx: &mut Self::Context, msg: &Frame, record: &mut Record) -> SendResult {
         match *msg {
             Frame::Heartbeat => {
-                ctx.text("h");
+                ctx.write(self.ws.text("h"));
             }
             Frame::Message(ref s) => {
-                ctx.text(format!("a[{:?}]", s));
+                ctx.write(self.ws.text(message(s)));
             }
             Frame::MessageVec(ref s) => {
-                ctx.text(format!("a{}", s));
+                ctx.write(self.ws.text(format!("a{}", s)));
             }
             Frame::MessageBlob(_) => {
                 // ctx.write(format!("a{}\n", s));
             }
             Frame::Open => {
-                ctx.text("o");
+                ctx.write(self.ws.text("o"));
             }
             Frame::Close(code) => {
                 record.close();
-                ctx.text(format!("c[{},{:?}]\n", code.num(), code.reason()));
+                ctx.write(self.ws.text(format!("c[{},{:?}]\n", code.num(), code.reason())));
             }
         };
 
         SendResult::Continue
     }
 
-    fn send_close(&mut self, ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>, code: CloseCode) {
-        ctx.text(format!("c[{},{:?}]", code.num(), code.reason()));
-    }
-
-    fn session_record(&mut self) -> &mut Option<Record> {
-        &mut self.rec
-    }
-
-    fn flags(&mut self) -> &mut Flags {
-        &mut self.flags
-    }
-
-    /// Stop transport and release session
-    fn release(&mut self, ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>) {
-        if let Some(mut rec) = self.session_record().take() {
-            if !ctx.connected() {
-                rec.interrupted();
-            }
-            ctx.state().do_send(Release { ses: rec });
+    /// Send broadcast message, uncompressed websocket frame
+    /// is shared by all sessions
+    fn write_broadcast(&mut self, ctx: &mut Self::Context, msg: &Broadcast)
+                       -> Option<SendResult>
+    {
+        if self.ws.compressed() {
+            return None
         }
-        ctx.stop();
-    }
-
-    fn handle_message(
-        &mut self,
-        msg: ChannelItem,
-        ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>,
-    ) {
-        match msg {
-            ChannelItem::Frame(msg) => {
-                if let Some(mut rec) = self.session_record().take() {
-                    if self.flags().contains(Flags::READY) {
-                        if SendResult::Stop == self.send(ctx, &msg, &mut rec) {
-                            *self.session_record() = Some(rec);
-                            self.release(ctx);
-                        } else {
-                            *self.session_record() = Some(rec);
-                        }
-                    } else {
-                        rec.add(msg);
-                        *self.session_record() = Some(rec);
-                    }
-                }
-            }
-            ChannelItem::Ready => {
-                if let Some(mut rec) = self.session_record().take() {
-                    if SendResult::Stop == self.send_buffered(ctx, &mut rec) {
-                        *self.session_record() = Some(rec);
-                        self.release(ctx);
-                    } else {
-                        *self.session_record() = Some(rec);
-                    }
-                }
-                if self.flags().contains(Flags::RELEASE) {
-                    self.release(ctx)
-                } else {
-                    self.flags().insert(Flags::READY);
-                }
-            }
+        let encoded = msg.encoded(Format::Websocket, |msg| text_frame(message(msg).as_bytes()));
+        if let Some(data) = encoded {
+            ctx.write(data);
+            Some(SendResult::Continue)
+        } else {
+            None
         }
     }
 
-    /// Send sockjs frame
-    fn send_buffered(
-        &mut self,
-        ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>,
-        record: &mut Record,
-    ) -> SendResult {
-        while !record.buffer.is_empty() {
-            if let Some(msg) = record.buffer.pop_front() {
-                if let SendResult::Stop = self.send(ctx, msg.as_ref(), record) {
-                    return SendResult::Stop;
-                }
-            }
-        }
-        SendResult::Continue
+    fn send_close(&mut self, ctx: &mut Self::Context, code: CloseCode) {
+        ctx.write(self.ws.text(format!("c[{},{:?}]", code.num(), code.reason())));
     }
 
-    fn init_transport(
-        &mut self,
-        session: String,
-        ctx: &mut ws::WebsocketContext<Self, Addr<Syn, SM>>,
-    ) {
-        // acquire session
-        let addr: Addr<Syn, _> = ctx.address();
-        ctx.state().send(Acquire::new(session, addr.recipient()))
-            .into_actor(self)
-            .map(|res, act, ctx| {
-                match res {
-                    Ok(mut rec) => {
-                        // copy messages into buffer
-                        trace!("STATE: {:?}", rec.0.state);
-
-                        match rec.0.state {
-                            SessionState::Running => {
-                                if let SendResult::Stop = act.send_buffered(ctx, &mut rec.0) {
-                                    // release immidietly
-                                    act.flags().insert(Flags::RELEASE);
-                                }
-                                *act.session_record() = Some(rec.0);
-                                ctx.add_message_stream(rec.1);
-                            },
-                            SessionState::New => {
-                                rec.0.state = SessionState::Running;
-                                if let SendResult::Stop = act.send(ctx, &Frame::Open, &mut rec.0)
-                                {
-                                    // release is send stops
-                                    act.flags().insert(Flags::RELEASE);
-                                } else if let SendResult::Stop =
-                                    act.send_buffered(ctx, &mut rec.0) // send buffered messages
-                                {
-                                    // release immidietly
-                                    act.flags().insert(Flags::RELEASE);
-                                }
-                                *act.session_record() = Some(rec.0);
-                                ctx.add_message_stream(rec.1);
-                            },
-
-                            SessionState::Interrupted => {
-                                act.send(ctx, &Frame::Close(CloseCode::Interrupted), &mut rec.0);
-                                ctx.state().do_send(Release{ses: rec.0});
-                            },
+    fn send_heartbeat(&mut self, ctx: &mut Self::Context) {
+        ctx.write(self.ws.text("h"));
+    }
 
-                            SessionState::Closed => {
-                                act.send(ctx, &Frame::Close(CloseCode::GoAway), &mut rec.0);
-                                ctx.state().do_send(Release{ses: rec.0});
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        act.send_close(ctx, err.into());
-                        ctx.stop();
-                    }
-                }
-            })
-        // session manager is dead?
-            .map_err(|_, act, ctx| {
-                act.send_close(ctx, CloseCode::InternalError);
-            })
-            .wait(ctx);
+    fn transport_state(&mut self) -> &mut TransportState {
+        &mut self.state
     }
 }
 
@@ -227,10 +140,10 @@ where
     S: Session,
     SM: SessionManager<S>,
 {
-    type Context = ws::WebsocketContext<Self, Addr<Syn, SM>>;
+    type Context = HttpContext<Self, Addr<Syn, SM>>;
 
     fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
-        if let Some(mut rec) = self.rec.take() {
+        if let Some(mut rec) = self.state.rec.take() {
             rec.close();
             ctx.state().do_send(Release { ses: rec });
         }
@@ -258,83 +171,72 @@ where
     type Result = ();
 
     fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
-        if let Some(mut rec) = self.rec.take() {
-            self.send(ctx, &msg.msg, &mut rec);
-            self.rec = Some(rec);
+        if let Some(mut rec) = self.state.rec.take() {
+            self.send_broadcast(ctx, &msg, &mut rec);
+            self.state.rec = Some(rec);
         }
     }
 }
 
-impl<S, SM> StreamHandler<ws::Message, ws::ProtocolError> for Websocket<S, SM>
+impl<S, SM> StreamHandler<WsMessage, WsError> for Websocket<S, SM>
 where
     S: Session,
     SM: SessionManager<S>,
 {
-    fn error(&mut self, _: ws::ProtocolError, ctx: &mut Self::Context) -> Running {
-        if let Some(mut rec) = self.rec.take() {
+    fn error(&mut self, err: WsError, ctx: &mut Self::Context) -> Running {
+        if let Some((code, reason)) = err.close() {
+            ctx.write(self.ws.close(code, reason));
+        }
+        if let Some(mut rec) = self.state.rec.take() {
             rec.interrupted();
             ctx.state().do_send(Release { ses: rec });
         }
         Running::Stop
     }
 
-    fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
+    fn handle(&mut self, msg: WsMessage, ctx: &mut Self::Context) {
         // process websocket messages
         match msg {
-            ws::Message::Ping(msg) => ctx.pong(&msg),
-            ws::Message::Text(text) => {
-                if text.is_empty() {
-                    return;
-                }
-                let msg: String = if text.starts_with('[') {
-                    if text.len() <= 2 {
-                        return;
-                    }
-                    match serde_json::from_slice(text[1..text.len() - 1].as_ref()) {
-                        Ok(msgs) => msgs,
-                        Err(_) => {
-                            ctx.close(Some(ws::CloseReason {
-                                code: ws::CloseCode::Invalid,
-                                description: Some("Broken JSON encoding".to_owned()),
-                            }));
-                            if let Some(mut rec) = self.rec.take() {
-                                rec.interrupted();
-                                ctx.state().do_send(Release { ses: rec });
-                            }
-                            ctx.stop();
-                            return;
-                        }
-                    }
-                } else {
-                    match serde_json::from_slice(text[..].as_ref()) {
-                        Ok(msgs) => msgs,
-                        Err(_) => {
-                            ctx.close(Some(ws::CloseReason {
-                                code: ws::CloseCode::Invalid,
-                                description: Some("Broken JSON encoding".to_owned()),
-                            }));
-                            if let Some(mut rec) = self.rec.take() {
-                                rec.interrupted();
-                                ctx.state().do_send(Release { ses: rec });
-                            }
-                            ctx.stop();
-                            return;
+            WsMessage::Ping(msg) => ctx.write(self.ws.pong(&msg)),
+            WsMessage::Text(text) => {
+                let msgs = match decode::websocket(&text) {
+                    Ok(msgs) => msgs,
+                    Err(_) => {
+                        ctx.write(self.ws.close(CLOSE_INVALID, "Broken JSON encoding"));
+                        if let Some(mut rec) = self.state.rec.take() {
+                            rec.interrupted();
+                            ctx.state().do_send(Release { ses: rec });
                         }
+                        ctx.stop();
+                        return;
                     }
                 };
 
-                if let Some(ref rec) = self.rec {
-                    ctx.state().do_send(SessionMessage {
-                        sid: Arc::clone(&rec.sid),
-                        msg: Message(msg),
-                    });
+                if msgs.iter().any(|msg| msg.len() > self.max_message_size) {
+                    ctx.write(self.ws.close(CLOSE_SIZE, "Message is too large"));
+                    if let Some(mut rec) = self.state.rec.take() {
+                        rec.interrupted();
+                        ctx.state().do_send(Release { ses: rec });
+                    }
+                    ctx.stop();
+                    return;
+                }
+
+                if let Some(ref rec) = self.state.rec {
+                    for msg in msgs {
+                        ctx.state().do_send(SessionMessage {
+                            sid: Arc::clone(&rec.sid),
+                            msg: Message(msg),
+                        });
+                    }
                 }
             }
-            ws::Message::Binary(_) => {
+            WsMessage::Binary(_) => {
                 error!("Binary messages are not supported");
             }
-            ws::Message::Close(_) => {
-                if let Some(mut rec) = self.rec.take() {
+            WsMessage::Close(code) => {
+                ctx.write(self.ws.close_reply(code));
+                if let Some(mut rec) = self.state.rec.take() {
                     rec.close();
                     ctx.state().do_send(Release { ses: rec });
                 }
@@ -0,0 +1,365 @@
+//! Server side websocket framing with permessage-deflate support
+use actix_web::error::PayloadError;
+use actix_web::HttpRequest;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{Async, Poll, Stream};
+
+use super::deflate::{DeflateParams, Deflater, InflateError, Inflater};
+
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_PROTOCOL: u16 = 1002;
+pub const CLOSE_INVALID: u16 = 1007;
+pub const CLOSE_SIZE: u16 = 1009;
+
+/// Max payload of control frame
+const MAX_CONTROL_SIZE: usize = 125;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Inbound websocket message
+#[derive(Debug)]
+pub enum WsMessage {
+    Text(String),
+    Binary(Bytes),
+    Ping(Bytes),
+    Pong,
+    /// Close frame with optional status code
+    Close(Option<u16>),
+}
+
+/// Websocket protocol error
+#[derive(Debug)]
+pub enum WsError {
+    /// Client frame is not masked
+    Unmasked,
+    /// Reserved bits, unknown opcode or malformed control frame
+    BadFrame,
+    /// Continuation frame without started message or
+    /// new message before previous one is finished
+    BadContinuation,
+    /// Text message is not utf-8
+    BadEncoding,
+    /// Compressed message can not be decompressed
+    BadDeflate,
+    /// Frame or message is bigger than max size
+    Overflow,
+    /// Payload error
+    Payload(PayloadError),
+}
+
+impl WsError {
+    /// Status code and reason of close frame sent to the peer,
+    /// `None` if connection is broken
+    pub fn close(&self) -> Option<(u16, &'static str)> {
+        match *self {
+            WsError::Payload(_) => None,
+            WsError::Overflow => Some((CLOSE_SIZE, "Message is too large")),
+            WsError::BadEncoding => Some((CLOSE_INVALID, "Invalid utf-8 text")),
+            WsError::BadDeflate => Some((CLOSE_INVALID, "Invalid compressed data")),
+            _ => Some((CLOSE_PROTOCOL, "Protocol error")),
+        }
+    }
+}
+
+impl From<InflateError> for WsError {
+    fn from(err: InflateError) -> WsError {
+        match err {
+            InflateError::Overflow => WsError::Overflow,
+            InflateError::Corrupt => WsError::BadDeflate,
+        }
+    }
+}
+
+/// Stream of inbound messages of websocket connection.
+///
+/// Same reader serves connections with and without permessage-deflate,
+/// so fragmented messages are accepted by both.
+pub fn reader<S: 'static>(req: HttpRequest<S>, max_size: usize, deflate: Option<&DeflateParams>)
+                          -> WsReader<HttpRequest<S>>
+{
+    WsReader::new(req, max_size, deflate)
+}
+
+/// Websocket frame writer, compresses data messages if
+/// permessage-deflate is negotiated
+pub struct WsWriter {
+    deflater: Option<Deflater>,
+}
+
+impl WsWriter {
+    pub fn new(deflate: Option<&DeflateParams>) -> WsWriter {
+        WsWriter {
+            deflater: deflate.map(|params| params.deflater()),
+        }
+    }
+
+    /// Messages get compressed, frames are specific to the connection
+    pub fn compressed(&self) -> bool {
+        self.deflater.is_some()
+    }
+
+    pub fn text<T: AsRef<[u8]>>(&mut self, data: T) -> Bytes {
+        self.message(OP_TEXT, data.as_ref())
+    }
+
+    pub fn binary<T: AsRef<[u8]>>(&mut self, data: T) -> Bytes {
+        self.message(OP_BINARY, data.as_ref())
+    }
+
+    pub fn ping(&mut self, data: &[u8]) -> Bytes {
+        frame(OP_PING, false, data)
+    }
+
+    pub fn pong(&mut self, data: &[u8]) -> Bytes {
+        frame(OP_PONG, false, data)
+    }
+
+    pub fn close(&mut self, code: u16, reason: &str) -> Bytes {
+        let mut payload = Vec::with_capacity(reason.len() + 2);
+        payload.push((code >> 8) as u8);
+        payload.push(code as u8);
+        payload.extend_from_slice(reason.as_bytes());
+        frame(OP_CLOSE, false, &payload)
+    }
+
+    /// Reply to peer's close frame, status code is echoed
+    pub fn close_reply(&mut self, code: Option<u16>) -> Bytes {
+        match code {
+            Some(code) => self.close(code, ""),
+            None => frame(OP_CLOSE, false, b""),
+        }
+    }
+
+    fn message(&mut self, opcode: u8, data: &[u8]) -> Bytes {
+        if let Some(ref mut deflater) = self.deflater {
+            if let Some(compressed) = deflater.deflate(data) {
+                return frame(opcode, true, &compressed);
+            }
+        }
+        frame(opcode, false, data)
+    }
+}
+
+/// Uncompressed text frame, can be shared between connections
+pub fn text_frame(data: &[u8]) -> Bytes {
+    frame(OP_TEXT, false, data)
+}
+
+fn frame(opcode: u8, compressed: bool, payload: &[u8]) -> Bytes {
+    let len = payload.len();
+    let rsv1 = if compressed { 0x40 } else { 0 };
+    let mut buf = BytesMut::with_capacity(len + 10);
+    buf.put_u8(0x80 | rsv1 | opcode);
+    if len < 126 {
+        buf.put_u8(len as u8);
+    } else if len <= 65_535 {
+        buf.put_u8(126);
+        buf.put_slice(&[(len >> 8) as u8, len as u8]);
+    } else {
+        buf.put_u8(127);
+        for i in (0..8).rev() {
+            buf.put_u8(((len as u64) >> (i * 8)) as u8);
+        }
+    }
+    buf.put_slice(payload);
+    buf.freeze()
+}
+
+/// Fragmented message, opcode and compression of the first frame
+struct Partial {
+    opcode: u8,
+    compressed: bool,
+    payload: BytesMut,
+}
+
+/// Stream of inbound websocket messages
+pub struct WsReader<S> {
+    stream: S,
+    buf: BytesMut,
+    max_size: usize,
+    inflater: Option<Inflater>,
+    partial: Option<Partial>,
+}
+
+impl<S> WsReader<S>
+where
+    S: Stream<Item = Bytes, Error = PayloadError>,
+{
+    pub fn new(stream: S, max_size: usize, deflate: Option<&DeflateParams>) -> WsReader<S> {
+        WsReader {
+            stream,
+            max_size,
+            buf: BytesMut::new(),
+            inflater: deflate.map(|params| params.inflater()),
+            partial: None,
+        }
+    }
+
+    /// Parse next message, control frames may be interleaved
+    /// with frames of fragmented message
+    fn parse(&mut self) -> Result<Option<WsMessage>, WsError> {
+        loop {
+            let (first, payload) = match self.parse_frame()? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            let finished = first & 0x80 != 0;
+            let compressed = first & 0x40 != 0;
+            let opcode = first & 0x0f;
+
+            // control frames are never fragmented nor compressed
+            if opcode & 0x08 != 0 {
+                if !finished || compressed || payload.len() > MAX_CONTROL_SIZE {
+                    return Err(WsError::BadFrame);
+                }
+                return control(opcode, payload).map(Some);
+            }
+
+            let msg = match opcode {
+                OP_CONTINUATION => {
+                    let mut partial = match self.partial.take() {
+                        Some(partial) => partial,
+                        None => return Err(WsError::BadContinuation),
+                    };
+                    if compressed {
+                        return Err(WsError::BadFrame);
+                    }
+                    if partial.payload.len() + payload.len() > self.max_size {
+                        return Err(WsError::Overflow);
+                    }
+                    partial.payload.extend_from_slice(&payload);
+                    partial
+                }
+                OP_TEXT | OP_BINARY => {
+                    if self.partial.is_some() {
+                        return Err(WsError::BadContinuation);
+                    }
+                    Partial {opcode, compressed, payload}
+                }
+                _ => return Err(WsError::BadFrame),
+            };
+
+            if finished {
+                return self.message(msg).map(Some);
+            }
+            self.partial = Some(msg);
+        }
+    }
+
+    /// Decode complete data message
+    fn message(&mut self, msg: Partial) -> Result<WsMessage, WsError> {
+        let payload = if msg.compressed {
+            match self.inflater.as_mut() {
+                Some(inflater) => Bytes::from(inflater.inflate(&msg.payload, self.max_size)?),
+                None => return Err(WsError::BadFrame),
+            }
+        } else {
+            msg.payload.freeze()
+        };
+
+        if msg.opcode == OP_TEXT {
+            match String::from_utf8(payload.to_vec()) {
+                Ok(text) => Ok(WsMessage::Text(text)),
+                Err(_) => Err(WsError::BadEncoding),
+            }
+        } else {
+            Ok(WsMessage::Binary(payload))
+        }
+    }
+
+    /// Parse next frame, returns first byte of the frame and unmasked payload
+    fn parse_frame(&mut self) -> Result<Option<(u8, BytesMut)>, WsError> {
+        if self.buf.len() < 2 {
+            return Ok(None);
+        }
+        let first = self.buf[0];
+        let second = self.buf[1];
+
+        let (len, idx) = match second & 0x7f {
+            126 => {
+                if self.buf.len() < 4 {
+                    return Ok(None);
+                }
+                ((self.buf[2] as u64) << 8 | self.buf[3] as u64, 4)
+            }
+            127 => {
+                if self.buf.len() < 10 {
+                    return Ok(None);
+                }
+                let len = self.buf[2..10]
+                    .iter()
+                    .fold(0u64, |len, b| len << 8 | *b as u64);
+                (len, 10)
+            }
+            len => (len as u64, 2),
+        };
+        if second & 0x80 == 0 {
+            return Err(WsError::Unmasked);
+        }
+        if len > self.max_size as u64 {
+            return Err(WsError::Overflow);
+        }
+        let len = len as usize;
+        if self.buf.len() < idx + 4 + len {
+            return Ok(None);
+        }
+
+        let mut payload = self.buf.split_to(idx + 4 + len);
+        let mask = payload.split_to(idx + 4);
+        let mask = &mask[idx..];
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+
+        if first & 0x30 != 0 {
+            return Err(WsError::BadFrame);
+        }
+        Ok(Some((first, payload)))
+    }
+}
+
+/// Decode control frame
+fn control(opcode: u8, payload: BytesMut) -> Result<WsMessage, WsError> {
+    match opcode {
+        OP_PING => Ok(WsMessage::Ping(payload.freeze())),
+        OP_PONG => Ok(WsMessage::Pong),
+        OP_CLOSE => match payload.len() {
+            0 => Ok(WsMessage::Close(None)),
+            1 => Err(WsError::BadFrame),
+            _ => {
+                if ::std::str::from_utf8(&payload[2..]).is_err() {
+                    return Err(WsError::BadEncoding);
+                }
+                Ok(WsMessage::Close(Some(u16::from(payload[0]) << 8 | u16::from(payload[1]))))
+            }
+        },
+        _ => Err(WsError::BadFrame),
+    }
+}
+
+impl<S> Stream for WsReader<S>
+where
+    S: Stream<Item = Bytes, Error = PayloadError>,
+{
+    type Item = WsMessage;
+    type Error = WsError;
+
+    fn poll(&mut self) -> Poll<Option<WsMessage>, WsError> {
+        loop {
+            if let Some(msg) = self.parse()? {
+                return Ok(Async::Ready(Some(msg)));
+            }
+            match self.stream.poll() {
+                Ok(Async::Ready(Some(chunk))) => self.buf.extend_from_slice(&chunk),
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(err) => return Err(WsError::Payload(err)),
+            }
+        }
+    }
+}
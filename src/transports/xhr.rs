@@ -3,25 +3,28 @@ use std::marker::PhantomData;
 use actix::*;
 use actix_web::*;
 use actix_web::http::Method;
-use serde_json;
+use bytes::Bytes;
 use http::header::{self, ACCESS_CONTROL_ALLOW_METHODS};
 
 use context::ChannelItem;
-use protocol::{Frame, CloseCode};
+use protocol::{encode, Frame, CloseCode};
 use utils::SockjsHeaders;
 use session::Session;
-use manager::{Broadcast, Record, SessionManager};
+use manager::{Broadcast, Format, Record, SessionManager};
 
-use super::{Transport, SendResult, Flags};
+use super::{Transport, TransportMode, SendResult, TransportState};
 
+/// Encode message frame
+fn message(msg: &str) -> Bytes {
+    Bytes::from(format!("{}\n", encode::message(msg)))
+}
 
 pub struct Xhr<S, SM>
     where S: Session, SM: SessionManager<S>,
 {
     s: PhantomData<S>,
     sm: PhantomData<SM>,
-    rec: Option<Record>,
-    flags: Flags,
+    state: TransportState,
 }
 
 // Http actor implementation
@@ -40,6 +43,9 @@ impl<S, SM> Actor for Xhr<S, SM>
 impl<S, SM> Transport<S, SM> for Xhr<S, SM>
     where S: Session, SM: SessionManager<S>,
 {
+    const NAME: &'static str = "xhr";
+    const MODE: TransportMode = TransportMode::Polling;
+
     fn send(&mut self, ctx: &mut Self::Context, msg: &Frame, record: &mut Record) -> SendResult
     {
         match *msg {
@@ -47,9 +53,7 @@ impl<S, SM> Transport<S, SM> for Xhr<S, SM>
                 ctx.write("h\n");
             },
             Frame::Message(ref s) => {
-                ctx.write("a[");
-                ctx.write(serde_json::to_string(s).unwrap());
-                ctx.write("]\n");
+                ctx.write(message(s));
             }
             Frame::MessageVec(ref s) => {
                 ctx.write("a");
@@ -73,6 +77,18 @@ impl<S, SM> Transport<S, SM> for Xhr<S, SM>
         SendResult::Stop
     }
 
+    fn write_broadcast(&mut self, ctx: &mut Self::Context, msg: &Broadcast)
+                       -> Option<SendResult>
+    {
+        if let Some(data) = msg.encoded(Format::Xhr, message) {
+            ctx.write(data);
+            ctx.write_eof();
+            Some(SendResult::Stop)
+        } else {
+            None
+        }
+    }
+
     fn send_heartbeat(&mut self, ctx: &mut Self::Context) {
         ctx.write("h\n");
         ctx.write_eof();
@@ -83,12 +99,8 @@ impl<S, SM> Transport<S, SM> for Xhr<S, SM>
         ctx.write_eof();
     }
 
-    fn session_record(&mut self) -> &mut Option<Record> {
-        &mut self.rec
-    }
-
-    fn flags(&mut self) -> &mut Flags {
-        &mut self.flags
+    fn transport_state(&mut self) -> &mut TransportState {
+        &mut self.state
     }
 }
 
@@ -125,8 +137,7 @@ impl<S, SM> Xhr<S, SM>
         // init transport
         let mut transport = Xhr{s: PhantomData,
                                 sm: PhantomData,
-                                rec: None,
-                                flags: Flags::empty()};
+                                state: TransportState::new()};
         transport.init_transport(session, &mut ctx);
 
         Ok(resp.body(ctx.actor(transport)))
@@ -5,17 +5,17 @@ use actix_web::http::Method;
 use actix_web::*;
 use futures::future::{ok, Either, Future};
 use http::header::ACCESS_CONTROL_ALLOW_METHODS;
-use serde_json;
 
 use manager::{SessionManager, SessionMessage};
+use protocol::decode;
 use session::{Message, Session};
-use utils::SockjsHeaders;
-
-use super::MAXSIZE;
+use utils::{payload_error, SockjsHeaders};
 
 #[allow(non_snake_case)]
 pub fn XhrSend<S, SM>(
     req: HttpRequest<Addr<Syn, SM>>,
+    max_body_size: usize,
+    max_message_size: usize,
 ) -> Either<HttpResponse, Box<Future<Item = HttpResponse, Error = Error>>>
 where
     S: Session,
@@ -38,12 +38,14 @@ where
                 .finish(),
         )
     } else {
-        Either::B(read(req))
+        Either::B(read(req, max_body_size, max_message_size))
     }
 }
 
 pub fn read<S, SM>(
     req: HttpRequest<Addr<Syn, SM>>,
+    max_body_size: usize,
+    max_message_size: usize,
 ) -> Box<Future<Item = HttpResponse, Error = Error>>
 where
     S: Session,
@@ -54,54 +56,52 @@ where
     Box::new(
         req.clone()
             .body()
-            .limit(MAXSIZE)
-            .map_err(error::ErrorBadRequest)
+            .limit(max_body_size)
+            .map_err(payload_error)
             .and_then(move |buf| {
                 let sid = Arc::new(sid);
 
-                // empty message
-                if buf.is_empty() {
+                let mut msgs = match decode::xhr_send(&buf) {
+                    Ok(msgs) => msgs,
+                    Err(err) => {
+                        return Either::A(ok(
+                            HttpResponse::InternalServerError().body(err.reason())
+                        ))
+                    }
+                };
+
+                if msgs.iter().any(|msg| msg.len() > max_message_size) {
                     return Either::A(ok(
-                        HttpResponse::InternalServerError().body("Payload expected.")
+                        HttpResponse::PayloadTooLarge().body("Message is too large.")
                     ));
-                } else if buf != b"[]".as_ref() {
-                    // deserialize json
-                    let mut msgs: Vec<String> = match serde_json::from_slice(&buf) {
-                        Ok(msgs) => msgs,
-                        Err(_) => {
-                            return Either::A(ok(
-                                HttpResponse::InternalServerError().body("Broken JSON encoding.")
-                            ))
-                        }
-                    };
-
-                    if !msgs.is_empty() {
-                        let last = msgs.pop().unwrap();
-                        for msg in msgs {
-                            req.state().do_send(SessionMessage {
-                                sid: Arc::clone(&sid),
-                                msg: Message(msg),
-                            });
-                        }
+                }
 
-                        return Either::B(
-                            req.state()
-                                .send(SessionMessage {
-                                    sid: Arc::clone(&sid),
-                                    msg: Message(last),
-                                })
-                                .from_err()
-                                .and_then(move |res| match res {
-                                    Ok(_) => Ok(HttpResponse::NoContent()
-                                        .content_type("text/plain; charset=UTF-8")
-                                        .sockjs_no_cache()
-                                        .sockjs_cors_headers(req.headers())
-                                        .sockjs_session_cookie(&req)
-                                        .finish()),
-                                    Err(_) => Err(error::ErrorNotFound("not found")),
-                                }),
-                        );
+                if !msgs.is_empty() {
+                    let last = msgs.pop().unwrap();
+                    for msg in msgs {
+                        req.state().do_send(SessionMessage {
+                            sid: Arc::clone(&sid),
+                            msg: Message(msg),
+                        });
                     }
+
+                    return Either::B(
+                        req.state()
+                            .send(SessionMessage {
+                                sid: Arc::clone(&sid),
+                                msg: Message(last),
+                            })
+                            .from_err()
+                            .and_then(move |res| match res {
+                                Ok(_) => Ok(HttpResponse::NoContent()
+                                    .content_type("text/plain; charset=UTF-8")
+                                    .sockjs_no_cache()
+                                    .sockjs_cors_headers(req.headers())
+                                    .sockjs_session_cookie(&req)
+                                    .finish()),
+                                Err(_) => Err(error::ErrorNotFound("not found")),
+                            }),
+                    );
                 }
 
                 Either::A(ok(HttpResponse::NoContent()
@@ -5,14 +5,16 @@ use actix::*;
 use actix_web::*;
 use actix_web::http::Method;
 use actix_web::http::header::ACCESS_CONTROL_ALLOW_METHODS;
+use bytes::Bytes;
 
 use context::ChannelItem;
-use protocol::{Frame, CloseCode};
+use protocol::{encode, Frame, CloseCode};
 use utils::SockjsHeaders;
 use session::Session;
-use manager::{Broadcast, Record, SessionManager};
+use manager::{Broadcast, Format, Record, SessionManager};
 
-use super::{Transport, SendResult, Flags};
+use super::encoding::BodyWriter;
+use super::{Transport, TransportMode, SendResult, TransportState};
 
 
 const OPEN_SEQ: &str =
@@ -47,6 +49,11 @@ const OPEN_SEQ: &str =
      hhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhh\
      hhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhh\n";
 
+/// Encode message frame
+fn message(msg: &str) -> Bytes {
+    Bytes::from(format!("{}\n", encode::message(msg)))
+}
+
 pub struct XhrStreaming<S, SM>
     where S: Session, SM: SessionManager<S>,
 {
@@ -54,13 +61,15 @@ pub struct XhrStreaming<S, SM>
     sm: PhantomData<SM>,
     size: usize,
     maxsize: usize,
-    flags: Flags,
-    rec: Option<Record>,
+    state: TransportState,
+    body: BodyWriter,
 }
 
 impl<S, SM> XhrStreaming<S, SM> where S: Session, SM: SessionManager<S> {
 
-    pub fn init(req: HttpRequest<Addr<Syn, SM>>, maxsize: usize) -> Result<HttpResponse> {
+    pub fn init(req: HttpRequest<Addr<Syn, SM>>, maxsize: usize, compress: bool)
+                -> Result<HttpResponse>
+    {
         if *req.method() == Method::OPTIONS {
             return Ok(
                 HttpResponse::NoContent()
@@ -82,15 +91,11 @@ impl<S, SM> XhrStreaming<S, SM> where S: Session, SM: SessionManager<S> {
             .sockjs_session_cookie(&req)
             .sockjs_cors_headers(req.headers())
             .take();
+        let mut body = BodyWriter::new(&req, &mut resp, compress);
 
-        let mut ctx = HttpContext::new(
-            req, XhrStreaming{maxsize,
-                              s: PhantomData,
-                              sm: PhantomData,
-                              size: 0,
-                              flags: Flags::empty(),
-                              rec: None});
-        ctx.write(OPEN_SEQ);
+        let mut ctx = HttpContext::from_request(req);
+        body.write(&mut ctx, OPEN_SEQ);
+        body.flush(&mut ctx);
 
         // init transport, but aftre prelude only
         ctx.drain().map(move |_, _, ctx| {
@@ -99,7 +104,23 @@ impl<S, SM> XhrStreaming<S, SM> where S: Session, SM: SessionManager<S> {
             });
         }).wait(&mut ctx);
 
-        Ok(resp.body(ctx))
+        Ok(resp.body(ctx.actor(XhrStreaming{maxsize, body,
+                                             s: PhantomData,
+                                             sm: PhantomData,
+                                             size: 0,
+                                             state: TransportState::new()})))
+    }
+
+    /// Flush written frame, stops transport if response is too big
+    fn flush(&mut self, ctx: &mut HttpContext<Self, Addr<Syn, SM>>) -> SendResult {
+        self.size += self.body.flush(ctx);
+
+        if self.size > self.maxsize {
+            self.body.write_eof(ctx);
+            SendResult::Stop
+        } else {
+            SendResult::Continue
+        }
     }
 }
 
@@ -119,65 +140,62 @@ impl<S, SM> Actor for XhrStreaming<S, SM>
 impl<S, SM> Transport<S, SM> for XhrStreaming<S, SM>
     where S: Session, SM: SessionManager<S>,
 {
+    const NAME: &'static str = "xhr_streaming";
+    const MODE: TransportMode = TransportMode::Streaming;
+
     fn send(&mut self,
             ctx: &mut Self::Context,
             msg: &Frame, record: &mut Record) -> SendResult
     {
-        self.size += match *msg {
+        match *msg {
             Frame::Heartbeat => {
-                ctx.write("h\n");
-                2
+                self.body.write(ctx, "h\n");
             },
             Frame::Message(ref s) => {
-                let s = format!("a[{:?}]\n", s);
-                let size = s.len();
-                ctx.write(s);
-                size
+                self.body.write(ctx, message(s));
             }
             Frame::MessageVec(ref s) => {
-                let s = format!("a{}\n", s);
-                let size = s.len();
-                ctx.write(s);
-                size
+                self.body.write(ctx, format!("a{}\n", s));
             }
             Frame::MessageBlob(_) => {
                 // ctx.write(format!("a{}\n", s));
-                0
             }
             Frame::Open => {
-                ctx.write("o\n");
-                2
+                self.body.write(ctx, "o\n");
             },
             Frame::Close(code) => {
                 record.close();
-                ctx.write(format!("c[{},{:?}]\n", code.num(), code.reason()));
-                ctx.write_eof();
+                self.body.write(ctx, format!("c[{},{:?}]\n", code.num(), code.reason()));
+                self.body.write_eof(ctx);
                 return SendResult::Stop;
             }
         };
+        self.flush(ctx)
+    }
 
-        if self.size > self.maxsize {
-            ctx.write_eof();
-            SendResult::Stop
+    fn write_broadcast(&mut self, ctx: &mut Self::Context, msg: &Broadcast)
+                       -> Option<SendResult>
+    {
+        if let Some(data) = msg.encoded(Format::XhrStreaming, message) {
+            self.body.write(ctx, data);
+            Some(self.flush(ctx))
         } else {
-            SendResult::Continue
+            None
         }
     }
 
     fn send_heartbeat(&mut self, ctx: &mut Self::Context) {
-        ctx.write("h\n");
+        self.body.write(ctx, "h\n");
+        self.body.flush(ctx);
     }
 
     fn send_close(&mut self, ctx: &mut Self::Context, code: CloseCode) {
-        ctx.write(format!("c[{},{:?}]\n", code.num(), code.reason()));
-    }
-
-    fn session_record(&mut self) -> &mut Option<Record> {
-        &mut self.rec
+        self.body.write(ctx, format!("c[{},{:?}]\n", code.num(), code.reason()));
+        self.body.flush(ctx);
     }
 
-    fn flags(&mut self) -> &mut Flags {
-        &mut self.flags
+    fn transport_state(&mut self) -> &mut TransportState {
+        &mut self.state
     }
 }
 
@@ -1,7 +1,10 @@
 use time;
-use actix_web::HttpRequest;
+use actix::{Message, Recipient, Syn};
+use actix_web::{error, Error, HttpRequest};
+use actix_web::error::PayloadError;
 use actix_web::http::Cookie;
 use actix_web::dev::HttpResponseBuilder;
+use http::StatusCode;
 use http::header::HeaderMap;
 use http::header::{EXPIRES, ORIGIN, CACHE_CONTROL,
                    ACCESS_CONTROL_ALLOW_ORIGIN,
@@ -100,3 +103,20 @@ impl SockjsHeaders for HttpResponseBuilder {
         self
     }
 }
+
+
+/// Send message to all recipients, drop disconnected recipients
+pub(crate) fn notify<M>(recipients: &mut Vec<Recipient<Syn, M>>, msg: M)
+    where M: Message + Clone + Send + 'static, M::Result: Send,
+{
+    recipients.retain(|r| r.do_send(msg.clone()).is_ok());
+}
+
+/// Error response of request body, `413 Payload Too Large` if body is over the limit
+pub(crate) fn payload_error(err: PayloadError) -> Error {
+    match err {
+        PayloadError::Overflow =>
+            error::InternalError::new(err, StatusCode::PAYLOAD_TOO_LARGE).into(),
+        err => error::ErrorBadRequest(err),
+    }
+}
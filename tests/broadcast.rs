@@ -0,0 +1,276 @@
+//! Broadcast messages encoded once per wire format
+//!
+//! Broadcast message is written from the cache shared by all recipients,
+//! bytes on the wire must be identical to the message sent to one session.
+extern crate actix;
+extern crate actix_web;
+extern crate bytes;
+extern crate futures;
+extern crate rand;
+extern crate serde_json;
+extern crate sockjs;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use actix::prelude::*;
+use actix_web::client::ClientResponse;
+use actix_web::http::{Method, StatusCode};
+use actix_web::test::TestServer;
+use actix_web::{App, HttpMessage};
+use bytes::BytesMut;
+use futures::Stream;
+
+use sockjs::test::TestManager;
+use sockjs::{Broadcast, Frame, Message, Session, SockJS, SockJSContext, SockJSManager};
+
+/// Message with characters that need escaping
+const MSG: &str = "x \"q\" \u{e9}";
+
+/// Session echoes messages, `!`-prefixed messages get broadcasted
+#[derive(Default)]
+struct Shout;
+
+impl Actor for Shout {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Shout {}
+
+impl Handler<Message> for Shout {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        if msg.0.starts_with('!') {
+            ctx.broadcast(msg.0[1..].to_owned());
+        } else {
+            ctx.send(msg);
+        }
+    }
+}
+
+fn serve() -> TestServer {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let sys = System::new("sockjs-managers");
+        let shout: Addr<Syn, _> = SockJSManager::<Shout>::start_default();
+        tx.send(shout).unwrap();
+        sys.run();
+    });
+    let shout = rx.recv().unwrap();
+
+    TestServer::with_factory(move || {
+        App::new().handler("/shout", SockJS::new(shout.clone()))
+    })
+}
+
+fn execute(srv: &mut TestServer, method: Method, path: &str, body: Option<String>)
+           -> ClientResponse
+{
+    let req = match body {
+        Some(body) => srv.client(method, path).body(body).unwrap(),
+        None => srv.client(method, path).finish().unwrap(),
+    };
+    srv.execute(req.send()).unwrap()
+}
+
+/// Websocket connection over plain tcp, frames are read as is
+struct RawWs {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl RawWs {
+    fn new(srv: &TestServer, path: &str) -> RawWs {
+        let mut stream = TcpStream::connect(srv.addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let req = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                           Connection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+                           Sec-WebSocket-Key: x3JJHMbDL1EzLkh9GBhXDw==\r\n\r\n", path);
+        stream.write_all(req.as_bytes()).unwrap();
+        let mut ws = RawWs {stream, buf: Vec::new()};
+        while ws.buf.windows(4).position(|w| w == b"\r\n\r\n").is_none() {
+            ws.fill();
+        }
+        let pos = ws.buf.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head: Vec<_> = ws.buf.drain(..pos + 4).collect();
+        assert!(head.starts_with(b"HTTP/1.1 101"));
+        ws
+    }
+
+    fn fill(&mut self) {
+        let mut chunk = [0u8; 4096];
+        match self.stream.read(&mut chunk) {
+            Ok(0) | Err(_) => panic!("Unexpected end of connection"),
+            Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+
+    /// Write masked text frame
+    fn text(&mut self, text: &str) {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        assert!(text.len() < 126);
+        let mut frame = vec![0x81, 0x80 | text.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(text.bytes().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        self.stream.write_all(&frame).unwrap();
+    }
+
+    /// Next frame with its header, short frames only
+    fn frame(&mut self) -> Vec<u8> {
+        while self.buf.len() < 2 || self.buf.len() < 2 + (self.buf[1] & 0x7f) as usize {
+            self.fill();
+        }
+        assert!(self.buf[1] & 0x7f < 126);
+        let size = 2 + (self.buf[1] & 0x7f) as usize;
+        self.buf.drain(..size).collect()
+    }
+}
+
+/// Transport connection of the session
+enum Conn {
+    /// Polling transport, every frame is read with new request
+    Polling(Method, String),
+    /// Streaming response and frame terminator
+    Streaming(Option<ClientResponse>, BytesMut, &'static str),
+    /// Websocket connection, raw websocket messages are not sockjs frames
+    Websocket(RawWs, bool),
+}
+
+impl Conn {
+    /// Connect transport, open frame is skipped
+    fn open(srv: &mut TestServer, url: &str, transport: &str) -> Conn {
+        let (method, path, open, end) = match transport {
+            "xhr" | "jsonp" => {
+                let mut conn = if transport == "xhr" {
+                    Conn::Polling(Method::POST, format!("{}/xhr", url))
+                } else {
+                    Conn::Polling(Method::GET, format!("{}/jsonp?c=callback", url))
+                };
+                conn.frame(srv);
+                return conn
+            }
+            "websocket" => {
+                let mut ws = RawWs::new(srv, &format!("{}/websocket", url));
+                assert_eq!(ws.frame(), b"\x81\x01o");
+                return Conn::Websocket(ws, false)
+            }
+            "raw_websocket" => {
+                return Conn::Websocket(RawWs::new(srv, "/shout/websocket"), true)
+            }
+            "xhr_streaming" => (Method::POST, "xhr_streaming", "o\n", "\n"),
+            "eventsource" => (Method::GET, "eventsource", "data: o\r\n\r\n", "\r\n\r\n"),
+            "htmlfile" => (Method::GET, "htmlfile?c=callback",
+                           "<script>\np(\"o\");\n</script>\r\n", "</script>\r\n"),
+            _ => unreachable!(),
+        };
+        let resp = execute(srv, method, &format!("{}/{}", url, path), None);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let mut conn = Conn::Streaming(Some(resp), BytesMut::new(), open);
+        conn.frame(srv);
+        if let Conn::Streaming(_, _, ref mut term) = conn {
+            *term = end;
+        }
+        conn
+    }
+
+    /// Send message to the session
+    fn send(&mut self, srv: &mut TestServer, url: &str, msg: &str) {
+        let body = serde_json::to_string(&[msg]).unwrap();
+        if let Conn::Websocket(ref mut ws, raw) = *self {
+            ws.text(if raw { msg } else { &body[..] });
+            return
+        }
+        let resp = execute(srv, Method::POST, &format!("{}/xhr_send", url), Some(body));
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    }
+
+    /// Next frame as written by transport
+    fn frame(&mut self, srv: &mut TestServer) -> Vec<u8> {
+        match *self {
+            Conn::Polling(ref method, ref path) => {
+                let resp = execute(srv, method.clone(), path, None);
+                assert_eq!(resp.status(), StatusCode::OK);
+                srv.execute(resp.body()).unwrap().to_vec()
+            }
+            Conn::Streaming(ref mut resp, ref mut buf, term) => loop {
+                let found = buf.windows(term.len()).position(|w| w == term.as_bytes());
+                if let Some(pos) = found {
+                    return buf.split_to(pos + term.len()).to_vec()
+                }
+                let (chunk, rest) = srv.execute(resp.take().unwrap().into_future())
+                    .map_err(|_| ()).unwrap();
+                buf.extend_from_slice(&chunk.expect("Unexpected end of response body"));
+                *resp = Some(rest);
+            },
+            Conn::Websocket(ref mut ws, _) => ws.frame(),
+        }
+    }
+}
+
+/// Broadcast message is identical to echoed message for both sessions
+fn verify(transport: &str) {
+    let mut srv = serve();
+    let url1 = format!("/shout/000/{:016x}", rand::random::<u64>());
+    let url2 = format!("/shout/000/{:016x}", rand::random::<u64>());
+    let mut c1 = Conn::open(&mut srv, &url1, transport);
+    let mut c2 = Conn::open(&mut srv, &url2, transport);
+
+    c1.send(&mut srv, &url1, MSG);
+    let echo = c1.frame(&mut srv);
+    assert!(String::from_utf8_lossy(&echo).contains("x"), "{:?}", echo);
+
+    c2.send(&mut srv, &url2, &format!("!{}", MSG));
+    assert_eq!(c1.frame(&mut srv), echo, "{}", transport);
+    assert_eq!(c2.frame(&mut srv), echo, "{}", transport);
+}
+
+#[test]
+fn test_xhr() {
+    verify("xhr");
+}
+
+#[test]
+fn test_xhr_streaming() {
+    verify("xhr_streaming");
+}
+
+#[test]
+fn test_eventsource() {
+    verify("eventsource");
+}
+
+#[test]
+fn test_htmlfile() {
+    verify("htmlfile");
+}
+
+#[test]
+fn test_jsonp() {
+    verify("jsonp");
+}
+
+#[test]
+fn test_websocket() {
+    verify("websocket");
+}
+
+#[test]
+fn test_raw_websocket() {
+    verify("raw_websocket");
+}
+
+// broadcast built with struct literal, `msg` is its only field
+#[test]
+fn test_struct_literal() {
+    let mut srv = TestManager::new(SockJSManager::<Shout>::default());
+    let tr1 = srv.connect("s1");
+    let tr2 = srv.connect("s2");
+    srv.manager().do_send(Broadcast {msg: Arc::new(Frame::Message(MSG.to_owned()))});
+    srv.settle();
+    assert_eq!(tr1.messages(), vec![MSG.to_owned()]);
+    assert_eq!(tr2.messages(), vec![MSG.to_owned()]);
+}
@@ -0,0 +1,231 @@
+//! SockJS client tests, client talks to in-process `SockJS` handlers
+#![cfg(feature = "client")]
+extern crate actix;
+extern crate actix_web;
+extern crate futures;
+extern crate sockjs;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use actix::prelude::*;
+use actix_web::test::TestServer;
+use actix_web::App;
+use futures::stream::{SplitSink, SplitStream};
+use futures::{Future, Sink, Stream};
+
+use sockjs::client::{Client, ClientError, ClientEvent, SockJSClient};
+use sockjs::{Message, Session, SockJS, SockJSContext, SockJSManager, TransportKind};
+
+#[derive(Default)]
+struct Echo;
+
+impl Actor for Echo {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Echo {}
+
+impl Handler<Message> for Echo {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        ctx.send(msg);
+    }
+}
+
+#[derive(Default)]
+struct Close;
+
+impl Actor for Close {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Close {
+    fn opened(&mut self, ctx: &mut SockJSContext<Self>) {
+        ctx.close()
+    }
+}
+
+impl Handler<Message> for Close {
+    type Result = ();
+
+    fn handle(&mut self, _: Message, ctx: &mut SockJSContext<Self>) {
+        ctx.close()
+    }
+}
+
+fn serve() -> TestServer {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let sys = System::new("sockjs-managers");
+        let echo: Addr<Syn, _> = SockJSManager::<Echo>::start_default();
+        let close: Addr<Syn, _> = SockJSManager::<Close>::start_default();
+        tx.send((echo, close)).unwrap();
+        sys.run();
+    });
+    let (echo, close) = rx.recv().unwrap();
+
+    TestServer::with_factory(move || {
+        App::new()
+            .handler("/echo", SockJS::new(echo.clone()))
+            .handler("/close", SockJS::new(close.clone()))
+            .handler("/limited_echo", SockJS::new(echo.clone()).max_message_size(16))
+            .handler(
+                "/disabled_websocket_echo",
+                SockJS::new(echo.clone()).disable(vec![TransportKind::Websocket]))
+    })
+}
+
+type Split = (SplitSink<SockJSClient>, SplitStream<SockJSClient>);
+
+fn next(srv: &mut TestServer, client: Split) -> (Option<ClientEvent>, Split) {
+    let (sink, stream) = client;
+    match srv.execute(stream.into_future()) {
+        Ok((ev, stream)) => (ev, (sink, stream)),
+        Err((err, _)) => panic!("Client error: {:?}", err),
+    }
+}
+
+fn send(srv: &mut TestServer, client: Split, msg: &'static str) -> Split {
+    let (sink, stream) = client;
+    (srv.execute(sink.send(Message::from(msg))).unwrap(), stream)
+}
+
+/// Open session, send message and receive it back
+fn echo(srv: &mut TestServer, client: Client, kind: TransportKind) {
+    let client = client.connect().split();
+
+    let (ev, client) = next(srv, client);
+    assert_eq!(ev, Some(ClientEvent::Open(kind)));
+
+    let client = send(srv, client, "hello");
+    let client = send(srv, client, "world");
+    let (ev, client) = next(srv, client);
+    assert_eq!(ev, Some(ClientEvent::Message("hello".to_owned())));
+    let (ev, _) = next(srv, client);
+    assert_eq!(ev, Some(ClientEvent::Message("world".to_owned())));
+}
+
+#[test]
+fn test_websocket() {
+    let mut srv = serve();
+    let client = Client::new(srv.url("/echo"));
+    echo(&mut srv, client, TransportKind::Websocket);
+}
+
+#[test]
+fn test_xhr_streaming() {
+    let mut srv = serve();
+    let client = Client::new(srv.url("/echo")).transports(vec![TransportKind::XhrStreaming]);
+    echo(&mut srv, client, TransportKind::XhrStreaming);
+}
+
+#[test]
+fn test_xhr_polling() {
+    let mut srv = serve();
+    let client = Client::new(srv.url("/echo")).transports(vec![TransportKind::Xhr]);
+    echo(&mut srv, client, TransportKind::Xhr);
+}
+
+// Websocket transport is disabled, client falls back to xhr-streaming
+#[test]
+fn test_fallback() {
+    let mut srv = serve();
+    let client = Client::new(srv.url("/disabled_websocket_echo"));
+    echo(&mut srv, client, TransportKind::XhrStreaming);
+}
+
+// Session closed by the server ends the stream
+#[test]
+fn test_close() {
+    let mut srv = serve();
+    for &kind in &[TransportKind::Websocket, TransportKind::XhrStreaming, TransportKind::Xhr] {
+        let client = Client::new(srv.url("/close")).transports(vec![kind]).connect().split();
+
+        let (ev, client) = next(&mut srv, client);
+        assert_eq!(ev, Some(ClientEvent::Open(kind)));
+        let (ev, client) = next(&mut srv, client);
+        assert_eq!(ev, Some(ClientEvent::Close(3000, "Go away!".to_owned())));
+        let (ev, _) = next(&mut srv, client);
+        assert_eq!(ev, None);
+    }
+}
+
+#[test]
+fn test_unavailable() {
+    let mut srv = serve();
+    let client = Client::new(srv.url("/missing")).connect();
+    match srv.execute(client.into_future()) {
+        Err((err, _)) => assert_eq!(err, ClientError::Unavailable),
+        Ok((ev, _)) => panic!("Unexpected event: {:?}", ev),
+    }
+}
+
+// Server interrupts session, client opens new session
+#[test]
+fn test_reconnect() {
+    let mut srv = serve();
+    let client = Client::new(srv.url("/limited_echo"))
+        .transports(vec![TransportKind::Websocket])
+        .reconnect(Duration::from_millis(10))
+        .connect()
+        .split();
+
+    let (ev, client) = next(&mut srv, client);
+    assert_eq!(ev, Some(ClientEvent::Open(TransportKind::Websocket)));
+
+    // message is too large, server closes websocket connection
+    let client = send(&mut srv, client, "message is too large");
+    let (ev, client) = next(&mut srv, client);
+    assert_eq!(ev, Some(ClientEvent::Interrupted));
+    let (ev, client) = next(&mut srv, client);
+    assert_eq!(ev, Some(ClientEvent::Open(TransportKind::Websocket)));
+
+    let client = send(&mut srv, client, "hello");
+    let (ev, _) = next(&mut srv, client);
+    assert_eq!(ev, Some(ClientEvent::Message("hello".to_owned())));
+}
+
+// Silent server, no heartbeats within heartbeat timeout, connection is considered lost
+#[test]
+fn test_heartbeat_timeout() {
+    let mut srv = serve();
+    for &kind in &[TransportKind::Websocket, TransportKind::XhrStreaming, TransportKind::Xhr] {
+        let client = Client::new(srv.url("/echo"))
+            .transports(vec![kind])
+            .heartbeat_timeout(Duration::from_millis(200))
+            .connect()
+            .split();
+
+        let (ev, client) = next(&mut srv, client);
+        assert_eq!(ev, Some(ClientEvent::Open(kind)));
+        let (ev, client) = next(&mut srv, client);
+        assert_eq!(ev, Some(ClientEvent::Interrupted));
+        let (ev, _) = next(&mut srv, client);
+        assert_eq!(ev, None);
+    }
+}
+
+// Frame bigger than client frame limit, connection is considered lost
+#[test]
+fn test_max_frame_size() {
+    let mut srv = serve();
+    for &kind in &[TransportKind::Websocket, TransportKind::Xhr] {
+        let client = Client::new(srv.url("/echo"))
+            .transports(vec![kind])
+            .max_frame_size(16)
+            .connect()
+            .split();
+
+        let (ev, client) = next(&mut srv, client);
+        assert_eq!(ev, Some(ClientEvent::Open(kind)));
+
+        let client = send(&mut srv, client, "message does not fit into frame");
+        let (ev, client) = next(&mut srv, client);
+        assert_eq!(ev, Some(ClientEvent::Interrupted));
+        let (ev, _) = next(&mut srv, client);
+        assert_eq!(ev, None);
+    }
+}
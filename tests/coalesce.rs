@@ -0,0 +1,100 @@
+//! Coalescing of outbound messages
+extern crate actix;
+extern crate sockjs;
+
+use std::thread;
+use std::time::Duration;
+
+use actix::prelude::*;
+
+use sockjs::test::TestManager;
+use sockjs::{Frame, Message, Session, SockJSContext, SockJSManager};
+
+/// Session sends `n` messages for `"<n>"` message
+#[derive(Default)]
+struct Burst {
+    count: usize,
+}
+
+impl Actor for Burst {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Burst {}
+
+impl Handler<Message> for Burst {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        for _ in 0..msg.0.parse::<usize>().unwrap() {
+            ctx.send(format!("m{}", self.count));
+            self.count += 1;
+        }
+    }
+}
+
+fn manager() -> TestManager<Burst> {
+    TestManager::new(SockJSManager::<Burst>::default().coalesce(Duration::from_millis(200), 3))
+}
+
+/// Let time pass and run event loop
+fn sleep(srv: &mut TestManager<Burst>, ms: u64) {
+    thread::sleep(Duration::from_millis(ms));
+    srv.settle();
+}
+
+fn batch(msgs: &str) -> Frame {
+    Frame::MessageVec(msgs.to_owned())
+}
+
+#[test]
+fn test_window_flush() {
+    let mut srv = manager();
+    let tr = srv.connect("s1");
+    assert_eq!(tr.frames(), vec![Frame::Open]);
+
+    // messages wait for the window
+    srv.send(&tr, "2");
+    assert!(tr.frames().is_empty());
+    sleep(&mut srv, 300);
+    assert_eq!(tr.frames(), vec![batch(r#"["m0","m1"]"#)]);
+
+    // single message is sent as is
+    srv.send(&tr, "1");
+    sleep(&mut srv, 300);
+    assert_eq!(tr.frames(), vec![Frame::Message("m2".to_owned())]);
+}
+
+#[test]
+fn test_max_size_flush() {
+    let mut srv = manager();
+    let tr = srv.connect("s1");
+    tr.frames();
+
+    // full batch is sent immediately
+    srv.send(&tr, "3");
+    assert_eq!(tr.frames(), vec![batch(r#"["m0","m1","m2"]"#)]);
+
+    // rest of the messages wait for the window
+    srv.send(&tr, "4");
+    assert_eq!(tr.frames(), vec![batch(r#"["m3","m4","m5"]"#)]);
+    sleep(&mut srv, 300);
+    assert_eq!(tr.frames(), vec![Frame::Message("m6".to_owned())]);
+}
+
+#[test]
+fn test_timer_cancelled() {
+    let mut srv = manager();
+    let tr = srv.connect("s1");
+    tr.frames();
+
+    // window timer of flushed batch does not flush next batch early
+    srv.send(&tr, "3");
+    assert_eq!(tr.frames().len(), 1);
+    sleep(&mut srv, 100);
+    srv.send(&tr, "1");
+    sleep(&mut srv, 150);
+    assert!(tr.frames().is_empty());
+    sleep(&mut srv, 100);
+    assert_eq!(tr.frames(), vec![Frame::Message("m3".to_owned())]);
+}
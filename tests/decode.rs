@@ -0,0 +1,124 @@
+//! Decoders round-trip messages encoded with `encode` module
+#[macro_use]
+extern crate quickcheck;
+extern crate sockjs;
+
+use sockjs::decode::{self, DecodeError};
+use sockjs::{encode, CloseCode, Frame};
+
+/// Encode form field value, space is `+`
+fn form_encode(value: &str) -> String {
+    let mut res = String::new();
+    for b in value.bytes() {
+        match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'*' => {
+                res.push(b as char)
+            }
+            b' ' => res.push('+'),
+            _ => res.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    res
+}
+
+quickcheck! {
+    fn xhr_send_round_trip(msgs: Vec<String>) -> bool {
+        decode::xhr_send(encode::array(&msgs).as_bytes()) == Ok(msgs)
+    }
+
+    fn jsonp_send_form_round_trip(msgs: Vec<String>) -> bool {
+        let body = format!("t=1&d={}", form_encode(&encode::array(&msgs)));
+        decode::jsonp_send(body.as_bytes(), true) == Ok(msgs)
+    }
+
+    fn jsonp_send_round_trip(msgs: Vec<String>) -> bool {
+        decode::jsonp_send(encode::array(&msgs).as_bytes(), false) == Ok(msgs)
+    }
+
+    fn websocket_array_round_trip(msgs: Vec<String>) -> bool {
+        decode::websocket(&encode::array(&msgs)) == Ok(msgs)
+    }
+
+    fn websocket_string_round_trip(msg: String) -> bool {
+        decode::websocket(&encode::string(&msg)) == Ok(vec![msg])
+    }
+
+    fn message_frame_round_trip(msg: String) -> bool {
+        match decode::frame(&encode::message(&msg)) {
+            Ok(Frame::MessageVec(msgs)) => decode::xhr_send(msgs.as_bytes()) == Ok(vec![msg]),
+            _ => false,
+        }
+    }
+
+    fn array_frame_round_trip(msgs: Vec<String>) -> bool {
+        match decode::frame(&format!("a{}\n", encode::array(&msgs))) {
+            Ok(Frame::MessageVec(body)) => decode::xhr_send(body.as_bytes()) == Ok(msgs),
+            _ => false,
+        }
+    }
+
+    fn decoders_never_panic(body: Vec<u8>) -> bool {
+        let _ = decode::xhr_send(&body);
+        let _ = decode::jsonp_send(&body, true);
+        let _ = decode::jsonp_send(&body, false);
+        let _ = decode::websocket(&String::from_utf8_lossy(&body));
+        let _ = decode::frame(&String::from_utf8_lossy(&body));
+        let _ = decode::close(&String::from_utf8_lossy(&body));
+        true
+    }
+}
+
+#[test]
+fn test_xhr_send() {
+    assert_eq!(decode::xhr_send(b""), Err(DecodeError::PayloadExpected));
+    assert_eq!(decode::xhr_send(b"[ ]"), Ok(vec![]));
+    assert_eq!(decode::xhr_send(b"[\"x\""), Err(DecodeError::BrokenJson));
+    assert_eq!(decode::xhr_send(b"\"x\""), Err(DecodeError::BrokenJson));
+}
+
+#[test]
+fn test_jsonp_send() {
+    assert_eq!(decode::jsonp_send(b"d=", true), Err(DecodeError::PayloadExpected));
+    assert_eq!(decode::jsonp_send(b"p=p", true), Err(DecodeError::PayloadExpected));
+    assert_eq!(decode::jsonp_send(b"d=%5B%22x%22", true), Err(DecodeError::BrokenJson));
+    assert_eq!(decode::jsonp_send(b"d=%FF", true), Err(DecodeError::PayloadExpected));
+    assert_eq!(decode::jsonp_send(b"p=p&d=%5B%22a+b%22%5D", true),
+               Ok(vec!["a b".to_owned()]));
+}
+
+#[test]
+fn test_websocket() {
+    assert_eq!(decode::websocket(""), Ok(vec![]));
+    assert_eq!(decode::websocket("  [\"a\",\"b\"]"), Ok(vec!["a".to_owned(), "b".to_owned()]));
+    assert_eq!(decode::websocket("\"a\""), Ok(vec!["a".to_owned()]));
+    assert_eq!(decode::websocket("[\"a\""), Err(DecodeError::BrokenJson));
+    assert_eq!(decode::websocket("a"), Err(DecodeError::BrokenJson));
+}
+
+#[test]
+fn test_frame() {
+    assert_eq!(decode::frame("o\n"), Ok(Frame::Open));
+    assert_eq!(decode::frame(&"h".repeat(2048)), Ok(Frame::Heartbeat));
+    assert_eq!(decode::frame("a[\"a\"]\r\n"), Ok(Frame::MessageVec("[\"a\"]".to_owned())));
+    assert_eq!(decode::frame("c[3000,\"Go away!\"]"), Ok(Frame::Close(CloseCode::GoAway)));
+    assert_eq!(decode::frame("c[4000,\"Custom\"]"), Err(DecodeError::BrokenJson));
+    assert_eq!(decode::frame(""), Err(DecodeError::PayloadExpected));
+    assert_eq!(decode::frame("x"), Err(DecodeError::BrokenJson));
+    assert_eq!(decode::close("c[4000,\"Custom\"]\n"), Ok((4000, "Custom".to_owned())));
+    assert_eq!(decode::close("a[]"), Err(DecodeError::BrokenJson));
+}
+
+#[test]
+fn test_encode() {
+    assert_eq!(encode::message("a\"b"), "a[\"a\\\"b\"]");
+    assert_eq!(encode::array(&["a", "b"]), "[\"a\",\"b\"]");
+    assert_eq!(encode::array::<String>(&[]), "[]");
+    // characters mangled by browsers get escaped
+    assert_eq!(encode::string("\u{2028}\u{ffff}\u{0}"), "\"\\u2028\\uffff\\u0000\"");
+}
+
+#[test]
+fn test_reason() {
+    assert_eq!(DecodeError::PayloadExpected.reason(), "Payload expected.");
+    assert_eq!(DecodeError::BrokenJson.reason(), "Broken JSON encoding.");
+}
@@ -0,0 +1,72 @@
+//! Handler function sessions
+extern crate futures;
+extern crate sockjs;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{Future, Sink, Stream};
+
+use sockjs::test::TestManager;
+use sockjs::{CloseCode, Frame, Message, SockJSManager};
+
+#[test]
+fn test_echo() {
+    let mut srv = TestManager::new(SockJSManager::from_fn(|sink, stream| {
+        stream.forward(sink).map(|_| ())
+    }));
+
+    let tr = srv.connect("s1");
+    srv.send(&tr, "hello");
+    srv.send(&tr, "world");
+    assert_eq!(tr.frames(), vec![Frame::Open,
+                                 Frame::Message("hello".to_owned()),
+                                 Frame::Message("world".to_owned())]);
+}
+
+// dropping the sink closes session
+#[test]
+fn test_close() {
+    let mut srv = TestManager::new(SockJSManager::from_fn(|sink, _| {
+        sink.send(Message::from("bye")).map(|_| ())
+    }));
+
+    let tr = srv.connect("s1");
+    assert_eq!(tr.frames(), vec![Frame::Open,
+                                 Frame::Message("bye".to_owned()),
+                                 Frame::Close(CloseCode::GoAway)]);
+    assert!(tr.released());
+}
+
+// stream ends once session is closed
+#[test]
+fn test_stream_end() {
+    let received = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&received);
+    let mut srv = TestManager::new(SockJSManager::from_fn(move |_, stream| {
+        let counter = Arc::clone(&counter);
+        stream.collect().map(move |msgs| counter.store(msgs.len(), Ordering::SeqCst))
+    }));
+
+    let tr = srv.connect("s1");
+    srv.send(&tr, "hello");
+    assert_eq!(received.load(Ordering::SeqCst), 0);
+    srv.interrupt(tr);
+    assert_eq!(received.load(Ordering::SeqCst), 1);
+
+    let tr = srv.connect("s1");
+    assert_eq!(tr.frames(), vec![Frame::Close(CloseCode::Interrupted)]);
+}
+
+#[test]
+fn test_broadcast() {
+    let mut srv = TestManager::new(SockJSManager::from_fn(|sink, stream| {
+        stream.for_each(move |msg| sink.broadcast(msg))
+    }));
+
+    let tr1 = srv.connect("s1");
+    let tr2 = srv.connect("s2");
+    srv.send(&tr1, "hello");
+    assert_eq!(tr1.messages(), vec!["hello".to_owned()]);
+    assert_eq!(tr2.messages(), vec!["hello".to_owned()]);
+}
@@ -0,0 +1,141 @@
+//! Message history and replay on join
+extern crate actix;
+extern crate sockjs;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use actix::prelude::*;
+
+use sockjs::test::TestManager;
+use sockjs::{Frame, GetHistory, Message, Publish, Session, SockJSContext, SockJSManager,
+             SubscribeTopic};
+
+#[derive(Default)]
+struct Idle;
+
+impl Actor for Idle {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Idle {}
+
+impl Handler<Message> for Idle {
+    type Result = ();
+
+    fn handle(&mut self, _: Message, _: &mut SockJSContext<Self>) {}
+}
+
+fn msgs(msgs: &[&str]) -> Vec<String> {
+    msgs.iter().map(|m| m.to_string()).collect()
+}
+
+#[test]
+fn test_broadcast_replay() {
+    let manager = SockJSManager::<Idle>::default().history(2, None).replay_on_join(true);
+    let mut srv = TestManager::new(manager);
+    let tr1 = srv.connect("s1");
+    srv.broadcast("m1");
+    srv.broadcast("m2");
+    srv.broadcast("m3");
+    assert_eq!(tr1.messages(), msgs(&["m1", "m2", "m3"]));
+
+    // new session gets last 2 messages after open frame
+    let tr2 = srv.connect("s2");
+    assert_eq!(tr2.frames(), vec![Frame::Open,
+                                  Frame::Message("m2".to_owned()),
+                                  Frame::Message("m3".to_owned())]);
+
+    // reconnected session is not a new one
+    srv.disconnect(tr1);
+    let tr1 = srv.connect("s1");
+    assert!(tr1.messages().is_empty());
+}
+
+#[test]
+fn test_topic_replay() {
+    let manager = SockJSManager::<Idle>::default().history(10, None).replay_on_join(true);
+    let mut srv = TestManager::new(manager);
+    srv.manager().do_send(Publish::new("a.x", "1"));
+    srv.manager().do_send(Publish::new("b.x", "2"));
+    srv.manager().do_send(Publish::new("a.y", "3"));
+    srv.manager().do_send(Publish::new("a.x", "4"));
+    srv.settle();
+
+    let tr = srv.connect("s1");
+    tr.frames();
+    srv.manager().do_send(
+        SubscribeTopic {sid: Arc::new("s1".to_owned()), pattern: "a.*".to_owned()});
+    srv.settle();
+    assert_eq!(tr.messages(), msgs(&["1", "3", "4"]));
+
+    // broadcast history is not replayed to subscribers
+    srv.broadcast("m1");
+    assert_eq!(tr.messages(), msgs(&["m1"]));
+    srv.manager().do_send(
+        SubscribeTopic {sid: Arc::new("s1".to_owned()), pattern: "b.#".to_owned()});
+    srv.settle();
+    assert_eq!(tr.messages(), msgs(&["2"]));
+}
+
+#[test]
+fn test_overlapping_replay() {
+    let manager = SockJSManager::<Idle>::default().history(10, None).replay_on_join(true);
+    let mut srv = TestManager::new(manager);
+    srv.manager().do_send(Publish::new("a.x", "1"));
+    srv.manager().do_send(Publish::new("a.y", "2"));
+    srv.manager().do_send(Publish::new("a.y.z", "3"));
+    srv.settle();
+
+    let tr = srv.connect("s1");
+    tr.frames();
+    let sid = Arc::new("s1".to_owned());
+    srv.manager().do_send(SubscribeTopic {sid: Arc::clone(&sid), pattern: "a.x".to_owned()});
+    srv.settle();
+    assert_eq!(tr.messages(), msgs(&["1"]));
+
+    // only topics not covered by existing subscriptions get replayed
+    srv.manager().do_send(SubscribeTopic {sid: Arc::clone(&sid), pattern: "a.#".to_owned()});
+    srv.settle();
+    assert_eq!(tr.messages(), msgs(&["2", "3"]));
+    srv.manager().do_send(SubscribeTopic {sid: Arc::clone(&sid), pattern: "a.*".to_owned()});
+    srv.settle();
+    assert!(tr.messages().is_empty());
+}
+
+#[test]
+fn test_max_age() {
+    let manager = SockJSManager::<Idle>::default()
+        .history(10, Some(Duration::from_millis(20)))
+        .replay_on_join(true);
+    let mut srv = TestManager::new(manager);
+    srv.broadcast("m1");
+    thread::sleep(Duration::from_millis(50));
+    srv.broadcast("m2");
+
+    let tr = srv.connect("s1");
+    assert_eq!(tr.messages(), msgs(&["m2"]));
+}
+
+#[test]
+fn test_get_history() {
+    let manager = SockJSManager::<Idle>::default().history(2, None);
+    let mut srv = TestManager::new(manager);
+    srv.broadcast("m1");
+    srv.manager().do_send(Publish::new("a", "1"));
+    srv.manager().do_send(Publish::new("a", "2"));
+    srv.manager().do_send(Publish::new("a", "3"));
+    srv.settle();
+
+    // replay is disabled
+    let tr = srv.connect("s1");
+    assert!(tr.messages().is_empty());
+
+    let addr = srv.manager().clone();
+    assert_eq!(srv.block_on(addr.send(GetHistory {topic: None})).unwrap(), msgs(&["m1"]));
+    assert_eq!(srv.block_on(addr.send(GetHistory {topic: Some("a".to_owned())})).unwrap(),
+               msgs(&["2", "3"]));
+    assert!(srv.block_on(addr.send(GetHistory {topic: Some("b".to_owned())})).unwrap()
+            .is_empty());
+}
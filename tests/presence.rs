@@ -0,0 +1,141 @@
+//! Presence tracking
+extern crate actix;
+extern crate sockjs;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix::prelude::*;
+
+use sockjs::test::TestManager;
+use sockjs::{ListPresence, Message, PresenceEvent, PresenceStatus, Session, SockJSContext,
+             SockJSManager, SubscribePresence};
+
+/// Session closes itself on `close` message
+#[derive(Default)]
+struct Closer;
+
+impl Actor for Closer {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Closer {}
+
+impl Handler<Message> for Closer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        if msg.0 == "close" {
+            ctx.close();
+        }
+    }
+}
+
+/// Records presence events as `"<event> <sid>"` strings
+struct Events(Arc<Mutex<Vec<String>>>);
+
+impl Actor for Events {
+    type Context = Context<Self>;
+}
+
+impl Handler<PresenceEvent> for Events {
+    type Result = ();
+
+    fn handle(&mut self, msg: PresenceEvent, _: &mut Context<Self>) {
+        let ev = match msg {
+            PresenceEvent::Join(p) => format!("join {}", p.sid),
+            PresenceEvent::Away(p) => format!("away {}", p.sid),
+            PresenceEvent::Leave(p) => format!("leave {}", p.sid),
+        };
+        self.0.lock().unwrap().push(ev);
+    }
+}
+
+fn subscribe(srv: &mut TestManager<Closer>) -> Arc<Mutex<Vec<String>>> {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let addr: Addr<Syn, _> = Events(Arc::clone(&events)).start();
+    srv.manager().do_send(SubscribePresence(addr.recipient()));
+    srv.settle();
+    events
+}
+
+fn take(events: &Arc<Mutex<Vec<String>>>) -> Vec<String> {
+    events.lock().unwrap().drain(..).collect()
+}
+
+#[test]
+fn test_release_acquire() {
+    let mut srv = TestManager::new(SockJSManager::<Closer>::default());
+    let events = subscribe(&mut srv);
+
+    let tr = srv.connect("s1");
+    assert_eq!(take(&events), vec!["join s1"]);
+
+    // released session is away until next transport request
+    srv.disconnect(tr);
+    assert_eq!(take(&events), vec!["away s1"]);
+    let tr = srv.connect("s1");
+    assert_eq!(take(&events), vec!["join s1"]);
+    srv.disconnect(tr);
+    assert_eq!(take(&events), vec!["away s1"]);
+}
+
+#[test]
+fn test_interrupted() {
+    let mut srv = TestManager::new(SockJSManager::<Closer>::default());
+    let events = subscribe(&mut srv);
+
+    let tr = srv.connect("s1");
+    srv.interrupt(tr);
+    assert_eq!(take(&events), vec!["join s1", "leave s1"]);
+
+    // interrupted session does not join again
+    let tr = srv.connect("s1");
+    srv.disconnect(tr);
+    srv.advance(Duration::new(11, 0));
+    assert!(take(&events).is_empty());
+}
+
+#[test]
+fn test_closed() {
+    let mut srv = TestManager::new(SockJSManager::<Closer>::default());
+    let events = subscribe(&mut srv);
+
+    let tr = srv.connect("s1");
+    srv.send(&tr, "close");
+    assert!(tr.released());
+    assert_eq!(take(&events), vec!["join s1", "leave s1"]);
+}
+
+#[test]
+fn test_expired() {
+    let mut srv = TestManager::new(SockJSManager::<Closer>::default());
+    let events = subscribe(&mut srv);
+
+    let tr = srv.connect("s1");
+    srv.disconnect(tr);
+    srv.advance(Duration::new(11, 0));
+    assert_eq!(take(&events), vec!["join s1", "away s1", "leave s1"]);
+}
+
+#[test]
+fn test_list() {
+    let mut srv = TestManager::new(SockJSManager::<Closer>::default());
+    let tr1 = srv.connect("s1");
+    let tr2 = srv.connect("s2");
+    srv.disconnect(tr2);
+
+    let fut = srv.manager().send(ListPresence);
+    let mut list: Vec<_> = srv.block_on(fut).unwrap().into_iter()
+        .map(|p| (p.sid.as_str().to_owned(), p.status))
+        .collect();
+    list.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(list, vec![("s1".to_owned(), PresenceStatus::Online),
+                          ("s2".to_owned(), PresenceStatus::Away)]);
+
+    srv.interrupt(tr1);
+    let fut = srv.manager().send(ListPresence);
+    let list = srv.block_on(fut).unwrap();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].sid.as_str(), "s2");
+}
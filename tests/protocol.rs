@@ -0,0 +1,2301 @@
+//! SockJS protocol conformance tests, port of `protocol/sockjs-protocol.py`
+//!
+//! Tests run against in-process `SockJS` handlers served by actix-web's
+//! test server. Services are the same as in `examples/sockjs-testsrv.rs`:
+//!
+//!  - `echo` - responds with identical data as received, binary messages
+//!    of raw websocket get echoed as text
+//!  - `disabled_websocket_echo` - identical to `echo`, but with websockets disabled
+//!  - `cookie_needed_echo` - identical to `echo`, but with JSESSIONID cookies sent
+//!  - `close` - server immediately closes the session
+//!  - `deflate_echo` - identical to `echo`, with permessage-deflate websocket extension
+//!  - `compressed_echo` - identical to `echo`, with gzip/deflate streaming responses
+//!  - `reliable_echo` - identical to `echo`, with reliable delivery mode
+extern crate actix;
+extern crate actix_web;
+extern crate bytes;
+extern crate flate2;
+extern crate futures;
+extern crate rand;
+extern crate regex;
+extern crate serde_json;
+extern crate sockjs;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+use actix_web::http::{HeaderMap, Method, StatusCode};
+use actix_web::test::TestServer;
+use actix_web::ws;
+use actix_web::client::ClientResponse;
+use actix_web::{App, HttpMessage};
+use bytes::{Bytes, BytesMut};
+use flate2::write::{GzDecoder, ZlibDecoder};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use futures::Stream;
+use regex::Regex;
+
+use sockjs::{Deflate, Expire, Message, Session, SockJS, SockJSContext, SockJSManager,
+             TransportKind};
+
+#[derive(Default)]
+struct Echo;
+
+impl Actor for Echo {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Echo {
+    fn binary(&mut self, ctx: &mut SockJSContext<Self>, msg: Bytes) {
+        ctx.send(String::from_utf8_lossy(&msg).into_owned());
+    }
+}
+
+impl Handler<Message> for Echo {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        ctx.send(msg);
+    }
+}
+
+#[derive(Default)]
+struct Close;
+
+impl Actor for Close {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Close {
+    fn opened(&mut self, ctx: &mut SockJSContext<Self>) {
+        ctx.close()
+    }
+}
+
+impl Handler<Message> for Close {
+    type Result = ();
+
+    fn handle(&mut self, _: Message, ctx: &mut SockJSContext<Self>) {
+        ctx.close()
+    }
+}
+
+/// Start test server with all test services
+fn serve() -> TestServer {
+    serve_echo().0
+}
+
+/// Start test server, session manager of `echo` service is returned too
+fn serve_echo() -> (TestServer, Addr<Syn, SockJSManager<Echo>>) {
+    // session managers are shared by all server workers
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let sys = System::new("sockjs-managers");
+        let echo: Addr<Syn, _> = SockJSManager::<Echo>::start_default();
+        let close: Addr<Syn, _> = SockJSManager::<Close>::start_default();
+        let reliable: Addr<Syn, _> = SockJSManager::<Echo>::default().reliable(16).start();
+        tx.send((echo, close, reliable)).unwrap();
+        sys.run();
+    });
+    let (echo, close, reliable) = rx.recv().unwrap();
+    let manager = echo.clone();
+
+    let srv = TestServer::with_factory(move || {
+        App::new()
+            .handler("/echo", SockJS::new(echo.clone()).maxsize(4096))
+            .handler("/close", SockJS::new(close.clone()))
+            .handler(
+                "/disabled_websocket_echo",
+                SockJS::new(echo.clone()).disable(vec![TransportKind::Websocket]))
+            .handler("/cookie_needed_echo", SockJS::new(echo.clone()).cookie_needed(true))
+            .handler(
+                "/limited_echo",
+                SockJS::new(echo.clone())
+                    .max_message_size(16)
+                    .max_body_size(64)
+                    .max_frame_size(64))
+            .handler("/deflate_echo", SockJS::new(echo.clone()).websocket_deflate(Deflate::new()))
+            .handler("/compressed_echo", SockJS::new(echo.clone()).streaming_compression(true))
+            .handler("/reliable_echo", SockJS::new(reliable.clone()))
+    });
+    (srv, manager)
+}
+
+/// Session url of the service, every call creates new session id
+fn session_url(service: &str) -> String {
+    format!("/{}/000/{:016x}", service, rand::random::<u64>())
+}
+
+struct Response {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: String,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).map(|val| val.to_str().unwrap())
+}
+
+fn send(srv: &mut TestServer, method: Method, path: &str,
+        headers: &[(&str, &str)], body: Option<&str>) -> ClientResponse
+{
+    let mut req = srv.client(method, path);
+    for &(name, value) in headers {
+        req.header(name, value);
+    }
+    let req = match body {
+        Some(body) => req.body(body.to_owned()).unwrap(),
+        None => req.finish().unwrap(),
+    };
+    srv.execute(req.send()).unwrap()
+}
+
+fn request(srv: &mut TestServer, method: Method, path: &str,
+           headers: &[(&str, &str)], body: Option<&str>) -> Response
+{
+    let resp = send(srv, method, path, headers, body);
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    let body = srv.execute(resp.body()).unwrap();
+    Response {status, headers, body: String::from_utf8(body.to_vec()).unwrap()}
+}
+
+fn get(srv: &mut TestServer, path: &str) -> Response {
+    request(srv, Method::GET, path, &[], None)
+}
+
+fn post(srv: &mut TestServer, path: &str, body: Option<&str>) -> Response {
+    request(srv, Method::POST, path, &[], body)
+}
+
+fn jsonp_send(srv: &mut TestServer, path: &str, body: &str) -> Response {
+    request(srv, Method::POST, path,
+            &[("Content-Type", "application/x-www-form-urlencoded")], Some(body))
+}
+
+/// Streaming response, body is read incrementally
+struct Streaming {
+    status: StatusCode,
+    headers: HeaderMap,
+    resp: Option<ClientResponse>,
+    buf: BytesMut,
+}
+
+impl Streaming {
+    fn new(srv: &mut TestServer, method: Method, path: &str, headers: &[(&str, &str)])
+           -> Streaming
+    {
+        let resp = send(srv, method, path, headers, None);
+        Streaming {
+            status: resp.status(),
+            headers: resp.headers().clone(),
+            resp: Some(resp),
+            buf: BytesMut::new(),
+        }
+    }
+
+    /// Read next chunk into buffer, returns `false` at the end of the body
+    fn fill(&mut self, srv: &mut TestServer) -> bool {
+        if let Some(resp) = self.resp.take() {
+            match srv.execute(resp.into_future()) {
+                Ok((Some(chunk), resp)) => {
+                    self.buf.extend_from_slice(&chunk);
+                    self.resp = Some(resp);
+                    true
+                }
+                _ => false,
+            }
+        } else {
+            false
+        }
+    }
+
+    /// Read exactly `size` bytes
+    fn read(&mut self, srv: &mut TestServer, size: usize) -> String {
+        while self.buf.len() < size {
+            assert!(self.fill(srv), "Unexpected end of response body: {:?}", self.buf);
+        }
+        String::from_utf8(self.buf.split_to(size).to_vec()).unwrap()
+    }
+
+    fn expect(&mut self, srv: &mut TestServer, data: &str) {
+        assert_eq!(self.read(srv, data.len()), data);
+    }
+
+    /// Response body is complete
+    fn eof(&mut self, srv: &mut TestServer) -> bool {
+        self.buf.is_empty() && !self.fill(srv)
+    }
+}
+
+/// Websocket connection
+struct WsConn {
+    reader: Option<ws::ClientReader>,
+    writer: ws::ClientWriter,
+}
+
+impl WsConn {
+    fn new(srv: &mut TestServer, path: &str) -> WsConn {
+        let (reader, writer) = srv.execute(ws::Client::new(srv.url(path)).connect()).unwrap();
+        WsConn {writer, reader: Some(reader)}
+    }
+
+    fn send(&mut self, text: &str) {
+        self.writer.text(text.to_owned());
+    }
+
+    /// Next message, `None` if connection is closed
+    fn recv(&mut self, srv: &mut TestServer) -> Option<ws::Message> {
+        if let Some(reader) = self.reader.take() {
+            match srv.execute(reader.into_future()) {
+                Ok((Some(msg), reader)) => {
+                    self.reader = Some(reader);
+                    Some(msg)
+                }
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    fn recv_text(&mut self, srv: &mut TestServer) -> String {
+        match self.recv(srv) {
+            Some(ws::Message::Text(text)) => text,
+            msg => panic!("Text message expected: {:?}", msg),
+        }
+    }
+
+    /// Connection get closed by the server
+    fn closed(&mut self, srv: &mut TestServer) -> bool {
+        match self.recv(srv) {
+            None | Some(ws::Message::Close(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Plain tcp connection for http version tests
+struct RawHttp {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+struct RawResponse {
+    status: u16,
+    version: String,
+    headers: Vec<(String, String)>,
+}
+
+impl RawResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|h| h.0 == name).map(|h| h.1.as_str())
+    }
+}
+
+impl RawHttp {
+    fn new(srv: &TestServer) -> RawHttp {
+        let stream = TcpStream::connect(srv.addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        RawHttp {stream, buf: Vec::new()}
+    }
+
+    fn request(&mut self, method: &str, path: &str, version: &str, headers: &[(&str, &str)])
+               -> RawResponse
+    {
+        let mut req = format!("{} {} HTTP/{}\r\nHost: localhost\r\n", method, path, version);
+        for &(name, value) in headers {
+            req.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        req.push_str("\r\n");
+        self.stream.write_all(req.as_bytes()).unwrap();
+
+        let head = loop {
+            if let Some(pos) = self.buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head: Vec<_> = self.buf.drain(..pos + 4).collect();
+                break String::from_utf8(head).unwrap();
+            }
+            assert!(self.fill(), "Unexpected end of response");
+        };
+
+        let mut lines = head.split("\r\n");
+        let mut status = lines.next().unwrap().splitn(3, ' ');
+        let version = status.next().unwrap().trim_left_matches("HTTP/").to_owned();
+        let status = status.next().unwrap().parse().unwrap();
+        let headers = lines
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut kv = line.splitn(2, ':');
+                let name = kv.next().unwrap().trim().to_lowercase();
+                let value = kv.next().unwrap_or("").trim().to_owned();
+                (name, value)
+            })
+            .collect();
+        RawResponse {status, version, headers}
+    }
+
+    fn fill(&mut self) -> bool {
+        let mut chunk = [0u8; 4096];
+        match self.stream.read(&mut chunk) {
+            Ok(0) | Err(_) => false,
+            Ok(n) => {
+                self.buf.extend_from_slice(&chunk[..n]);
+                true
+            }
+        }
+    }
+
+    fn read_bytes(&mut self, size: usize) -> Vec<u8> {
+        while self.buf.len() < size {
+            assert!(self.fill(), "Unexpected end of response");
+        }
+        self.buf.drain(..size).collect()
+    }
+
+    fn read(&mut self, size: usize) -> String {
+        String::from_utf8(self.read_bytes(size)).unwrap()
+    }
+
+    fn read_chunk(&mut self) -> String {
+        String::from_utf8(self.read_chunk_bytes()).unwrap()
+    }
+
+    fn read_chunk_bytes(&mut self) -> Vec<u8> {
+        let line = loop {
+            if let Some(pos) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                let line: Vec<_> = self.buf.drain(..pos + 2).collect();
+                break String::from_utf8(line).unwrap();
+            }
+            assert!(self.fill(), "Unexpected end of response");
+        };
+        let size = usize::from_str_radix(line.trim(), 16).unwrap();
+        let chunk = self.read_bytes(size);
+        self.read(2);
+        chunk
+    }
+
+    fn closed(&mut self) -> bool {
+        self.buf.is_empty() && !self.fill()
+    }
+}
+
+/// Websocket connection over plain tcp, frames are written and read as is
+struct RawWs {
+    c: RawHttp,
+    /// `Sec-WebSocket-Extensions` header of the handshake response
+    extensions: Option<String>,
+}
+
+impl RawWs {
+    fn new(srv: &TestServer, path: &str, headers: &[(&str, &str)]) -> RawWs {
+        let mut c = RawHttp::new(srv);
+        let mut all = vec![("Upgrade", "websocket"),
+                           ("Connection", "Upgrade"),
+                           ("Sec-WebSocket-Version", "13"),
+                           ("Sec-WebSocket-Key", "x3JJHMbDL1EzLkh9GBhXDw==")];
+        all.extend_from_slice(headers);
+        let r = c.request("GET", path, "1.1", &all);
+        assert_eq!(r.status, 101);
+        let extensions = r.header("sec-websocket-extensions").map(|h| h.to_owned());
+        RawWs {c, extensions}
+    }
+
+    /// Write masked frame, `head` is first byte of the frame
+    fn write(&mut self, head: u8, payload: &[u8]) {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let len = payload.len();
+        let mut frame = vec![head];
+        if len < 126 {
+            frame.push(0x80 | len as u8);
+        } else if len <= 65_535 {
+            frame.extend_from_slice(&[0x80 | 126, (len >> 8) as u8, len as u8]);
+        } else {
+            frame.push(0x80 | 127);
+            for i in (0..8).rev() {
+                frame.push(((len as u64) >> (i * 8)) as u8);
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        self.c.stream.write_all(&frame).unwrap();
+    }
+
+    fn text(&mut self, text: &str) {
+        self.write(0x81, text.as_bytes());
+    }
+
+    /// Write close frame with status code
+    fn close(&mut self, code: u16) {
+        self.write(0x88, &[(code >> 8) as u8, code as u8]);
+    }
+
+    /// Next frame, first byte and payload. `None` if connection is closed.
+    fn read(&mut self) -> Option<(u8, Vec<u8>)> {
+        if self.c.closed() {
+            return None
+        }
+        let head = self.c.read_bytes(2);
+        let len = match head[1] & 0x7f {
+            126 => self.c.read_bytes(2).iter().fold(0, |len, b| len << 8 | *b as usize),
+            127 => self.c.read_bytes(8).iter().fold(0, |len, b| len << 8 | *b as usize),
+            len => len as usize,
+        };
+        Some((head[0], self.c.read_bytes(len)))
+    }
+
+    fn read_text(&mut self) -> String {
+        match self.read() {
+            Some((0x81, payload)) => String::from_utf8(payload).unwrap(),
+            frame => panic!("Text frame expected: {:?}", frame),
+        }
+    }
+
+    /// Status code of close frame
+    fn read_close(&mut self) -> u16 {
+        match self.read() {
+            Some((0x88, ref payload)) if payload.len() >= 2 =>
+                u16::from(payload[0]) << 8 | u16::from(payload[1]),
+            frame => panic!("Close frame expected: {:?}", frame),
+        }
+    }
+}
+
+// Helpers
+// =======
+
+// Compare the 'content-type' header ignoring spaces
+fn verify_content_type(headers: &HeaderMap, content_type: &str) {
+    assert_eq!(header(headers, "content-type").unwrap().replace(' ', ""), content_type);
+}
+
+fn verify_no_cookie(resp: &Response) {
+    assert!(resp.header("set-cookie").is_none());
+}
+
+// Most of the XHR/Ajax based transports do work CORS if proper
+// headers are set.
+fn verify_cors(headers: &HeaderMap, origin: Option<&str>) {
+    if let Some(origin) = origin {
+        assert_eq!(header(headers, "access-control-allow-origin"), Some(origin));
+        // In order to get cookies (`JSESSIONID` mostly) flying, we
+        // need to set `allow-credentials` header to true.
+        assert_eq!(header(headers, "access-control-allow-credentials"), Some("true"));
+    } else {
+        assert_eq!(header(headers, "access-control-allow-origin"), Some("*"));
+        assert!(header(headers, "access-control-allow-credentials").is_none());
+    }
+}
+
+// Sometimes, due to transports limitations we need to request
+// private data using GET method. In such case it's very important
+// to disallow any caching.
+fn verify_not_cached(headers: &HeaderMap) {
+    assert_eq!(header(headers, "cache-control"),
+               Some("no-store, no-cache, no-transform, must-revalidate, max-age=0"));
+    assert!(header(headers, "expires").is_none());
+    assert!(header(headers, "last-modified").is_none());
+}
+
+fn verify_cacheable(headers: &HeaderMap) {
+    let cache_control = header(headers, "cache-control").unwrap();
+    assert!(cache_control.contains("public"));
+    // max-age must be large, one year (31536000) is best
+    assert!(Regex::new("max-age=[1-9][0-9]{6}").unwrap().is_match(cache_control));
+    assert!(header(headers, "expires").is_some());
+}
+
+// Multiple transport protocols need to support OPTIONS method. All
+// responses to OPTIONS requests must be cacheable and contain
+// appropriate headers.
+fn verify_options(srv: &mut TestServer, path: &str, allowed_methods: &str) {
+    for &origin in &["test", "null"] {
+        let r = request(srv, Method::OPTIONS, path,
+                        &[("Access-Control-Request-Method", allowed_methods),
+                          ("Origin", origin)], None);
+        // A 200 'OK' or a 204 'No Content' should both be acceptable.
+        assert!(r.status == StatusCode::NO_CONTENT || r.status == StatusCode::OK);
+        verify_cacheable(&r.headers);
+        let max_age: u64 = r.header("access-control-max-age").unwrap().parse().unwrap();
+        assert!(max_age > 1_000_000);
+        let allow = r.header("access-control-allow-methods").unwrap();
+        for method in allowed_methods.split(',') {
+            assert!(allow.contains(method.trim()),
+                    "Access-Control-Allow-Methods did not contain: {}", method);
+        }
+        assert!(r.body.is_empty());
+        verify_cors(&r.headers, Some(origin));
+    }
+}
+
+// Greeting url: `/`
+// ----------------
+
+// The most important part of the url scheme, is without doubt, the
+// top url. Make sure the greeting is valid.
+#[test]
+fn test_greeting() {
+    let mut srv = serve();
+    for url in &["/echo", "/echo/"] {
+        let r = get(&mut srv, url);
+        assert_eq!(r.status, StatusCode::OK);
+        verify_content_type(&r.headers, "text/plain;charset=UTF-8");
+        assert_eq!(r.body, "Welcome to SockJS!\n");
+        verify_no_cookie(&r);
+    }
+}
+
+// Other simple requests should return 404.
+#[test]
+fn test_not_found() {
+    let mut srv = serve();
+    for suffix in &["/a", "/a.html", "//", "///", "/a/a", "/a/a/", "/a", "/a/"] {
+        let r = get(&mut srv, &format!("/echo{}", suffix));
+        assert_eq!(r.status, StatusCode::NOT_FOUND, "{}", suffix);
+    }
+}
+
+// IFrame page: `/iframe*.html`
+// ----------------------------
+
+const IFRAME_BODY: &str = r#"^<!DOCTYPE html>
+<html>
+<head>
+  <meta http-equiv="X-UA-Compatible" content="IE=edge" />
+  <meta http-equiv="Content-Type" content="text/html; charset=UTF-8" />
+  <script src="(?P<sockjs_url>[^"]*)"></script>
+  <script>
+    document.domain = document.domain;
+    SockJS.bootstrap_iframe\(\);
+  </script>
+</head>
+<body>
+  <h2>Don't panic!</h2>
+  <p>This is a SockJS hidden iframe. It's used for cross domain magic.</p>
+</body>
+</html>$"#;
+
+// The '/iframe.html' page and its variants must give `200/ok` and be
+// served with 'text/html' content type.
+fn verify_iframe(srv: &mut TestServer, path: &str) -> Response {
+    let r = get(srv, path);
+    assert_eq!(r.status, StatusCode::OK, "{}", path);
+    verify_content_type(&r.headers, "text/html;charset=UTF-8");
+    // The iframe page must be strongly cacheable, supply
+    // Cache-Control, Expires and Etag headers and avoid
+    // Last-Modified header.
+    verify_cacheable(&r.headers);
+    assert!(r.header("etag").is_some());
+    assert!(r.header("last-modified").is_none());
+
+    // Body must be exactly as specified, with the exception of
+    // `sockjs_url`, which should be configurable.
+    let body = Regex::new(IFRAME_BODY).unwrap();
+    let sockjs_url = body.captures(r.body.trim()).expect("iframe body")["sockjs_url"].to_owned();
+
+    // `Sockjs_url` must be a valid url and should utilize caching.
+    assert!(sockjs_url.starts_with('/') || sockjs_url.starts_with("http"));
+    verify_no_cookie(&r);
+    r
+}
+
+// SockJS server must provide this html page.
+#[test]
+fn test_iframe_simple_url() {
+    let mut srv = serve();
+    verify_iframe(&mut srv, "/echo/iframe.html");
+}
+
+// To properly utilize caching, the same content must be served
+// for request which try to version the iframe.
+#[test]
+fn test_iframe_versioned_url() {
+    let mut srv = serve();
+    for suffix in &["/iframe-a.html", "/iframe-.html", "/iframe-0.1.2.html",
+                    "/iframe-0.1.2abc-dirty.2144.html"] {
+        verify_iframe(&mut srv, &format!("/echo{}", suffix));
+    }
+}
+
+// In some circumstances (`devel` set to true) client library
+// wants to skip caching altogether. That is achieved by
+// supplying a random query string.
+#[test]
+fn test_iframe_queried_url() {
+    let mut srv = serve();
+    for suffix in &["/iframe-a.html?t=1234", "/iframe-0.1.2.html?t=123414",
+                    "/iframe-0.1.2abc-dirty.2144.html?t=qweqweq123"] {
+        verify_iframe(&mut srv, &format!("/echo{}", suffix));
+    }
+}
+
+// Malformed urls must give 404 answer.
+#[test]
+fn test_iframe_invalid_url() {
+    let mut srv = serve();
+    for suffix in &["/iframe.htm", "/iframe", "/IFRAME.HTML", "/IFRAME",
+                    "/iframe.HTML", "/iframe.xml", "/iframe-/.html"] {
+        let r = get(&mut srv, &format!("/echo{}", suffix));
+        assert_eq!(r.status, StatusCode::NOT_FOUND, "{}", suffix);
+    }
+}
+
+// The iframe page must be strongly cacheable. ETag headers must
+// not change too often. Server must support 'if-none-match'
+// requests.
+#[test]
+fn test_iframe_cacheability() {
+    let mut srv = serve();
+    let r1 = get(&mut srv, "/echo/iframe.html");
+    let r2 = get(&mut srv, "/echo/iframe.html");
+    let etag = r1.header("etag").unwrap().to_owned();
+    assert_eq!(r2.header("etag"), Some(etag.as_str()));
+
+    let r = request(&mut srv, Method::GET, "/echo/iframe.html",
+                    &[("If-None-Match", etag.as_str())], None);
+    assert_eq!(r.status, StatusCode::NOT_MODIFIED);
+    assert!(r.header("content-type").map(|ct| ct.is_empty()).unwrap_or(true));
+    assert!(r.body.is_empty());
+}
+
+// Info test: `/info`
+// ------------------
+
+// This url is called before the client starts the session. It's
+// used to check server capabilities (websocket support, cookies
+// requiremet) and to get the value of "origin" setting.
+#[test]
+fn test_info_basic() {
+    let mut srv = serve();
+    let r = request(&mut srv, Method::GET, "/echo/info", &[("Origin", "test")], None);
+    assert_eq!(r.status, StatusCode::OK);
+    verify_content_type(&r.headers, "application/json;charset=UTF-8");
+    verify_no_cookie(&r);
+    verify_not_cached(&r.headers);
+    verify_cors(&r.headers, Some("test"));
+
+    let data: serde_json::Value = serde_json::from_str(&r.body).unwrap();
+    // Are websockets enabled on the server?
+    assert_eq!(data["websocket"], true);
+    // Do transports need to support cookies (ie: for load
+    // balancing purposes.
+    assert!(data["cookie_needed"].is_boolean());
+    // List of allowed origins. Currently ignored.
+    assert_eq!(data["origins"], json_array(&["*:*"]));
+    // Source of entropy for random number generator.
+    assert!(data["entropy"].is_u64());
+}
+
+fn json_array(items: &[&str]) -> serde_json::Value {
+    serde_json::Value::Array(items.iter().map(|s| (*s).into()).collect())
+}
+
+// As browsers don't have a good entropy source, the server must
+// help with tht. Info url must supply a good, unpredictable random
+// number from the range <0; 2^32-1> to feed the browser.
+#[test]
+fn test_info_entropy() {
+    let mut srv = serve();
+    let entropy = |srv: &mut TestServer| {
+        let data: serde_json::Value = serde_json::from_str(&get(srv, "/echo/info").body).unwrap();
+        let entropy = data["entropy"].as_u64().unwrap();
+        assert!(entropy <= u64::from(u32::max_value()));
+        entropy
+    };
+    let e1 = entropy(&mut srv);
+    let e2 = entropy(&mut srv);
+    assert_ne!(e1, e2);
+}
+
+// Info url must support CORS.
+#[test]
+fn test_info_options() {
+    let mut srv = serve();
+    verify_options(&mut srv, "/echo/info", "OPTIONS, GET");
+}
+
+// SockJS client may be hosted from file:// url. In practice that
+// means the 'Origin' headers sent by the browser will have a value
+// of a string "null".
+#[test]
+fn test_info_options_null_origin() {
+    let mut srv = serve();
+    let r = request(&mut srv, Method::OPTIONS, "/echo/info",
+                    &[("Origin", "null"), ("Access-Control-Request-Method", "POST")], None);
+    assert!(r.status == StatusCode::NO_CONTENT || r.status == StatusCode::OK);
+    assert!(r.body.is_empty());
+    assert_eq!(r.header("access-control-allow-origin"), Some("null"));
+}
+
+// The 'disabled_websocket_echo' service should have websockets
+// disabled.
+#[test]
+fn test_info_disabled_websocket() {
+    let mut srv = serve();
+    let r = get(&mut srv, "/disabled_websocket_echo/info");
+    assert_eq!(r.status, StatusCode::OK);
+    let data: serde_json::Value = serde_json::from_str(&r.body).unwrap();
+    assert_eq!(data["websocket"], false);
+}
+
+// Session URLs
+// ============
+
+// To test session URLs we're going to use `xhr-polling` transport
+// facilitites.
+fn verify_session(srv: &mut TestServer, session_part: &str) {
+    let r = post(srv, &format!("/echo{}/xhr", session_part), None);
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "o\n");
+}
+
+// The server must accept any value in `server` and `session` fields.
+#[test]
+fn test_session_any_value() {
+    let mut srv = serve();
+    // add some randomness, so that test could be rerun immediately.
+    let r = rand::random::<u16>() % 1024;
+    verify_session(&mut srv, &format!("/a/a{}", r));
+    for session_part in &[format!("/_/_{}", r), format!("/1/{}", r),
+                          format!("/abcdefgh_i-j%20/abcdefg_i-j%20{}", r)] {
+        verify_session(&mut srv, session_part);
+    }
+}
+
+// But not an empty string, anything containing dots or paths with
+// less or more parts.
+#[test]
+fn test_session_invalid_paths() {
+    let mut srv = serve();
+    for suffix in &["//", "/a./a", "/a/a.", "/./.", "/", "///"] {
+        let path = format!("/echo{}/xhr", suffix);
+        assert_eq!(get(&mut srv, &path).status, StatusCode::NOT_FOUND, "{}", path);
+        assert_eq!(post(&mut srv, &path, None).status, StatusCode::NOT_FOUND, "{}", path);
+    }
+}
+
+// A session is identified by only `session_id`. `server_id` is a
+// parameter for load balancer and must be ignored by the server.
+#[test]
+fn test_session_ignoring_server_id() {
+    let mut srv = serve();
+    let sid = format!("{:016x}", rand::random::<u64>());
+    let r = post(&mut srv, &format!("/echo/000/{}/xhr", sid), None);
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "o\n");
+
+    let r = post(&mut srv, &format!("/echo/000/{}/xhr_send", sid), Some(r#"["a"]"#));
+    assert_eq!(r.status, StatusCode::NO_CONTENT);
+    assert!(r.body.is_empty());
+
+    let r = post(&mut srv, &format!("/echo/999/{}/xhr", sid), None);
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "a[\"a\"]\n");
+}
+
+// Protocol and framing
+// --------------------
+
+// When server receives a request with unknown `session_id` it must
+// recognize that as request for a new session. When server opens a
+// new sesion it must immediately send an frame containing a letter
+// `o`.
+#[test]
+fn test_simple_session() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "o\n");
+
+    // After a session was established the server needs to accept
+    // requests for sending messages.
+    let r = post(&mut srv, &format!("{}/xhr_send", url), Some(r#"["a"]"#));
+    assert_eq!(r.status, StatusCode::NO_CONTENT);
+    assert!(r.body.is_empty());
+
+    // We're using an echo service - we'll receive our message
+    // back. The message is encoded as an array 'a'.
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "a[\"a\"]\n");
+
+    // Sending messages to not existing sessions is invalid.
+    let r = post(&mut srv, "/echo/000/bad_session/xhr_send", Some(r#"["a"]"#));
+    assert_eq!(r.status, StatusCode::NOT_FOUND);
+
+    // The server must not allow two receiving connections to wait
+    // on a single session. In such case the server must send a
+    // close frame to the new connection.
+    let r1 = Streaming::new(&mut srv, Method::POST, &format!("{}/xhr", url), &[]);
+    thread::sleep(Duration::from_millis(250));
+    let r2 = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r2.body, "c[2010,\"Another connection still open\"]\n");
+    assert_eq!(r2.status, StatusCode::OK);
+    drop(r1);
+}
+
+// The server may terminate the connection, passing error code and
+// message.
+#[test]
+fn test_close_session() {
+    let mut srv = serve();
+    let url = session_url("close");
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "o\n");
+
+    // Until the timeout occurs, the server must constantly serve
+    // the close message.
+    for _ in 0..2 {
+        let r = post(&mut srv, &format!("{}/xhr", url), None);
+        assert_eq!(r.status, StatusCode::OK);
+        assert_eq!(r.body, "c[3000,\"Go away!\"]\n");
+    }
+}
+
+// The session must time out after 5 seconds of not having a
+// receiving connection. Session manager checks sessions every 10
+// seconds, test expires sessions as if time passed.
+#[test]
+fn test_session_expiry() {
+    let (mut srv, manager) = serve_echo();
+    let url = session_url("echo");
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.body, "o\n");
+
+    // Expired session is gone, sending messages is invalid.
+    // Transport releases session after response is sent.
+    let mut tries = 0;
+    loop {
+        let expire = Expire{now: Instant::now() + Duration::from_secs(11)};
+        srv.execute(manager.send(expire)).unwrap();
+        let r = post(&mut srv, &format!("{}/xhr_send", url), Some(r#"["a"]"#));
+        if r.status == StatusCode::NOT_FOUND {
+            break
+        }
+        assert_eq!(r.status, StatusCode::NO_CONTENT);
+        tries += 1;
+        assert!(tries < 50, "Session is not expired");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // New session get opened with the same id.
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.body, "o\n");
+}
+
+// WebSocket protocols: `/*/*/websocket`
+// -------------------------------------
+
+// Normal requests to websocket should not succeed.
+#[test]
+fn test_websocket_http_method() {
+    let mut srv = serve();
+    let r = get(&mut srv, "/echo/0/0/websocket");
+    assert_eq!(r.status, StatusCode::BAD_REQUEST);
+}
+
+// Some proxies and load balancers can rewrite 'Connection' header,
+// in such case we must refuse connection.
+#[test]
+fn test_websocket_invalid_connection_header() {
+    let mut srv = serve();
+    let r = request(&mut srv, Method::GET, "/echo/0/0/websocket",
+                    &[("Upgrade", "WebSocket"), ("Connection", "close")], None);
+    assert_eq!(r.status, StatusCode::BAD_REQUEST);
+}
+
+// WebSocket should only accept GET
+#[test]
+fn test_websocket_invalid_method() {
+    let mut srv = serve();
+    let upgrade: &[(&str, &str)] = &[("Upgrade", "WebSocket"), ("Connection", "Upgrade")];
+    let none: &[(&str, &str)] = &[];
+    for headers in &[upgrade, none] {
+        let r = request(&mut srv, Method::POST, "/echo/0/0/websocket", headers, None);
+        assert_eq!(r.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert!(r.header("content-type").is_none());
+        assert!(r.header("allow").is_some());
+        assert!(r.body.is_empty());
+    }
+}
+
+// The server must support Hybi-10 protocol
+#[test]
+fn test_websocket_transport() {
+    let mut srv = serve();
+    let mut ws = WsConn::new(&mut srv, &format!("{}/websocket", session_url("echo")));
+    assert_eq!(ws.recv_text(&mut srv), "o");
+    // Server must ignore empty messages.
+    ws.send("");
+    ws.send(r#"["a"]"#);
+    assert_eq!(ws.recv_text(&mut srv), "a[\"a\"]");
+}
+
+// Acks and replays are applied while websocket holds the session
+#[test]
+fn test_websocket_reliable() {
+    let mut srv = serve();
+    let mut ws = WsConn::new(&mut srv, &format!("{}/websocket", session_url("reliable_echo")));
+    assert_eq!(ws.recv_text(&mut srv), "o");
+    ws.send(r#"["a"]"#);
+    assert_eq!(ws.recv_text(&mut srv), "a[\"1:a\"]");
+    ws.send(r#"["b"]"#);
+    assert_eq!(ws.recv_text(&mut srv), "a[\"2:b\"]");
+    ws.send(r#"["c"]"#);
+    assert_eq!(ws.recv_text(&mut srv), "a[\"3:c\"]");
+
+    // acknowledged messages are dropped from replay window
+    ws.send(r#"["#ack:2"]"#);
+    ws.send(r#"["#replay:0"]"#);
+    assert_eq!(ws.recv_text(&mut srv), "a[\"3:c\"]");
+    ws.send(r#"["#ack:3"]"#);
+    ws.send(r#"["#replay:0", "d"]"#);
+    assert_eq!(ws.recv_text(&mut srv), "a[\"4:d\"]");
+}
+
+#[test]
+fn test_websocket_close() {
+    let mut srv = serve();
+    let mut ws = WsConn::new(&mut srv, &format!("{}/websocket", session_url("close")));
+    assert_eq!(ws.recv_text(&mut srv), "o");
+    assert_eq!(ws.recv_text(&mut srv), "c[3000,\"Go away!\"]");
+    assert!(ws.closed(&mut srv));
+}
+
+// When user sends broken data - broken JSON for example, the
+// server must abruptly terminate the ws connection.
+#[test]
+fn test_websocket_broken_json() {
+    let mut srv = serve();
+    let mut ws = WsConn::new(&mut srv, &format!("{}/websocket", session_url("echo")));
+    assert_eq!(ws.recv_text(&mut srv), "o");
+    ws.send(r#"["a"#);
+    assert!(ws.closed(&mut srv));
+}
+
+// As a fun part, Firefox 6.0.2 supports Websockets protocol '7'. But,
+// it doesn't send a normal 'Connection: Upgrade' header. Instead it
+// sends: 'Connection: keep-alive, Upgrade'. Brilliant.
+#[test]
+fn test_websocket_firefox_602_connection_header() {
+    let srv = serve();
+    let mut c = RawHttp::new(&srv);
+    let r = c.request("GET", &format!("{}/websocket", session_url("echo")), "1.1",
+                      &[("Upgrade", "websocket"),
+                        ("Connection", "keep-alive, Upgrade"),
+                        ("Sec-WebSocket-Version", "7"),
+                        ("Sec-WebSocket-Origin", "http://asd"),
+                        ("Sec-WebSocket-Key", "x3JJHMbDL1EzLkh9GBhXDw==")]);
+    assert_eq!(r.status, 101);
+}
+
+// XhrPolling: `/*/*/xhr`, `/*/*/xhr_send`
+// ---------------------------------------
+
+// The transport must support CORS requests, and answer correctly
+// to OPTIONS requests.
+#[test]
+fn test_xhr_options() {
+    let mut srv = serve();
+    for suffix in &["/xhr", "/xhr_send"] {
+        verify_options(&mut srv, &format!("/echo/abc/abc{}", suffix), "OPTIONS, POST");
+    }
+}
+
+// Test the transport itself.
+#[test]
+fn test_xhr_transport() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let r = request(&mut srv, Method::POST, &format!("{}/xhr", url), &[("Origin", "test")], None);
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "o\n");
+    verify_content_type(&r.headers, "application/javascript;charset=UTF-8");
+    verify_cors(&r.headers, Some("test"));
+    // iOS 6 caches POSTs. Make sure we send no-cache header.
+    verify_not_cached(&r.headers);
+
+    // Xhr transports receive json-encoded array of messages.
+    let r = request(&mut srv, Method::POST, &format!("{}/xhr_send", url),
+                    &[("Origin", "test")], Some(r#"["x"]"#));
+    assert_eq!(r.status, StatusCode::NO_CONTENT);
+    assert!(r.body.is_empty());
+    // The content type of `xhr_send` must be set to `text/plain`,
+    // even though the response code is `204`.
+    verify_content_type(&r.headers, "text/plain;charset=UTF-8");
+    verify_cors(&r.headers, Some("test"));
+    verify_not_cached(&r.headers);
+
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "a[\"x\"]\n");
+}
+
+// Publishing messages to a non-existing session must result in
+// a 404 error.
+#[test]
+fn test_xhr_invalid_session() {
+    let mut srv = serve();
+    let r = post(&mut srv, &format!("{}/xhr_send", session_url("echo")), Some(r#"["x"]"#));
+    assert_eq!(r.status, StatusCode::NOT_FOUND);
+}
+
+// The server must behave when invalid json data is sent or when no
+// json data is sent at all.
+#[test]
+fn test_xhr_invalid_json() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "o\n");
+
+    let r = post(&mut srv, &format!("{}/xhr_send", url), Some(r#"["x"#));
+    assert_eq!(r.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(r.body.contains("Broken JSON encoding."));
+
+    let r = post(&mut srv, &format!("{}/xhr_send", url), Some(""));
+    assert_eq!(r.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(r.body.contains("Payload expected."));
+
+    let r = post(&mut srv, &format!("{}/xhr_send", url), Some(r#"["a"]"#));
+    assert!(r.body.is_empty());
+    assert_eq!(r.status, StatusCode::NO_CONTENT);
+
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.body, "a[\"a\"]\n");
+    assert_eq!(r.status, StatusCode::OK);
+}
+
+// The server must accept messages sent with different content
+// types.
+#[test]
+fn test_xhr_content_types() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.body, "o\n");
+
+    let ctypes = ["text/plain", "T", "application/json", "application/xml", "",
+                  "application/json; charset=utf-8", "text/xml; charset=utf-8", "text/xml"];
+    for &ct in &ctypes {
+        let r = request(&mut srv, Method::POST, &format!("{}/xhr_send", url),
+                        &[("Content-Type", ct)], Some(r#"["a"]"#));
+        assert_eq!(r.status, StatusCode::NO_CONTENT);
+        assert!(r.body.is_empty());
+    }
+
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, format!("a[{}]\n", vec![r#""a""#; ctypes.len()].join(",")));
+}
+
+// When client sends a CORS request with
+// 'Access-Control-Request-Headers' header set, the server must
+// echo back this header as 'Access-Control-Allow-Headers'.
+#[test]
+fn test_xhr_request_headers_cors() {
+    let mut srv = serve();
+    let r = request(&mut srv, Method::OPTIONS, &format!("{}/xhr", session_url("echo")),
+                    &[("Origin", "test"),
+                      ("Access-Control-Request-Method", "POST"),
+                      ("Access-Control-Request-Headers", "a, b, c")], None);
+    assert!(r.status == StatusCode::NO_CONTENT || r.status == StatusCode::OK);
+    verify_cors(&r.headers, Some("test"));
+    assert_eq!(r.header("access-control-allow-headers"), Some("a, b, c"));
+
+    let r = request(&mut srv, Method::OPTIONS, &format!("{}/xhr", session_url("echo")),
+                    &[("Origin", "test"),
+                      ("Access-Control-Request-Method", "POST"),
+                      ("Access-Control-Request-Headers", "")], None);
+    assert!(r.status == StatusCode::NO_CONTENT || r.status == StatusCode::OK);
+    verify_cors(&r.headers, Some("test"));
+    assert!(r.header("access-control-allow-headers").map(|h| h.is_empty()).unwrap_or(true));
+
+    let r = request(&mut srv, Method::OPTIONS, &format!("{}/xhr", session_url("echo")),
+                    &[("Origin", "test"), ("Access-Control-Request-Method", "POST")], None);
+    assert!(r.status == StatusCode::NO_CONTENT || r.status == StatusCode::OK);
+    verify_cors(&r.headers, Some("test"));
+    assert!(r.header("access-control-allow-headers").is_none());
+}
+
+// The client must be able to send frames containint no messages to
+// the server. This is used as a heartbeat mechanism.
+#[test]
+fn test_xhr_sending_empty_frame() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "o\n");
+
+    // Sending empty frames with no data must allowed.
+    let r = post(&mut srv, &format!("{}/xhr_send", url), Some("[]"));
+    assert_eq!(r.status, StatusCode::NO_CONTENT);
+
+    let r = post(&mut srv, &format!("{}/xhr_send", url), Some(r#"["a"]"#));
+    assert_eq!(r.status, StatusCode::NO_CONTENT);
+
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.body, "a[\"a\"]\n");
+    assert_eq!(r.status, StatusCode::OK);
+}
+
+// XhrStreaming: `/*/*/xhr_streaming`
+// ----------------------------------
+
+/// Xhr streaming prelude, 2KiB of `h` bytes
+fn xhr_streaming_prelude() -> String {
+    format!("{}\n", "h".repeat(2048))
+}
+
+#[test]
+fn test_xhr_streaming_options() {
+    let mut srv = serve();
+    verify_options(&mut srv, "/echo/abc/abc/xhr_streaming", "OPTIONS, POST");
+}
+
+#[test]
+fn test_xhr_streaming_transport() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let mut r = Streaming::new(&mut srv, Method::POST, &format!("{}/xhr_streaming", url),
+                               &[("Origin", "test")]);
+    assert_eq!(r.status, StatusCode::OK);
+    verify_content_type(&r.headers, "application/javascript;charset=UTF-8");
+    verify_cors(&r.headers, Some("test"));
+    // iOS 6 caches POSTs. Make sure we send no-cache header.
+    verify_not_cached(&r.headers);
+
+    // The transport must first send 2KiB of `h` bytes as prelude.
+    r.expect(&mut srv, &xhr_streaming_prelude());
+    r.expect(&mut srv, "o\n");
+
+    let r1 = post(&mut srv, &format!("{}/xhr_send", url), Some(r#"["x"]"#));
+    assert_eq!(r1.status, StatusCode::NO_CONTENT);
+    assert!(r1.body.is_empty());
+
+    r.expect(&mut srv, "a[\"x\"]\n");
+}
+
+// Single streaming request will buffer all data until closed. The
+// test server closes streaming request after 4096 bytes were sent
+// (including framing).
+#[test]
+fn test_xhr_streaming_response_limit() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let mut r = Streaming::new(&mut srv, Method::POST, &format!("{}/xhr_streaming", url), &[]);
+    assert_eq!(r.status, StatusCode::OK);
+    r.expect(&mut srv, &xhr_streaming_prelude());
+    r.expect(&mut srv, "o\n");
+
+    let msg = format!("\"{}\"", "x".repeat(128));
+    for _ in 0..31 {
+        let r1 = post(&mut srv, &format!("{}/xhr_send", url), Some(&format!("[{}]", msg)));
+        assert_eq!(r1.status, StatusCode::NO_CONTENT);
+        r.expect(&mut srv, &format!("a[{}]\n", msg));
+    }
+
+    // The connection should be closed after enough data was
+    // delivered.
+    assert!(r.eof(&mut srv));
+}
+
+// EventSource: `/*/*/eventsource`
+// -------------------------------
+
+#[test]
+fn test_eventsource_transport() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let mut r = Streaming::new(&mut srv, Method::GET, &format!("{}/eventsource", url), &[]);
+    assert_eq!(r.status, StatusCode::OK);
+    verify_content_type(&r.headers, "text/event-stream");
+    // As EventSource is requested using GET we must be very
+    // careful not to allow it being cached.
+    verify_not_cached(&r.headers);
+
+    // The transport must first send a new line prelude, due to a
+    // bug in Opera.
+    r.expect(&mut srv, "\r\n");
+    r.expect(&mut srv, "data: o\r\n\r\n");
+
+    let r1 = post(&mut srv, &format!("{}/xhr_send", url), Some(r#"["x"]"#));
+    assert!(r1.body.is_empty());
+    assert_eq!(r1.status, StatusCode::NO_CONTENT);
+    r.expect(&mut srv, "data: a[\"x\"]\r\n\r\n");
+
+    // This protocol doesn't allow binary data and we need to
+    // specially treat leading space, new lines and things like
+    // \x00. But, now the protocol json-encodes everything.
+    let r1 = post(&mut srv, &format!("{}/xhr_send", url), Some(r#"["  \u0000\n\r "]"#));
+    assert!(r1.body.is_empty());
+    assert_eq!(r1.status, StatusCode::NO_CONTENT);
+    r.expect(&mut srv, "data: a[\"  \\u0000\\n\\r \"]\r\n\r\n");
+}
+
+#[test]
+fn test_eventsource_response_limit() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let mut r = Streaming::new(&mut srv, Method::GET, &format!("{}/eventsource", url), &[]);
+    assert_eq!(r.status, StatusCode::OK);
+    r.expect(&mut srv, "\r\n");
+    r.expect(&mut srv, "data: o\r\n\r\n");
+
+    let msg = format!("\"{}\"", "x".repeat(4096));
+    let r1 = post(&mut srv, &format!("{}/xhr_send", url), Some(&format!("[{}]", msg)));
+    assert_eq!(r1.status, StatusCode::NO_CONTENT);
+    r.expect(&mut srv, &format!("data: a[{}]\r\n\r\n", msg));
+
+    // The connection should be closed after enough data was
+    // delivered.
+    assert!(r.eof(&mut srv));
+}
+
+// HtmlFile: `/*/*/htmlfile`
+// -------------------------
+
+const HTMLFILE_HEAD: &str = r#"<!doctype html>
+<html><head>
+  <meta http-equiv="X-UA-Compatible" content="IE=edge" />
+  <meta http-equiv="Content-Type" content="text/html; charset=UTF-8" />
+</head><body><h2>Don't panic!</h2>
+  <script>
+    document.domain = document.domain;
+    var c = parent.callback;
+    c.start();
+    function p(d) {c.message(d);};
+    window.onload = function() {c.stop();};
+  </script>"#;
+
+/// Read htmlfile prelude, head is padded with at least 1KiB of spaces
+fn htmlfile_prelude(srv: &mut TestServer, r: &mut Streaming) {
+    let prelude = r.read(srv, HTMLFILE_HEAD.len() + 1 + 1024);
+    assert_eq!(prelude.trim(), HTMLFILE_HEAD);
+    assert!(prelude.len() > 1024);
+}
+
+#[test]
+fn test_htmlfile_transport() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let path = format!("{}/htmlfile?c=%63allback", url);
+    let mut r = Streaming::new(&mut srv, Method::GET, &path, &[]);
+    assert_eq!(r.status, StatusCode::OK);
+    verify_content_type(&r.headers, "text/html;charset=UTF-8");
+    // As HtmlFile is requested using GET we must be very careful
+    // not to allow it being cached.
+    verify_not_cached(&r.headers);
+
+    htmlfile_prelude(&mut srv, &mut r);
+    r.expect(&mut srv, "<script>\np(\"o\");\n</script>\r\n");
+
+    let r1 = post(&mut srv, &format!("{}/xhr_send", url), Some(r#"["x"]"#));
+    assert!(r1.body.is_empty());
+    assert_eq!(r1.status, StatusCode::NO_CONTENT);
+    r.expect(&mut srv, "<script>\np(\"a[\\\"x\\\"]\");\n</script>\r\n");
+}
+
+#[test]
+fn test_htmlfile_no_callback() {
+    let mut srv = serve();
+    let r = get(&mut srv, "/echo/a/a/htmlfile");
+    assert_eq!(r.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(r.body.contains("\"callback\" parameter required"));
+}
+
+// Supplying invalid characters to callback parameter is invalid
+// and must result in a 500 errors. Invalid characters are any
+// matching the following regexp: `[^a-zA-Z0-9-_.]`
+#[test]
+fn test_htmlfile_invalid_callback() {
+    let mut srv = serve();
+    for callback in &["%20", "*", "abc(", "abc%28"] {
+        let r = get(&mut srv, &format!("/echo/a/a/htmlfile?c={}", callback));
+        assert_eq!(r.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(r.body.contains("invalid \"callback\" parameter"));
+    }
+}
+
+#[test]
+fn test_htmlfile_response_limit() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let mut r = Streaming::new(&mut srv, Method::GET, &format!("{}/htmlfile?c=callback", url), &[]);
+    assert_eq!(r.status, StatusCode::OK);
+    htmlfile_prelude(&mut srv, &mut r);
+    r.expect(&mut srv, "<script>\np(\"o\");\n</script>\r\n");
+
+    let msg = "x".repeat(4096);
+    let r1 = post(&mut srv, &format!("{}/xhr_send", url), Some(&format!("[\"{}\"]", msg)));
+    assert_eq!(r1.status, StatusCode::NO_CONTENT);
+    r.expect(&mut srv, &format!("<script>\np(\"a[\\\"{}\\\"]\");\n</script>\r\n", msg));
+
+    // The connection should be closed after enough data was
+    // delivered.
+    assert!(r.eof(&mut srv));
+}
+
+// JsonpPolling: `/*/*/jsonp`, `/*/*/jsonp_send`
+// ---------------------------------------------
+
+#[test]
+fn test_jsonp_transport() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let r = get(&mut srv, &format!("{}/jsonp?c=%63allback", url));
+    assert_eq!(r.status, StatusCode::OK);
+    verify_content_type(&r.headers, "application/javascript;charset=UTF-8");
+    // As JsonPolling is requested using GET we must be very
+    // careful not to allow it being cached.
+    verify_not_cached(&r.headers);
+    assert_eq!(r.body, "/**/callback(\"o\");\r\n");
+
+    let r = jsonp_send(&mut srv, &format!("{}/jsonp_send", url), "d=%5B%22x%22%5D");
+    // Konqueror does weird things on 204. As a workaround we need
+    // to respond with something - let it be the string `ok`.
+    assert_eq!(r.body, "ok");
+    assert_eq!(r.status, StatusCode::OK);
+    verify_content_type(&r.headers, "text/plain;charset=UTF-8");
+    // iOS 6 caches POSTs. Make sure we send no-cache header.
+    verify_not_cached(&r.headers);
+
+    let r = get(&mut srv, &format!("{}/jsonp?c=%63allback", url));
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "/**/callback(\"a[\\\"x\\\"]\");\r\n");
+}
+
+#[test]
+fn test_jsonp_no_callback() {
+    let mut srv = serve();
+    let r = get(&mut srv, "/echo/a/a/jsonp");
+    assert_eq!(r.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(r.body.contains("\"callback\" parameter required"));
+}
+
+// Supplying invalid characters to callback parameter is invalid
+// and must result in a 500 errors. Invalid characters are any
+// matching the following regexp: `[^a-zA-Z0-9-_.]`
+#[test]
+fn test_jsonp_invalid_callback() {
+    let mut srv = serve();
+    for callback in &["%20", "*", "abc(", "abc%28"] {
+        let r = get(&mut srv, &format!("/echo/a/a/jsonp?c={}", callback));
+        assert_eq!(r.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(r.body.contains("invalid \"callback\" parameter"));
+    }
+}
+
+// The server must behave when invalid json data is sent or when no
+// json data is sent at all.
+#[test]
+fn test_jsonp_invalid_json() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let r = get(&mut srv, &format!("{}/jsonp?c=x", url));
+    assert_eq!(r.body, "/**/x(\"o\");\r\n");
+
+    let r = jsonp_send(&mut srv, &format!("{}/jsonp_send", url), "d=%5B%22x");
+    assert_eq!(r.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(r.body.contains("Broken JSON encoding."));
+
+    for data in &["", "d=", "p=p"] {
+        let r = jsonp_send(&mut srv, &format!("{}/jsonp_send", url), data);
+        assert_eq!(r.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(r.body.contains("Payload expected."));
+    }
+
+    let r = jsonp_send(&mut srv, &format!("{}/jsonp_send", url), "d=%5B%22b%22%5D");
+    assert_eq!(r.body, "ok");
+
+    let r = get(&mut srv, &format!("{}/jsonp?c=x", url));
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "/**/x(\"a[\\\"b\\\"]\");\r\n");
+}
+
+// The server must accept messages sent with different content
+// types.
+#[test]
+fn test_jsonp_content_types() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let r = get(&mut srv, &format!("{}/jsonp?c=x", url));
+    assert_eq!(r.body, "/**/x(\"o\");\r\n");
+
+    let r = jsonp_send(&mut srv, &format!("{}/jsonp_send", url), "d=%5B%22abc%22%5D");
+    assert_eq!(r.body, "ok");
+    let r = request(&mut srv, Method::POST, &format!("{}/jsonp_send", url),
+                    &[("Content-Type", "text/plain")], Some(r#"["%61bc"]"#));
+    assert_eq!(r.body, "ok");
+
+    let r = get(&mut srv, &format!("{}/jsonp?c=x", url));
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "/**/x(\"a[\\\"abc\\\",\\\"%61bc\\\"]\");\r\n");
+}
+
+#[test]
+fn test_jsonp_close() {
+    let mut srv = serve();
+    let url = session_url("close");
+    let r = get(&mut srv, &format!("{}/jsonp?c=x", url));
+    assert_eq!(r.body, "/**/x(\"o\");\r\n");
+
+    for _ in 0..2 {
+        let r = get(&mut srv, &format!("{}/jsonp?c=x", url));
+        assert_eq!(r.body, "/**/x(\"c[3000,\\\"Go away!\\\"]\");\r\n");
+    }
+}
+
+#[test]
+fn test_jsonp_sending_empty_frame() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let r = get(&mut srv, &format!("{}/jsonp?c=x", url));
+    assert_eq!(r.body, "/**/x(\"o\");\r\n");
+
+    // Sending frames containing no messages must be allowed.
+    let r = jsonp_send(&mut srv, &format!("{}/jsonp_send", url), "d=%5B%5D");
+    assert_eq!(r.body, "ok");
+
+    let r = jsonp_send(&mut srv, &format!("{}/jsonp_send", url), "d=%5B%22x%22%5D");
+    assert_eq!(r.body, "ok");
+
+    let r = get(&mut srv, &format!("{}/jsonp?c=x", url));
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "/**/x(\"a[\\\"x\\\"]\");\r\n");
+}
+
+// JSESSIONID cookie
+// -----------------
+
+// Helper to check cookie validity.
+fn verify_cookie(headers: &HeaderMap, value: &str) {
+    let cookie = header(headers, "set-cookie").unwrap();
+    let mut parts = cookie.split(';');
+    assert_eq!(parts.next().unwrap().trim(), format!("JSESSIONID={}", value));
+    assert_eq!(parts.next().unwrap().trim().to_lowercase(), "path=/");
+}
+
+// Verify if info has cookie_needed set.
+#[test]
+fn test_cookie_basic() {
+    let mut srv = serve();
+    let r = get(&mut srv, "/cookie_needed_echo/info");
+    assert_eq!(r.status, StatusCode::OK);
+    verify_no_cookie(&r);
+
+    let data: serde_json::Value = serde_json::from_str(&r.body).unwrap();
+    assert_eq!(data["cookie_needed"], true);
+}
+
+// JSESSIONID cookie must be set by default
+#[test]
+fn test_cookie_xhr() {
+    let mut srv = serve();
+    // polling url must set cookies
+    let r = post(&mut srv, &format!("{}/xhr", session_url("cookie_needed_echo")), None);
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "o\n");
+    verify_cookie(&r.headers, "dummy");
+
+    // Cookie must be echoed back if it's already set.
+    let r = request(&mut srv, Method::POST, &format!("{}/xhr", session_url("cookie_needed_echo")),
+                    &[("Cookie", "JSESSIONID=abcdef")], None);
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "o\n");
+    verify_cookie(&r.headers, "abcdef");
+}
+
+#[test]
+fn test_cookie_xhr_streaming() {
+    let mut srv = serve();
+    let path = format!("{}/xhr_streaming", session_url("cookie_needed_echo"));
+    let r = Streaming::new(&mut srv, Method::POST, &path, &[]);
+    assert_eq!(r.status, StatusCode::OK);
+    verify_cookie(&r.headers, "dummy");
+}
+
+#[test]
+fn test_cookie_eventsource() {
+    let mut srv = serve();
+    let path = format!("{}/eventsource", session_url("cookie_needed_echo"));
+    let r = Streaming::new(&mut srv, Method::GET, &path, &[]);
+    assert_eq!(r.status, StatusCode::OK);
+    verify_cookie(&r.headers, "dummy");
+}
+
+#[test]
+fn test_cookie_htmlfile() {
+    let mut srv = serve();
+    let path = format!("{}/htmlfile?c=%63allback", session_url("cookie_needed_echo"));
+    let r = Streaming::new(&mut srv, Method::GET, &path, &[]);
+    assert_eq!(r.status, StatusCode::OK);
+    verify_cookie(&r.headers, "dummy");
+}
+
+#[test]
+fn test_cookie_jsonp() {
+    let mut srv = serve();
+    let url = session_url("cookie_needed_echo");
+    let r = get(&mut srv, &format!("{}/jsonp?c=%63allback", url));
+    assert_eq!(r.status, StatusCode::OK);
+    verify_cookie(&r.headers, "dummy");
+    assert_eq!(r.body, "/**/callback(\"o\");\r\n");
+
+    let r = jsonp_send(&mut srv, &format!("{}/jsonp_send", url), "d=%5B%22x%22%5D");
+    assert_eq!(r.body, "ok");
+    assert_eq!(r.status, StatusCode::OK);
+    verify_cookie(&r.headers, "dummy");
+}
+
+// Raw WebSocket url: `/websocket`
+// -------------------------------
+
+#[test]
+fn test_raw_websocket_transport() {
+    let mut srv = serve();
+    let mut ws = WsConn::new(&mut srv, "/echo/websocket");
+    ws.send("Hello world!\u{ffff}");
+    assert_eq!(ws.recv_text(&mut srv), "Hello world!\u{ffff}");
+}
+
+// Text and binary messages get delivered to the session in order.
+#[test]
+fn test_raw_websocket_binary() {
+    let srv = serve();
+    let mut ws = RawWs::new(&srv, "/echo/websocket", &[]);
+    ws.text("a");
+    ws.write(0x82, b"b");
+    ws.text("c");
+    ws.write(0x82, b"d");
+    for msg in &["a", "b", "c", "d"] {
+        assert_eq!(ws.read_text(), *msg);
+    }
+}
+
+// Disabling websocket disables raw websocket as well.
+#[test]
+fn test_raw_websocket_disabled() {
+    let srv = serve();
+    for path in &["/disabled_websocket_echo/websocket",
+                  "/disabled_websocket_echo/000/disabled/websocket"] {
+        let mut c = RawHttp::new(&srv);
+        let r = c.request("GET", path, "1.1",
+                          &[("Upgrade", "websocket"),
+                            ("Connection", "Upgrade"),
+                            ("Sec-WebSocket-Version", "13"),
+                            ("Sec-WebSocket-Key", "x3JJHMbDL1EzLkh9GBhXDw==")]);
+        assert_eq!(r.status, 404);
+    }
+}
+
+// Deprecated `disable_transports()` ignores unknown transport names
+#[test]
+#[allow(deprecated)]
+fn test_disable_transports_unknown() {
+    let (_, echo) = serve_echo();
+    let mut srv = TestServer::with_factory(move || {
+        App::new().handler(
+            "/legacy", SockJS::new(echo.clone()).disable_transports(vec!["xhr", "unknown"]))
+    });
+    let url = session_url("legacy");
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.status, StatusCode::NOT_FOUND);
+    let r = get(&mut srv, &format!("{}/jsonp?c=x", url));
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "/**/x(\"o\");\r\n");
+}
+
+// Server closes raw websocket with "Go away!" reason. Websocket client
+// does not expose close reason, so close frame is read from tcp stream.
+#[test]
+fn test_raw_websocket_close() {
+    let srv = serve();
+    let mut c = RawHttp::new(&srv);
+    let r = c.request("GET", "/close/websocket", "1.1",
+                      &[("Upgrade", "websocket"),
+                        ("Connection", "Upgrade"),
+                        ("Sec-WebSocket-Version", "13"),
+                        ("Sec-WebSocket-Key", "x3JJHMbDL1EzLkh9GBhXDw==")]);
+    assert_eq!(r.status, 101);
+
+    // close opcode, unmasked payload: status code and reason
+    let head = c.read_bytes(2);
+    assert_eq!(head[0] & 0x0f, 0x8);
+    let payload = c.read_bytes(head[1] as usize);
+    assert_eq!(u16::from(payload[0]) << 8 | u16::from(payload[1]), 1000);
+    assert_eq!(&payload[2..], b"Go away!");
+}
+
+// Size limits
+// ===========
+
+// Messages bigger than `max_message_size` are refused, whole request
+// is refused even if other messages fit.
+#[test]
+fn test_message_size_xhr_send() {
+    let mut srv = serve();
+    let url = session_url("limited_echo");
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.body, "o\n");
+
+    let r = post(&mut srv, &format!("{}/xhr_send", url),
+                 Some(r#"["a", "0123456789abcdefg"]"#));
+    assert_eq!(r.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    // message of exactly max size is accepted
+    let r = post(&mut srv, &format!("{}/xhr_send", url), Some(r#"["0123456789abcdef"]"#));
+    assert_eq!(r.status, StatusCode::NO_CONTENT);
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.body, "a[\"0123456789abcdef\"]\n");
+}
+
+#[test]
+fn test_message_size_jsonp_send() {
+    let mut srv = serve();
+    let url = session_url("limited_echo");
+    let r = get(&mut srv, &format!("{}/jsonp?c=x", url));
+    assert_eq!(r.body, "/**/x(\"o\");\r\n");
+
+    let r = jsonp_send(&mut srv, &format!("{}/jsonp_send", url),
+                       "d=%5B%220123456789abcdefg%22%5D");
+    assert_eq!(r.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    let r = jsonp_send(&mut srv, &format!("{}/jsonp_send", url), "d=%5B%22a%22%5D");
+    assert_eq!(r.status, StatusCode::OK);
+    assert_eq!(r.body, "ok");
+}
+
+// Websocket get closed with 1009 status code
+#[test]
+fn test_message_size_websocket() {
+    let srv = serve();
+    let mut ws = RawWs::new(&srv, &format!("{}/websocket", session_url("limited_echo")), &[]);
+    assert_eq!(ws.read_text(), "o");
+    ws.text(r#"["0123456789abcdef"]"#);
+    assert_eq!(ws.read_text(), "a[\"0123456789abcdef\"]");
+    ws.text(r#"["0123456789abcdefg"]"#);
+    assert_eq!(ws.read_close(), 1009);
+    assert!(ws.read().is_none());
+}
+
+// Default frame limit fits message of default message limit
+#[test]
+fn test_default_limits_websocket() {
+    let srv = serve();
+    let mut ws = RawWs::new(&srv, &format!("{}/websocket", session_url("echo")), &[]);
+    assert_eq!(ws.read_text(), "o");
+    let msg = "a".repeat(131_072);
+    ws.text(&format!("[\"{}\"]", msg));
+    assert_eq!(ws.read_text(), format!("a[\"{}\"]", msg));
+    ws.text(&format!("[\"{}a\"]", msg));
+    assert_eq!(ws.read_close(), 1009);
+}
+
+// Request bodies bigger than `max_body_size` are refused
+#[test]
+fn test_body_size() {
+    let mut srv = serve();
+    let url = session_url("limited_echo");
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.body, "o\n");
+
+    let body = format!("[{}\"a\"]", "\"a\", ".repeat(12));
+    assert!(body.len() > 64);
+    let r = post(&mut srv, &format!("{}/xhr_send", url), Some(&body));
+    assert_eq!(r.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    let r = jsonp_send(&mut srv, &format!("{}/jsonp_send", url), &format!("d={}", body));
+    assert_eq!(r.status, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+// Websocket get closed if frame is bigger than `max_frame_size`
+#[test]
+fn test_frame_size_websocket() {
+    let srv = serve();
+    let mut ws = RawWs::new(&srv, &format!("{}/websocket", session_url("limited_echo")), &[]);
+    assert_eq!(ws.read_text(), "o");
+    ws.text(&format!("[{}\"a\"]", "\"a\", ".repeat(12)));
+    match ws.read() {
+        None | Some((0x88, _)) => (),
+        frame => panic!("Connection must be closed: {:?}", frame),
+    }
+}
+
+// Websocket framing
+// =================
+
+/// Compress message with permessage-deflate, sync flush tail is stripped
+fn deflate(c: &mut Compress, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 64);
+    c.compress_vec(data, &mut out, FlushCompress::Sync).unwrap();
+    assert!(out.ends_with(&[0, 0, 0xff, 0xff]));
+    let len = out.len() - 4;
+    out.truncate(len);
+    out
+}
+
+fn inflate(d: &mut Decompress, data: &[u8]) -> Vec<u8> {
+    let mut input = data.to_vec();
+    input.extend_from_slice(&[0, 0, 0xff, 0xff]);
+    let mut out = Vec::with_capacity(65_536);
+    d.decompress_vec(&input, &mut out, FlushDecompress::Sync).unwrap();
+    out
+}
+
+/// Extension negotiated for the offer
+fn negotiate(srv: &TestServer, service: &str, offer: &str) -> Option<String> {
+    RawWs::new(srv, &format!("/{}/websocket", service),
+               &[("Sec-WebSocket-Extensions", offer)]).extensions
+}
+
+#[test]
+fn test_websocket_deflate_negotiation() {
+    let srv = serve();
+    assert_eq!(negotiate(&srv, "deflate_echo", "permessage-deflate"),
+               Some("permessage-deflate".to_owned()));
+    assert_eq!(negotiate(&srv, "deflate_echo", "permessage-deflate; client_max_window_bits"),
+               Some("permessage-deflate".to_owned()));
+    assert_eq!(negotiate(&srv, "deflate_echo", "permessage-deflate; server_max_window_bits=10"),
+               Some("permessage-deflate; server_max_window_bits=10".to_owned()));
+    assert_eq!(negotiate(&srv, "deflate_echo", "permessage-deflate; server_no_context_takeover"),
+               Some("permessage-deflate; server_no_context_takeover".to_owned()));
+
+    // unacceptable offers get skipped
+    assert_eq!(negotiate(&srv, "deflate_echo", "permessage-deflate; foo, permessage-deflate"),
+               Some("permessage-deflate".to_owned()));
+    assert_eq!(negotiate(&srv, "deflate_echo", "permessage-deflate; server_max_window_bits=8"),
+               None);
+    assert_eq!(negotiate(&srv, "deflate_echo", "x-webkit-deflate-frame"), None);
+
+    // extension is not enabled
+    assert_eq!(negotiate(&srv, "echo", "permessage-deflate"), None);
+}
+
+// Fragmented messages, interleaved control frames and compressed payloads
+#[test]
+fn test_websocket_deflate_framing() {
+    let srv = serve();
+    let mut ws = RawWs::new(&srv, "/deflate_echo/websocket",
+                            &[("Sec-WebSocket-Extensions", "permessage-deflate")]);
+    assert!(ws.extensions.is_some());
+
+    // fragmented message with ping between fragments
+    ws.write(0x01, b"frag");
+    ws.write(0x89, b"ping");
+    assert_eq!(ws.read(), Some((0x8a, b"ping".to_vec())));
+    ws.write(0x00, b"men");
+    ws.write(0x80, b"ted");
+    assert_eq!(ws.read_text(), "fragmented");
+
+    // compressed message, short reply is not compressed
+    let mut c = Compress::new(Compression::default(), false);
+    let data = deflate(&mut c, b"compressed");
+    ws.write(0xc1, &data);
+    assert_eq!(ws.read_text(), "compressed");
+
+    // compressed fragmented message, only first frame has RSV1 bit
+    let data = deflate(&mut c, b"compressed and fragmented");
+    ws.write(0x41, &data[..2]);
+    ws.write(0x80, &data[2..]);
+    assert_eq!(ws.read_text(), "compressed and fragmented");
+
+    // long reply is compressed
+    let long = "x".repeat(512);
+    ws.text(&long);
+    let mut d = Decompress::new(false);
+    match ws.read() {
+        Some((0xc1, payload)) => assert_eq!(inflate(&mut d, &payload), long.as_bytes()),
+        frame => panic!("Compressed text frame expected: {:?}", frame),
+    }
+
+    // close frame is echoed
+    ws.close(1000);
+    assert_eq!(ws.read_close(), 1000);
+    assert!(ws.read().is_none());
+}
+
+// Uncompressed connections accept fragmented messages too
+#[test]
+fn test_websocket_fragments() {
+    let srv = serve();
+    let mut ws = RawWs::new(&srv, "/echo/websocket", &[]);
+    assert!(ws.extensions.is_none());
+    ws.write(0x01, b"frag");
+    ws.write(0x89, b"ping");
+    assert_eq!(ws.read(), Some((0x8a, b"ping".to_vec())));
+    ws.write(0x00, b"men");
+    ws.write(0x80, b"ted");
+    assert_eq!(ws.read_text(), "fragmented");
+
+    let mut ws = RawWs::new(&srv, &format!("{}/websocket", session_url("echo")), &[]);
+    assert_eq!(ws.read_text(), "o");
+    ws.write(0x01, b"[\"a");
+    ws.write(0x80, b"b\"]");
+    assert_eq!(ws.read_text(), "a[\"ab\"]");
+}
+
+// Corrupt compressed data is invalid payload, not too large message
+#[test]
+fn test_websocket_corrupt_deflate() {
+    let srv = serve();
+    let mut ws = RawWs::new(&srv, "/deflate_echo/websocket",
+                            &[("Sec-WebSocket-Extensions", "permessage-deflate")]);
+    assert!(ws.extensions.is_some());
+    ws.write(0xc1, &[0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(ws.read_close(), 1007);
+    assert!(ws.read().is_none());
+}
+
+// Peer gets close frame with 1002 status code on protocol errors
+#[test]
+fn test_websocket_protocol_errors() {
+    let srv = serve();
+    let frames: Vec<Vec<(u8, Vec<u8>)>> = vec![
+        // control frame bigger than 125 bytes
+        vec![(0x89, vec![b'p'; 126])],
+        // fragmented control frame
+        vec![(0x09, b"ping".to_vec())],
+        // continuation without first frame
+        vec![(0x80, b"a".to_vec())],
+        // new message before fragmented message is finished
+        vec![(0x01, b"a".to_vec()), (0x81, b"b".to_vec())],
+        // reserved bits
+        vec![(0xa1, b"a".to_vec())],
+    ];
+    for service in &["echo", "deflate_echo"] {
+        for msgs in &frames {
+            let mut ws = RawWs::new(&srv, &format!("/{}/websocket", service),
+                                    &[("Sec-WebSocket-Extensions", "permessage-deflate")]);
+            for &(head, ref payload) in msgs {
+                ws.write(head, payload);
+            }
+            assert_eq!(ws.read_close(), 1002);
+            assert!(ws.read().is_none());
+        }
+    }
+
+    // unmasked frame
+    for service in &["echo", "deflate_echo"] {
+        let mut ws = RawWs::new(&srv, &format!("/{}/websocket", service),
+                                &[("Sec-WebSocket-Extensions", "permessage-deflate")]);
+        ws.c.stream.write_all(&[0x81, 0x01, b'a']).unwrap();
+        assert_eq!(ws.read_close(), 1002);
+        assert!(ws.read().is_none());
+    }
+}
+
+// Close frame of the peer is echoed by both transports
+#[test]
+fn test_websocket_close_echo() {
+    let srv = serve();
+    for service in &["echo", "deflate_echo"] {
+        let mut ws = RawWs::new(&srv, &format!("{}/websocket", session_url(service)), &[]);
+        assert_eq!(ws.read_text(), "o");
+        ws.close(1001);
+        assert_eq!(ws.read_close(), 1001);
+        assert!(ws.read().is_none());
+
+        let mut ws = RawWs::new(&srv, &format!("/{}/websocket", service), &[]);
+        ws.text("a");
+        assert_eq!(ws.read_text(), "a");
+        ws.close(1000);
+        assert_eq!(ws.read_close(), 1000);
+        assert!(ws.read().is_none());
+    }
+}
+
+// Streaming compression
+// =====================
+
+/// Decoder of compressed response body
+enum BodyDecoder {
+    Gzip(GzDecoder<Vec<u8>>),
+    Deflate(ZlibDecoder<Vec<u8>>),
+    Identity(Vec<u8>),
+}
+
+impl BodyDecoder {
+    fn new(encoding: Option<&str>) -> BodyDecoder {
+        match encoding {
+            Some("gzip") => BodyDecoder::Gzip(GzDecoder::new(Vec::new())),
+            Some("deflate") => BodyDecoder::Deflate(ZlibDecoder::new(Vec::new())),
+            None => BodyDecoder::Identity(Vec::new()),
+            Some(encoding) => panic!("Unexpected encoding: {}", encoding),
+        }
+    }
+
+    /// Decode chunk, returns body decoded so far
+    fn decode(&mut self, chunk: &[u8]) -> &[u8] {
+        match *self {
+            BodyDecoder::Gzip(ref mut dec) => {
+                dec.write_all(chunk).unwrap();
+                dec.flush().unwrap();
+                dec.get_ref()
+            }
+            BodyDecoder::Deflate(ref mut dec) => {
+                dec.write_all(chunk).unwrap();
+                dec.flush().unwrap();
+                dec.get_ref()
+            }
+            BodyDecoder::Identity(ref mut body) => {
+                body.extend_from_slice(chunk);
+                body
+            }
+        }
+    }
+}
+
+// Streaming responses get compressed with encoding accepted by the client,
+// gzip is preferred.
+#[test]
+fn test_streaming_compression() {
+    let srv = serve();
+    let cases = [
+        ("gzip", Some("gzip")),
+        ("deflate", Some("deflate")),
+        ("deflate, gzip", Some("gzip")),
+        ("gzip;q=0, deflate", Some("deflate")),
+        ("x-gzip", Some("gzip")),
+        ("br", None),
+    ];
+    for &(accept, encoding) in &cases {
+        let mut c = RawHttp::new(&srv);
+        let path = format!("{}/xhr_streaming", session_url("compressed_echo"));
+        let r = c.request("POST", &path, "1.1",
+                          &[("Accept-Encoding", accept), ("Content-Length", "0")]);
+        assert_eq!(r.status, 200);
+        assert_eq!(r.header("vary"), Some("Accept-Encoding"));
+        assert_eq!(r.header("content-encoding"), encoding, "{}", accept);
+
+        // prelude and open frame
+        let mut decoder = BodyDecoder::new(encoding);
+        loop {
+            let body = decoder.decode(&c.read_chunk_bytes()).to_vec();
+            if body.len() >= 2051 {
+                assert!(body[..2048].iter().all(|b| *b == b'h'));
+                assert_eq!(&body[2048..], b"\no\n");
+                break
+            }
+        }
+    }
+
+    // compression is not enabled
+    let mut c = RawHttp::new(&srv);
+    let path = format!("{}/xhr_streaming", session_url("echo"));
+    let r = c.request("POST", &path, "1.1",
+                      &[("Accept-Encoding", "gzip"), ("Content-Length", "0")]);
+    assert_eq!(r.status, 200);
+    assert_eq!(r.header("vary"), None);
+}
+
+// JSON Unicode Encoding
+// =====================
+
+// The browser must escape quite a list of chars, this is due to
+// browser mangling outgoing chars on transports like XHR.
+const ESCAPABLE_BY_CLIENT: &str = concat!(
+    r#"["\x{00}-\x{1f}\x{7f}-\x{9f}\x{00ad}\x{0600}-\x{0604}\x{070f}\x{17b4}\x{17b5}"#,
+    r#"\x{2000}-\x{20ff}\x{feff}\x{fff0}-\x{ffff}\x{00}-\x{1f}\x{fffe}\x{ffff}\x{0300}-"#,
+    r#"\x{0333}\x{033d}-\x{0346}\x{034a}-\x{034c}\x{0350}-\x{0352}\x{0357}-\x{0358}\x{035c}"#,
+    r#"-\x{0362}\x{0374}\x{037e}\x{0387}\x{0591}-\x{05af}\x{05c4}\x{0610}-\x{0617}\x{0653}-"#,
+    r#"\x{0654}\x{0657}-\x{065b}\x{065d}-\x{065e}\x{06df}-\x{06e2}\x{06eb}-\x{06ec}\x{0730}"#,
+    r#"\x{0732}-\x{0733}\x{0735}-\x{0736}\x{073a}\x{073d}\x{073f}-\x{0741}\x{0743}\x{0745}"#,
+    r#"\x{0747}\x{07eb}-\x{07f1}\x{0951}\x{0958}-\x{095f}\x{09dc}-\x{09dd}\x{09df}\x{0a33}"#,
+    r#"\x{0a36}\x{0a59}-\x{0a5b}\x{0a5e}\x{0b5c}-\x{0b5d}\x{0e38}-\x{0e39}\x{0f43}\x{0f4d}"#,
+    r#"\x{0f52}\x{0f57}\x{0f5c}\x{0f69}\x{0f72}-\x{0f76}\x{0f78}\x{0f80}-\x{0f83}\x{0f93}"#,
+    r#"\x{0f9d}\x{0fa2}\x{0fa7}\x{0fac}\x{0fb9}\x{1939}-\x{193a}\x{1a17}\x{1b6b}\x{1cda}-"#,
+    r#"\x{1cdb}\x{1dc0}-\x{1dcf}\x{1dfc}\x{1dfe}\x{1f71}\x{1f73}\x{1f75}\x{1f77}\x{1f79}"#,
+    r#"\x{1f7b}\x{1f7d}\x{1fbb}\x{1fbe}\x{1fc9}\x{1fcb}\x{1fd3}\x{1fdb}\x{1fe3}\x{1feb}"#,
+    r#"\x{1fee}-\x{1fef}\x{1ff9}\x{1ffb}\x{1ffd}\x{2000}-\x{2001}\x{20d0}-\x{20d1}\x{20d4}-"#,
+    r#"\x{20d7}\x{20e7}-\x{20e9}\x{2126}\x{212a}-\x{212b}\x{2329}-\x{232a}\x{2adc}\x{302b}-"#,
+    r#"\x{302c}\x{aab2}-\x{aab3}\x{f900}-\x{fa0d}\x{fa10}\x{fa12}\x{fa15}-\x{fa1e}\x{fa20}"#,
+    r#"\x{fa22}\x{fa25}-\x{fa26}\x{fa2a}-\x{fa2d}\x{fa30}-\x{fa6d}\x{fa70}-\x{fad9}\x{fb1d}"#,
+    r#"\x{fb1f}\x{fb2a}-\x{fb36}\x{fb38}-\x{fb3c}\x{fb3e}\x{fb40}-\x{fb41}\x{fb43}-\x{fb44}"#,
+    r#"\x{fb46}-\x{fb4e}]"#,
+);
+
+// The server is able to send much more chars verbatim. But, it can't
+// send Unicode surrogates over Websockets, also various \u2xxxx chars
+// get mangled.
+const ESCAPABLE_BY_SERVER: &str =
+    r"[\x{00}-\x{1f}\x{200c}-\x{200f}\x{2028}-\x{202f}\x{2060}-\x{206f}\x{fff0}-\x{ffff}]";
+
+/// Json string of `\uXXXX` escapes of all chars in range matching the pattern
+fn killer_string_esc(pattern: &str, start: u32) -> String {
+    let re = Regex::new(pattern).unwrap();
+    let mut esc = String::from("\"");
+    for i in start..65_536 {
+        if let Some(ch) = std::char::from_u32(i) {
+            if re.is_match(&ch.to_string()) {
+                esc.push_str(&format!("\\u{:04x}", i));
+            }
+        }
+    }
+    esc.push('"');
+    esc
+}
+
+// Make sure that server encodes at least all the characters
+// it's supposed to encode.
+#[test]
+fn test_json_xhr_server_encodes() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.body, "o\n");
+    assert_eq!(r.status, StatusCode::OK);
+
+    let killer = killer_string_esc(ESCAPABLE_BY_SERVER, 255);
+    let killer: String = serde_json::from_str(&killer).unwrap();
+    let payload = format!("[\"{}\"]", killer);
+    let r = post(&mut srv, &format!("{}/xhr_send", url), Some(&payload));
+    assert_eq!(r.status, StatusCode::NO_CONTENT);
+
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.status, StatusCode::OK);
+}
+
+// Make sure that server decodes the chars we're customly
+// encoding.
+#[test]
+fn test_json_xhr_server_decodes() {
+    let mut srv = serve();
+    let url = session_url("echo");
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.body, "o\n");
+    assert_eq!(r.status, StatusCode::OK);
+
+    // Sending escaped
+    let killer = killer_string_esc(ESCAPABLE_BY_CLIENT, 0);
+    let r = post(&mut srv, &format!("{}/xhr_send", url), Some(&format!("[{}]", killer)));
+    assert_eq!(r.status, StatusCode::NO_CONTENT);
+
+    let r = post(&mut srv, &format!("{}/xhr", url), None);
+    assert_eq!(r.status, StatusCode::OK);
+    // skip framing, quotes and parenthesis
+    let body = r.body.trim();
+    let recv = &body[2..body.len() - 1];
+
+    // Received string is indeed what we sent previously. We don't
+    // really need to know what exactly got escaped and what not.
+    let a: String = serde_json::from_str(recv).unwrap();
+    let b: String = serde_json::from_str(&killer).unwrap();
+    assert_eq!(a, b);
+}
+
+// Handling close
+// ==============
+
+// When server is closing session, it should unlink current
+// request. That means, if a new request appears, it should receive
+// an application close message rather than "Another connection
+// still open" message.
+#[test]
+fn test_handling_close_frame() {
+    let mut srv = serve();
+    let path = format!("{}/xhr_streaming", session_url("close"));
+    let mut r1 = Streaming::new(&mut srv, Method::POST, &path, &[]);
+    r1.expect(&mut srv, &xhr_streaming_prelude());
+    r1.expect(&mut srv, "o\n");
+    r1.expect(&mut srv, "c[3000,\"Go away!\"]\n");
+
+    let mut r2 = Streaming::new(&mut srv, Method::POST, &path, &[]);
+    r2.expect(&mut srv, &xhr_streaming_prelude());
+    r2.expect(&mut srv, "c[3000,\"Go away!\"]\n");
+
+    // HTTP streaming requests should be automatically closed after
+    // close.
+    assert!(r1.eof(&mut srv));
+    assert!(r2.eof(&mut srv));
+}
+
+#[test]
+fn test_handling_close_request() {
+    let mut srv = serve();
+    let path = format!("{}/xhr_streaming", session_url("echo"));
+    let mut r1 = Streaming::new(&mut srv, Method::POST, &path, &[]);
+    r1.expect(&mut srv, &xhr_streaming_prelude());
+    r1.expect(&mut srv, "o\n");
+
+    let mut r2 = Streaming::new(&mut srv, Method::POST, &path, &[]);
+    r2.expect(&mut srv, &xhr_streaming_prelude());
+    r2.expect(&mut srv, "c[2010,\"Another connection still open\"]\n");
+
+    // HTTP streaming requests should be automatically closed after
+    // getting the close frame.
+    assert!(r2.eof(&mut srv));
+}
+
+// When a polling request is closed by a network error - not by
+// server, the session should be automatically closed.
+#[test]
+fn test_handling_abort_xhr_streaming() {
+    let mut srv = serve();
+    let path = format!("{}/xhr_streaming", session_url("echo"));
+    let mut r1 = Streaming::new(&mut srv, Method::POST, &path, &[]);
+    r1.expect(&mut srv, &xhr_streaming_prelude());
+    r1.expect(&mut srv, "o\n");
+
+    // Can't do second polling request now.
+    let mut r2 = Streaming::new(&mut srv, Method::POST, &path, &[]);
+    r2.expect(&mut srv, &xhr_streaming_prelude());
+    r2.expect(&mut srv, "c[2010,\"Another connection still open\"]\n");
+    assert!(r2.eof(&mut srv));
+
+    drop(r1);
+    thread::sleep(Duration::from_millis(250));
+
+    // Polling request now, after we aborted previous one, should
+    // trigger a connection closure. Implementations may close
+    // the session and forget the state related. Alternatively
+    // they may return a 1002 close message.
+    let mut r3 = Streaming::new(&mut srv, Method::POST, &path, &[]);
+    r3.expect(&mut srv, &xhr_streaming_prelude());
+    let frame = r3.read(&mut srv, 2);
+    if frame != "o\n" {
+        assert_eq!(frame + &r3.read(&mut srv, 31), "c[1002,\"Connection interrupted\"]\n");
+    }
+}
+
+// The same for polling transports
+#[test]
+fn test_handling_abort_xhr_polling() {
+    let mut srv = serve();
+    let path = format!("{}/xhr", session_url("echo"));
+    let r1 = post(&mut srv, &path, None);
+    assert_eq!(r1.body, "o\n");
+
+    let r1 = Streaming::new(&mut srv, Method::POST, &path, &[]);
+    thread::sleep(Duration::from_millis(250));
+
+    // Can't do second polling request now.
+    let r2 = post(&mut srv, &path, None);
+    assert_eq!(r2.body, "c[2010,\"Another connection still open\"]\n");
+
+    drop(r1);
+    thread::sleep(Duration::from_millis(250));
+
+    // Polling request now, after we aborted previous one, should
+    // trigger a connection closure.
+    let r3 = post(&mut srv, &path, None);
+    assert!(r3.body == "o\n" || r3.body == "c[1002,\"Connection interrupted\"]\n");
+}
+
+// Http 1.0 and 1.1 chunking
+// =========================
+
+// We're going to test a greeting url. No dynamic content, just the
+// simplest possible response.
+#[test]
+fn test_http10_synchronous() {
+    let srv = serve();
+    let mut c = RawHttp::new(&srv);
+    let r = c.request("GET", "/echo", "1.0", &[("Connection", "Keep-Alive")]);
+    assert_eq!(r.status, 200);
+    // In practice the exact http version on the response doesn't
+    // really matter. Many serves always respond 1.1.
+    assert!(r.version == "1.0" || r.version == "1.1");
+    // Transfer-encoding is not allowed in http/1.0.
+    assert!(r.header("transfer-encoding").is_none());
+
+    // There are two ways to give valid response. Use
+    // Content-Length (and maybe connection:Keep-Alive) or
+    // Connection: close.
+    if let Some(length) = r.header("content-length").map(|l| l.to_owned()) {
+        assert_eq!(length, "19");
+        assert_eq!(c.read(19), "Welcome to SockJS!\n");
+        let connection = r.header("connection").unwrap_or("").to_lowercase();
+        if connection == "close" || connection.is_empty() {
+            // Connection-close behaviour is default in http 1.0
+            assert!(c.closed());
+        } else {
+            assert_eq!(connection, "keep-alive");
+            // We should be able to issue another request on the same connection
+            let r = c.request("GET", "/echo", "1.0", &[("Connection", "Keep-Alive")]);
+            assert_eq!(r.status, 200);
+        }
+    } else {
+        assert_eq!(r.header("connection").unwrap().to_lowercase(), "close");
+        assert_eq!(c.read(19), "Welcome to SockJS!\n");
+        assert!(c.closed());
+    }
+}
+
+#[test]
+fn test_http10_streaming() {
+    let srv = serve();
+    let mut c = RawHttp::new(&srv);
+    let path = format!("{}/xhr_streaming", session_url("close"));
+    let r = c.request("POST", &path, "1.0", &[("Connection", "Keep-Alive")]);
+    assert_eq!(r.status, 200);
+    // Transfer-encoding is not allowed in http/1.0.
+    assert!(r.header("transfer-encoding").is_none());
+    // Content-length is not allowed - we don't know it yet.
+    assert!(r.header("content-length").is_none());
+
+    // This is a the same logic as test_handling_close_frame
+    assert!(c.read(2048 + 1).starts_with('h'));
+    assert_eq!(c.read(2), "o\n");
+    assert_eq!(c.read(19), "c[3000,\"Go away!\"]\n");
+    assert!(c.closed());
+}
+
+#[test]
+fn test_http11_synchronous() {
+    let srv = serve();
+    let mut c = RawHttp::new(&srv);
+    let r = c.request("GET", "/echo", "1.1", &[("Connection", "Keep-Alive")]);
+    // Keepalive is default in http 1.1
+    assert_eq!(r.version, "1.1");
+    let connection = r.header("connection").unwrap_or("").to_lowercase();
+    assert!(connection == "keep-alive" || connection.is_empty(),
+            "Your server doesn't support connection:Keep-Alive");
+
+    // Server should use 'Content-Length' or 'Transfer-Encoding'
+    if let Some(length) = r.header("content-length").map(|l| l.to_owned()) {
+        assert_eq!(length, "19");
+        assert_eq!(c.read(19), "Welcome to SockJS!\n");
+        assert!(r.header("transfer-encoding").is_none());
+    } else {
+        assert_eq!(r.header("transfer-encoding").unwrap().to_lowercase(), "chunked");
+        assert_eq!(c.read_chunk(), "Welcome to SockJS!\n");
+        assert_eq!(c.read_chunk(), "");
+    }
+
+    // We should be able to issue another request on the same connection
+    let r = c.request("GET", "/echo", "1.1", &[("Connection", "Keep-Alive")]);
+    assert_eq!(r.status, 200);
+}
+
+#[test]
+fn test_http11_streaming() {
+    let srv = serve();
+    let mut c = RawHttp::new(&srv);
+    let path = format!("{}/xhr_streaming", session_url("close"));
+    let r = c.request("POST", &path, "1.1", &[("Connection", "Keep-Alive")]);
+    assert_eq!(r.status, 200);
+    // Transfer-encoding is required in http/1.1.
+    assert_eq!(r.header("transfer-encoding").unwrap().to_lowercase(), "chunked");
+    // Content-length is not allowed.
+    assert!(r.header("content-length").is_none());
+
+    // This is a the same logic as test_handling_close_frame
+    assert!(c.read_chunk().starts_with('h'));
+    assert_eq!(c.read_chunk(), "o\n");
+    assert_eq!(c.read_chunk(), "c[3000,\"Go away!\"]\n");
+    assert_eq!(c.read_chunk(), "");
+}
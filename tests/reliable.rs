@@ -0,0 +1,120 @@
+//! Reliable delivery, message numbering, acknowledgements and replay
+extern crate actix;
+extern crate sockjs;
+
+use std::sync::Arc;
+
+use actix::prelude::*;
+
+use sockjs::test::TestManager;
+use sockjs::{Frame, Message, Session, SessionMessage, SockJSContext, SockJSManager};
+
+#[derive(Default)]
+struct Echo;
+
+impl Actor for Echo {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Echo {}
+
+impl Handler<Message> for Echo {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        ctx.send(msg);
+    }
+}
+
+fn msgs(msgs: &[&str]) -> Vec<String> {
+    msgs.iter().map(|m| m.to_string()).collect()
+}
+
+/// Send control message to session without transport
+fn control(srv: &mut TestManager<Echo>, msg: &'static str) {
+    srv.manager().do_send(SessionMessage {sid: Arc::new("s1".to_owned()), msg: msg.into()});
+    srv.settle();
+}
+
+#[test]
+fn test_numbering() {
+    let mut srv = TestManager::new(SockJSManager::<Echo>::default().reliable(16));
+    let tr = srv.connect("s1");
+    srv.send(&tr, "a");
+    srv.send(&tr, "b");
+    assert_eq!(tr.frames(), vec![Frame::Open,
+                                 Frame::Message("1:a".to_owned()),
+                                 Frame::Message("2:b".to_owned())]);
+
+    // control messages are not delivered to the session
+    srv.send(&tr, "#ack:2");
+    srv.send(&tr, "#replay:2");
+    assert!(tr.frames().is_empty());
+
+    // numbering continues after reconnect
+    srv.disconnect(tr);
+    let tr = srv.connect("s1");
+    srv.send(&tr, "c");
+    assert_eq!(tr.messages(), msgs(&["3:c"]));
+}
+
+#[test]
+fn test_ack() {
+    let mut srv = TestManager::new(SockJSManager::<Echo>::default().reliable(16));
+    let tr = srv.connect("s1");
+    srv.send(&tr, "a");
+    srv.send(&tr, "b");
+    srv.send(&tr, "c");
+    assert_eq!(tr.messages(), msgs(&["1:a", "2:b", "3:c"]));
+
+    // transport holds the record, acknowledged messages are dropped
+    // from replay window right away
+    srv.send(&tr, "#ack:2");
+    srv.send(&tr, "#replay:0");
+    assert_eq!(tr.messages(), msgs(&["3:c"]));
+
+    // ack of unknown sequence number drops all messages
+    srv.send(&tr, "#ack:100");
+    srv.send(&tr, "#replay:0");
+    assert!(tr.messages().is_empty());
+}
+
+#[test]
+fn test_replay() {
+    let mut srv = TestManager::new(SockJSManager::<Echo>::default().reliable(2));
+    let tr = srv.connect("s1");
+    for msg in &["a", "b", "c", "d"] {
+        srv.send(&tr, *msg);
+    }
+    assert_eq!(tr.messages(), msgs(&["1:a", "2:b", "3:c", "4:d"]));
+
+    // replay past the window returns messages that are still in the window
+    srv.send(&tr, "#replay:0");
+    assert_eq!(tr.messages(), msgs(&["3:c", "4:d"]));
+
+    // replayed messages are not renumbered
+    srv.send(&tr, "#replay:3");
+    assert_eq!(tr.messages(), msgs(&["4:d"]));
+
+    // replay after last message is empty
+    srv.send(&tr, "#replay:4");
+    assert!(tr.messages().is_empty());
+}
+
+#[test]
+fn test_released_record() {
+    let mut srv = TestManager::new(SockJSManager::<Echo>::default().reliable(16));
+    let tr = srv.connect("s1");
+    srv.send(&tr, "a");
+    srv.send(&tr, "b");
+    srv.send(&tr, "c");
+    assert_eq!(tr.messages(), msgs(&["1:a", "2:b", "3:c"]));
+    srv.disconnect(tr);
+
+    // controls of session without transport are applied to the record,
+    // replayed messages wait for next transport
+    control(&mut srv, "#ack:1");
+    control(&mut srv, "#replay:0");
+    let tr = srv.connect("s1");
+    assert_eq!(tr.messages(), msgs(&["2:b", "3:c"]));
+}
@@ -0,0 +1,153 @@
+//! Resumable sessions, suspend on expiration and resume with token
+extern crate actix;
+extern crate sockjs;
+
+use std::any::Any;
+use std::time::Duration;
+
+use actix::prelude::*;
+
+use sockjs::test::{TestManager, TestTransport};
+use sockjs::{Message, Session, SockJSContext, SockJSManager};
+
+/// Session counts received messages, counter survives resume
+#[derive(Default)]
+struct Counter {
+    count: usize,
+}
+
+impl Actor for Counter {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Counter {
+    fn opened(&mut self, ctx: &mut SockJSContext<Self>) {
+        ctx.send("opened");
+    }
+
+    fn suspend(&mut self, _: &mut SockJSContext<Self>) -> Option<Box<Any + Send>> {
+        Some(Box::new(self.count))
+    }
+
+    fn resumed(&mut self, ctx: &mut SockJSContext<Self>, state: Box<Any + Send>) {
+        if let Ok(count) = state.downcast::<usize>() {
+            self.count = *count;
+        }
+        ctx.send(format!("resumed:{}", self.count));
+    }
+}
+
+impl Handler<Message> for Counter {
+    type Result = ();
+
+    fn handle(&mut self, _: Message, ctx: &mut SockJSContext<Self>) {
+        self.count += 1;
+        ctx.send(format!("{}", self.count));
+    }
+}
+
+fn manager() -> TestManager<Counter> {
+    TestManager::new(SockJSManager::<Counter>::default().resumable(Duration::new(60, 0)))
+}
+
+/// Take messages, split off resume token
+fn split_token(tr: &TestTransport) -> (String, Vec<String>) {
+    let mut token = None;
+    let mut msgs = Vec::new();
+    for msg in tr.messages() {
+        if msg.starts_with("#resume:") {
+            token = Some(msg[8..].to_owned());
+        } else {
+            msgs.push(msg);
+        }
+    }
+    (token.expect("resume token is not sent"), msgs)
+}
+
+/// Open session, send two messages and let session expire
+fn suspended(srv: &mut TestManager<Counter>) -> String {
+    let tr = srv.connect("s1");
+    srv.send(&tr, "a");
+    srv.send(&tr, "b");
+    let (token, msgs) = split_token(&tr);
+    assert_eq!(msgs, vec!["opened", "1", "2"]);
+    srv.disconnect(tr);
+    srv.advance(Duration::new(11, 0));
+    token
+}
+
+#[test]
+fn test_resume_token() {
+    let mut srv = manager();
+    let tr = srv.connect("s1");
+    let msgs = tr.messages();
+    assert_eq!(msgs.len(), 2);
+    assert!(msgs[0].starts_with("#resume:"));
+    assert_eq!(msgs[0].len(), 8 + 32);
+    assert_eq!(msgs[1], "opened");
+
+    // every session gets its own token
+    let tr2 = srv.connect("s2");
+    let (token2, _) = split_token(&tr2);
+    assert_ne!(&msgs[0][8..], token2.as_str());
+
+    // token is sent once per session
+    srv.disconnect(tr);
+    let tr = srv.connect("s1");
+    srv.send(&tr, "a");
+    assert_eq!(tr.messages(), vec!["1"]);
+}
+
+#[test]
+fn test_resume() {
+    let mut srv = manager();
+    let token = suspended(&mut srv);
+
+    let tr = srv.resume("s2", &token);
+    let (_, msgs) = split_token(&tr);
+    assert_eq!(msgs, vec!["resumed:2"]);
+    srv.send(&tr, "c");
+    assert_eq!(tr.messages(), vec!["3"]);
+
+    // token can be used once
+    let tr = srv.resume("s3", &token);
+    let (_, msgs) = split_token(&tr);
+    assert_eq!(msgs, vec!["opened"]);
+}
+
+#[test]
+fn test_resume_expired() {
+    let mut srv = manager();
+    let token = suspended(&mut srv);
+
+    // suspended state is kept for resume timeout
+    srv.advance(Duration::new(61, 0));
+    let tr = srv.resume("s2", &token);
+    let (_, msgs) = split_token(&tr);
+    assert_eq!(msgs, vec!["opened"]);
+    srv.send(&tr, "c");
+    assert_eq!(tr.messages(), vec!["1"]);
+}
+
+#[test]
+fn test_resume_unknown() {
+    let mut srv = manager();
+    let tr = srv.resume("s1", "0123456789abcdef0123456789abcdef");
+    let (_, msgs) = split_token(&tr);
+    assert_eq!(msgs, vec!["opened"]);
+}
+
+#[test]
+fn test_not_suspended_on_close() {
+    let mut srv = manager();
+    let tr = srv.connect("s1");
+    srv.send(&tr, "a");
+    let (token, _) = split_token(&tr);
+
+    // interrupted session is not suspended
+    srv.interrupt(tr);
+    srv.advance(Duration::new(11, 0));
+    let tr = srv.resume("s2", &token);
+    let (_, msgs) = split_token(&tr);
+    assert_eq!(msgs, vec!["opened"]);
+}
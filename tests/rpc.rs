@@ -0,0 +1,192 @@
+//! JSON-RPC layer
+extern crate actix;
+extern crate futures;
+extern crate serde_json;
+extern crate sockjs;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix::prelude::*;
+use futures::Future;
+use serde_json::Value;
+
+use sockjs::test::TestManager;
+use sockjs::{CallError, Message, RpcError, Session, SockJSContext, SockJSManager};
+
+type Calls = Arc<Mutex<Vec<Result<Value, CallError>>>>;
+
+/// Session with `add` method, plain message calls peer method
+#[derive(Default)]
+struct Calc {
+    calls: Calls,
+}
+
+impl Actor for Calc {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Calc {
+    fn opened(&mut self, ctx: &mut SockJSContext<Self>) {
+        ctx.method("add", |_, params, _| {
+            let args: Vec<i64> =
+                serde_json::from_value(params).map_err(|_| RpcError::invalid_params())?;
+            Ok(Value::from(args.iter().sum::<i64>()))
+        });
+    }
+}
+
+impl Handler<Message> for Calc {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        let calls = Arc::clone(&self.calls);
+        Arbiter::handle().spawn(
+            ctx.call(&msg.0, Value::from(vec![1, 2])).then(move |res| {
+                calls.lock().unwrap().push(res);
+                Ok(())
+            }));
+    }
+}
+
+fn manager(calls: &Calls, timeout: Option<Duration>) -> TestManager<Calc> {
+    let calls = Arc::clone(calls);
+    let manager = SockJSManager::new(move || Calc {calls: Arc::clone(&calls)});
+    match timeout {
+        Some(timeout) => TestManager::new(manager.rpc(timeout)),
+        None => TestManager::new(manager),
+    }
+}
+
+#[test]
+fn test_method() {
+    let calls = Calls::default();
+    let mut srv = manager(&calls, Some(Duration::from_secs(10)));
+
+    let tr = srv.connect("s1");
+    tr.frames();
+    srv.send(&tr, r#"{"jsonrpc":"2.0","method":"add","params":[1,2],"id":1}"#);
+    srv.send(&tr, r#"{"jsonrpc":"2.0","method":"add","params":"x","id":"a"}"#);
+    srv.send(&tr, r#"{"jsonrpc":"2.0","method":"sub","params":[1,2],"id":2}"#);
+    // notification, no response
+    srv.send(&tr, r#"{"jsonrpc":"2.0","method":"add","params":[1,2]}"#);
+    srv.send(&tr, r#"{"jsonrpc":"2.0","method":5,"id":3}"#);
+    assert_eq!(tr.messages(), vec![
+        r#"{"jsonrpc":"2.0","result":3,"id":1}"#.to_owned(),
+        r#"{"jsonrpc":"2.0","error":{"code":-32602,"message":"Invalid params"},"id":"a"}"#
+            .to_owned(),
+        r#"{"jsonrpc":"2.0","error":{"code":-32601,"message":"Method not found"},"id":2}"#
+            .to_owned(),
+        r#"{"jsonrpc":"2.0","error":{"code":-32600,"message":"Invalid Request"},"id":null}"#
+            .to_owned(),
+    ]);
+    assert!(calls.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_invalid_response() {
+    let calls = Calls::default();
+    let mut srv = manager(&calls, Some(Duration::from_secs(10)));
+
+    let tr = srv.connect("s1");
+    tr.frames();
+    srv.send(&tr, "echo");
+    tr.messages();
+
+    // invalid responses are dropped, peer does not get answer
+    srv.send(&tr, r#"{"jsonrpc":"2.0","id":1}"#);
+    srv.send(&tr, r#"{"jsonrpc":"2.0","result":1}"#);
+    srv.send(&tr, r#"{"jsonrpc":"2.0","result":1,"id":"a"}"#);
+    srv.send(&tr, r#"{"jsonrpc":"2.0","error":"failed","id":1}"#);
+    assert!(tr.messages().is_empty());
+    assert!(calls.lock().unwrap().is_empty());
+
+    // call is still pending
+    srv.send(&tr, r#"{"jsonrpc":"2.0","result":3,"id":1}"#);
+    assert_eq!(*calls.lock().unwrap(), vec![Ok(Value::from(3))]);
+}
+
+// Rpc and plain messages are delivered through same channel, peer's order is kept
+#[test]
+fn test_order() {
+    let calls = Calls::default();
+    let mut srv = manager(&calls, Some(Duration::from_secs(10)));
+
+    let tr = srv.connect("s1");
+    tr.frames();
+    srv.send(&tr, r#"{"jsonrpc":"2.0","method":"add","params":[1],"id":1}"#);
+    srv.send(&tr, "echo");
+    srv.send(&tr, r#"{"jsonrpc":"2.0","method":"add","params":[2],"id":2}"#);
+    assert_eq!(tr.messages(), vec![
+        r#"{"jsonrpc":"2.0","result":1,"id":1}"#.to_owned(),
+        r#"{"jsonrpc":"2.0","method":"echo","params":[1,2],"id":1}"#.to_owned(),
+        r#"{"jsonrpc":"2.0","result":2,"id":2}"#.to_owned(),
+    ]);
+}
+
+#[test]
+fn test_call() {
+    let calls = Calls::default();
+    let mut srv = manager(&calls, Some(Duration::from_secs(10)));
+
+    let tr = srv.connect("s1");
+    tr.frames();
+    srv.send(&tr, "echo");
+    srv.send(&tr, "fail");
+    assert_eq!(tr.messages(), vec![
+        r#"{"jsonrpc":"2.0","method":"echo","params":[1,2],"id":1}"#.to_owned(),
+        r#"{"jsonrpc":"2.0","method":"fail","params":[1,2],"id":2}"#.to_owned(),
+    ]);
+
+    srv.send(&tr, r#"{"jsonrpc":"2.0","error":{"code":1,"message":"failed"},"id":2}"#);
+    srv.send(&tr, r#"{"jsonrpc":"2.0","result":[1,2],"id":1}"#);
+    // unknown call
+    srv.send(&tr, r#"{"jsonrpc":"2.0","result":[1,2],"id":5}"#);
+    assert_eq!(*calls.lock().unwrap(), vec![
+        Err(CallError::Error(RpcError::new(1, "failed"))),
+        Ok(Value::from(vec![1, 2])),
+    ]);
+    assert!(tr.messages().is_empty());
+}
+
+#[test]
+fn test_call_timeout() {
+    let calls = Calls::default();
+    let mut srv = manager(&calls, Some(Duration::from_secs(10)));
+
+    let tr = srv.connect("s1");
+    srv.send(&tr, "echo");
+    srv.advance(Duration::from_secs(5));
+    assert!(calls.lock().unwrap().is_empty());
+    srv.advance(Duration::from_secs(10));
+    assert_eq!(*calls.lock().unwrap(), vec![Err(CallError::Timeout)]);
+
+    // late response is ignored
+    srv.send(&tr, r#"{"jsonrpc":"2.0","result":[1,2],"id":1}"#);
+    assert_eq!(calls.lock().unwrap().len(), 1);
+}
+
+// pending calls fail once session get closed
+#[test]
+fn test_call_closed() {
+    let calls = Calls::default();
+    let mut srv = manager(&calls, Some(Duration::from_secs(10)));
+
+    let tr = srv.connect("s1");
+    srv.send(&tr, "echo");
+    srv.interrupt(tr);
+    assert_eq!(*calls.lock().unwrap(), vec![Err(CallError::Closed)]);
+}
+
+#[test]
+fn test_disabled() {
+    let calls = Calls::default();
+    let mut srv = manager(&calls, None);
+
+    let tr = srv.connect("s1");
+    tr.frames();
+    // request object is a plain message
+    srv.send(&tr, r#"{"jsonrpc":"2.0","method":"add","params":[1,2],"id":1}"#);
+    assert_eq!(*calls.lock().unwrap(), vec![Err(CallError::Disabled)]);
+    assert!(tr.messages().is_empty());
+}
@@ -0,0 +1,47 @@
+//! Session lifecycle callbacks
+extern crate actix;
+extern crate sockjs;
+
+use actix::prelude::*;
+
+use sockjs::test::TestManager;
+use sockjs::{Message, Session, SockJSContext, SockJSManager, TransportInfo};
+
+/// Session reports connected transport
+#[derive(Default)]
+struct Transport;
+
+impl Actor for Transport {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Transport {
+    fn acquired(&mut self, ctx: &mut SockJSContext<Self>, transport: TransportInfo) {
+        assert_eq!(ctx.transport(), Some(&transport));
+        ctx.send(format!("acquired:{}", transport.name));
+    }
+}
+
+impl Handler<Message> for Transport {
+    type Result = ();
+
+    fn handle(&mut self, _: Message, ctx: &mut SockJSContext<Self>) {
+        let connected = ctx.transport().is_some();
+        ctx.send(format!("connected:{}", connected));
+    }
+}
+
+#[test]
+fn test_transport() {
+    let mut srv = TestManager::new(SockJSManager::<Transport>::default());
+    let tr = srv.connect("s1");
+    assert_eq!(tr.messages(), vec!["acquired:test"]);
+    srv.send(&tr, "a");
+    assert_eq!(tr.messages(), vec!["connected:true"]);
+    srv.disconnect(tr);
+
+    // acquired again on reconnect
+    let tr = srv.connect("s1");
+    assert_eq!(tr.messages(), vec!["acquired:test"]);
+    srv.disconnect(tr);
+}
@@ -0,0 +1,275 @@
+//! STOMP broker
+extern crate sockjs;
+
+use sockjs::stomp::{
+    FrameError, MAX_UNACKED, StompApp, StompBroker, StompFrame, StompPublish, StompSession,
+};
+use sockjs::test::{TestManager, TestTransport};
+use sockjs::{CloseCode, Frame, SockJSManager};
+
+#[derive(Default)]
+struct Chat;
+
+impl StompApp for Chat {
+    fn send(&mut self, frame: StompFrame, broker: &StompBroker) -> Result<(), String> {
+        if frame.body.is_empty() {
+            return Err("Empty message".to_owned())
+        }
+        broker.publish(StompPublish::new("/topic/chat", frame.body).header("from", "chat"));
+        Ok(())
+    }
+}
+
+fn parse(msg: &str) -> StompFrame {
+    StompFrame::parse(msg).unwrap().unwrap()
+}
+
+/// Frames received by transport since last call
+fn frames(tr: &TestTransport) -> Vec<StompFrame> {
+    tr.messages().iter().map(|msg| parse(msg)).collect()
+}
+
+fn connect(srv: &mut TestManager<StompSession<Chat>>, sid: &str) -> TestTransport {
+    let tr = srv.connect(sid);
+    srv.send(&tr, "CONNECT\naccept-version:1.1,1.2\nhost:localhost\n\n\0");
+    let frames = frames(&tr);
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].command, "CONNECTED");
+    assert_eq!(frames[0].get("version"), Some("1.2"));
+    assert_eq!(frames[0].get("session"), Some(sid));
+    tr
+}
+
+#[test]
+fn test_frame() {
+    let frame = parse("SEND\ndestination:/queue/a\r\nx:a\\cb\\\\\nx:c\n\nhello\0\n\n");
+    assert_eq!(frame.command, "SEND");
+    assert_eq!(frame.get("destination"), Some("/queue/a"));
+    assert_eq!(frame.get("x"), Some("a:b\\"));
+    assert_eq!(frame.body, "hello");
+    assert_eq!(parse(&frame.to_string()), frame);
+    assert_eq!(frame.to_string(),
+               "SEND\ndestination:/queue/a\nx:a\\cb\\\\\nx:c\n\nhello\0");
+
+    // CONNECT headers are not escaped
+    let frame = parse("\r\n\r\nCONNECT\r\nlogin:a\\c\r\n\r\n\0");
+    assert_eq!(frame.get("login"), Some("a\\c"));
+
+    let frame = parse("SEND\ncontent-length:3\n\na\0b\0");
+    assert_eq!(frame.body, "a\0b");
+
+    assert_eq!(StompFrame::parse("\n"), Ok(None));
+    assert_eq!(StompFrame::parse("SEND\n\nbody"), Err(FrameError::MissingNul));
+    assert_eq!(StompFrame::parse("SEND\nx\n\n\0"), Err(FrameError::InvalidHeader));
+    assert_eq!(StompFrame::parse("SEND\nx:\\t\n\n\0"), Err(FrameError::InvalidEscape));
+}
+
+#[test]
+fn test_not_connected() {
+    let mut srv = TestManager::new(SockJSManager::<StompSession<Chat>>::default());
+
+    let tr = srv.connect("s1");
+    tr.frames();
+    srv.send(&tr, "SEND\ndestination:/topic/a\nreceipt:1\n\nhello\0");
+    let sent = tr.frames();
+    assert_eq!(sent.len(), 2);
+    match sent[0] {
+        Frame::Message(ref msg) => {
+            let frame = parse(msg);
+            assert_eq!(frame.command, "ERROR");
+            assert_eq!(frame.get("message"), Some("Not connected"));
+            assert_eq!(frame.get("receipt-id"), Some("1"));
+        }
+        ref frame => panic!("Unexpected frame: {:?}", frame),
+    }
+    assert_eq!(sent[1], Frame::Close(CloseCode::GoAway));
+
+    let tr = srv.connect("s2");
+    srv.send(&tr, "CONNECT\naccept-version:1.0\n\n\0");
+    let frames = frames(&tr);
+    assert_eq!(frames[0].command, "ERROR");
+}
+
+#[test]
+fn test_subscribe() {
+    let mut srv = TestManager::new(SockJSManager::<StompSession<Chat>>::default());
+    let tr1 = connect(&mut srv, "s1");
+    let tr2 = connect(&mut srv, "s2");
+
+    srv.send(&tr1, "SUBSCRIBE\nid:0\ndestination:/topic/a\n\n\0");
+    srv.send(&tr2, "SUBSCRIBE\nid:sub\ndestination:/topic/a\nreceipt:r1\n\n\0");
+    srv.send(&tr2, "SUBSCRIBE\nid:sub-b\ndestination:/topic/b\n\n\0");
+    assert_eq!(frames(&tr2), vec![StompFrame::new("RECEIPT").header("receipt-id", "r1")]);
+
+    srv.send(&tr1, "SEND\ndestination:/topic/a\ncontent-type:text/plain\nreceipt:2\n\nhello\0");
+    assert_eq!(frames(&tr1), vec![
+        StompFrame::new("RECEIPT").header("receipt-id", "2"),
+        StompFrame::new("MESSAGE")
+            .header("subscription", "0")
+            .header("message-id", "1")
+            .header("destination", "/topic/a")
+            .header("content-type", "text/plain")
+            .header("content-length", "5")
+            .body("hello"),
+    ]);
+    let frames2 = frames(&tr2);
+    assert_eq!(frames2.len(), 1);
+    assert_eq!(frames2[0].get("subscription"), Some("sub"));
+    assert_eq!(frames2[0].body, "hello");
+
+    // published from outside of sessions
+    srv.manager().do_send(StompPublish::new("/topic/b", "world"));
+    srv.settle();
+    assert!(frames(&tr1).is_empty());
+    let frames2 = frames(&tr2);
+    assert_eq!(frames2.len(), 1);
+    assert_eq!(frames2[0].get("subscription"), Some("sub-b"));
+    assert_eq!(frames2[0].body, "world");
+
+    srv.send(&tr2, "UNSUBSCRIBE\nid:sub\n\n\0");
+    srv.send(&tr1, "SEND\ndestination:/topic/a\n\nhello\0");
+    assert_eq!(frames(&tr1).len(), 1);
+    assert!(frames(&tr2).is_empty());
+}
+
+#[test]
+fn test_ack() {
+    let mut srv = TestManager::new(SockJSManager::<StompSession<Chat>>::default());
+    let tr = connect(&mut srv, "s1");
+
+    srv.send(&tr, "SUBSCRIBE\nid:0\ndestination:/queue/a\nack:client\n\n\0");
+    srv.send(&tr, "SEND\ndestination:/queue/a\n\n1\0");
+    srv.send(&tr, "SEND\ndestination:/queue/a\n\n2\0");
+    let acks: Vec<_> =
+        frames(&tr).iter().map(|frame| frame.get("ack").unwrap().to_owned()).collect();
+    assert_eq!(acks, vec!["1".to_owned(), "2".to_owned()]);
+
+    // cumulative ack
+    srv.send(&tr, "ACK\nid:2\nreceipt:a\n\n\0");
+    assert_eq!(frames(&tr), vec![StompFrame::new("RECEIPT").header("receipt-id", "a")]);
+    srv.send(&tr, "ACK\nid:1\n\n\0");
+    let frames = frames(&tr);
+    assert_eq!(frames[0].command, "ERROR");
+    assert_eq!(frames[0].get("message"), Some("Unknown message 1"));
+}
+
+#[test]
+fn test_unacked_limit() {
+    let mut srv = TestManager::new(SockJSManager::<StompSession<Chat>>::default());
+    let tr = connect(&mut srv, "s1");
+
+    srv.send(&tr, "SUBSCRIBE\nid:0\ndestination:/queue/a\nack:client-individual\n\n\0");
+    for _ in 0..MAX_UNACKED + 1 {
+        srv.manager().do_send(StompPublish::new("/queue/a", "msg"));
+        srv.settle();
+    }
+    let frames = tr.frames();
+    assert_eq!(frames.len(), MAX_UNACKED + 2);
+    match frames[MAX_UNACKED] {
+        Frame::Message(ref msg) => {
+            let frame = parse(msg);
+            assert_eq!(frame.command, "ERROR");
+            assert_eq!(frame.get("message"), Some("Too many unacknowledged messages"));
+        }
+        ref frame => panic!("Unexpected frame {:?}", frame),
+    }
+    assert_eq!(frames[MAX_UNACKED + 1], Frame::Close(CloseCode::GoAway));
+}
+
+#[test]
+fn test_destinations() {
+    let mut srv = TestManager::new(SockJSManager::<StompSession<Chat>>::default());
+    let tr1 = connect(&mut srv, "s1");
+    let tr2 = connect(&mut srv, "s2");
+
+    // two subscriptions of one session to the same destination
+    srv.send(&tr1, "SUBSCRIBE\nid:0\ndestination:/topic/a\n\n\0");
+    srv.send(&tr1, "SUBSCRIBE\nid:1\ndestination:/topic/a\n\n\0");
+    srv.send(&tr2, "SUBSCRIBE\nid:0\ndestination:/topic/b\n\n\0");
+
+    srv.manager().do_send(StompPublish::new("/topic/a", "a"));
+    srv.settle();
+    assert_eq!(frames(&tr1).len(), 2);
+    assert!(frames(&tr2).is_empty());
+
+    // destination stays subscribed until last subscription is removed
+    srv.send(&tr1, "UNSUBSCRIBE\nid:0\n\n\0");
+    srv.manager().do_send(StompPublish::new("/topic/a", "a"));
+    srv.settle();
+    let frames1 = frames(&tr1);
+    assert_eq!(frames1.len(), 1);
+    assert_eq!(frames1[0].get("subscription"), Some("1"));
+
+    srv.send(&tr1, "UNSUBSCRIBE\nid:1\n\n\0");
+    srv.manager().do_send(StompPublish::new("/topic/a", "a").header("content-length", "7"));
+    srv.manager().do_send(StompPublish::new("/topic/b", "b").header("content-length", "7"));
+    srv.settle();
+    assert!(frames(&tr1).is_empty());
+    let frames2 = frames(&tr2);
+    assert_eq!(frames2.len(), 1);
+    assert_eq!(frames2[0].get("content-length"), Some("1"));
+}
+
+// Messages are built by every subscribed session, released session gets them buffered
+#[test]
+fn test_released_subscriber() {
+    let mut srv = TestManager::new(SockJSManager::<StompSession<Chat>>::default());
+    let tr1 = connect(&mut srv, "s1");
+    let tr2 = connect(&mut srv, "s2");
+
+    srv.send(&tr1, "SUBSCRIBE\nid:a\ndestination:/topic/a\n\n\0");
+    srv.send(&tr2, "SUBSCRIBE\nid:b\ndestination:/topic/a\nack:client\n\n\0");
+    srv.disconnect(tr2);
+
+    srv.manager().do_send(StompPublish::new("/topic/a", "1"));
+    srv.manager().do_send(StompPublish::new("/topic/a", "2"));
+    srv.settle();
+    let frames1 = frames(&tr1);
+    assert_eq!(frames1.len(), 2);
+    assert_eq!(frames1[0].get("subscription"), Some("a"));
+    assert_eq!(frames1[0].get("ack"), None);
+
+    let tr2 = srv.connect("s2");
+    let frames2 = frames(&tr2);
+    assert_eq!(frames2.len(), 2);
+    assert_eq!(frames2[0].get("subscription"), Some("b"));
+    assert_eq!(frames2[0].get("ack"), Some("1"));
+    assert_eq!(frames2[1].body, "2");
+    assert_eq!(frames2[1].get("ack"), Some("2"));
+
+    srv.send(&tr2, "ACK\nid:2\nreceipt:r\n\n\0");
+    assert_eq!(frames(&tr2), vec![StompFrame::new("RECEIPT").header("receipt-id", "r")]);
+}
+
+#[test]
+fn test_app() {
+    let mut srv = TestManager::new(SockJSManager::<StompSession<Chat>>::default());
+    let tr = connect(&mut srv, "s1");
+
+    srv.send(&tr, "SUBSCRIBE\nid:0\ndestination:/topic/chat\n\n\0");
+    srv.send(&tr, "SEND\ndestination:/app/chat\n\nhello\0");
+    let frames1 = frames(&tr);
+    assert_eq!(frames1.len(), 1);
+    assert_eq!(frames1[0].get("destination"), Some("/topic/chat"));
+    assert_eq!(frames1[0].get("from"), Some("chat"));
+    assert_eq!(frames1[0].body, "hello");
+
+    srv.send(&tr, "SEND\ndestination:/app/chat\n\n\0");
+    let frames = tr.frames();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[1], Frame::Close(CloseCode::GoAway));
+}
+
+#[test]
+fn test_disconnect() {
+    let mut srv = TestManager::new(SockJSManager::<StompSession<Chat>>::default());
+    let tr = connect(&mut srv, "s1");
+
+    srv.send(&tr, "DISCONNECT\nreceipt:77\n\n\0");
+    let frames = tr.frames();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0], Frame::Message(
+        StompFrame::new("RECEIPT").header("receipt-id", "77").to_string()));
+    assert_eq!(frames[1], Frame::Close(CloseCode::GoAway));
+    assert!(tr.released());
+}
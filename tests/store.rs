@@ -0,0 +1,183 @@
+//! Session stores and session persistence
+extern crate actix;
+extern crate sockjs;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use actix::prelude::*;
+
+use sockjs::test::TestManager;
+use sockjs::{FileStore, MemoryStore, Message, Session, SessionStore, SockJSContext,
+             SockJSManager, StoredSession};
+
+/// Session keeps received messages as its state
+#[derive(Default)]
+struct Notes {
+    notes: Vec<String>,
+}
+
+impl Actor for Notes {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Notes {
+    fn save(&mut self, _: &mut SockJSContext<Self>) -> Option<Vec<u8>> {
+        Some(self.notes.join(",").into_bytes())
+    }
+
+    fn restored(&mut self, ctx: &mut SockJSContext<Self>, state: Option<Vec<u8>>) {
+        let state = String::from_utf8(state.unwrap_or_default()).unwrap();
+        self.notes = state.split(',').map(|s| s.to_owned()).collect();
+        ctx.send(format!("restored:{}", state));
+    }
+}
+
+impl Handler<Message> for Notes {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, _: &mut SockJSContext<Self>) {
+        self.notes.push(msg.0);
+    }
+}
+
+/// Memory store that counts writes
+#[derive(Clone, Default)]
+struct CountingStore {
+    store: MemoryStore,
+    saves: Arc<AtomicUsize>,
+    removes: Arc<AtomicUsize>,
+}
+
+impl SessionStore for CountingStore {
+    fn save(&mut self, sid: &str, session: &StoredSession) -> io::Result<()> {
+        self.saves.fetch_add(1, Ordering::SeqCst);
+        self.store.save(sid, session)
+    }
+
+    fn load(&mut self, sid: &str) -> io::Result<Option<StoredSession>> {
+        self.store.load(sid)
+    }
+
+    fn remove(&mut self, sid: &str) -> io::Result<()> {
+        self.removes.fetch_add(1, Ordering::SeqCst);
+        self.store.remove(sid)
+    }
+}
+
+fn session(state: &str, messages: &[&str]) -> StoredSession {
+    StoredSession {
+        state: Some(state.as_bytes().to_vec()),
+        messages: messages.iter().map(|m| m.to_string()).collect(),
+    }
+}
+
+/// Save, load and remove sessions of the store
+fn round_trip<T: SessionStore>(store: &mut T) {
+    assert_eq!(store.load("s1").unwrap(), None);
+
+    store.save("s1", &session("one", &["a", "b"])).unwrap();
+    store.save("s2", &StoredSession::default()).unwrap();
+    assert_eq!(store.load("s1").unwrap(), Some(session("one", &["a", "b"])));
+    assert_eq!(store.load("s2").unwrap(), Some(StoredSession::default()));
+
+    // save overwrites session
+    store.save("s1", &session("two", &[])).unwrap();
+    assert_eq!(store.load("s1").unwrap(), Some(session("two", &[])));
+
+    store.remove("s1").unwrap();
+    assert_eq!(store.load("s1").unwrap(), None);
+    assert_eq!(store.load("s2").unwrap(), Some(StoredSession::default()));
+
+    // removing unknown session is not an error
+    store.remove("s1").unwrap();
+}
+
+#[test]
+fn test_memory_store() {
+    let mut store = MemoryStore::new();
+    round_trip(&mut store);
+
+    // clones share sessions
+    let mut clone = store.clone();
+    clone.save("s3", &session("three", &[])).unwrap();
+    assert_eq!(store.load("s3").unwrap(), Some(session("three", &[])));
+}
+
+#[test]
+fn test_file_store() {
+    let dir = env::temp_dir().join("sockjs-test-file-store");
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut store = FileStore::new(&dir).unwrap();
+    round_trip(&mut store);
+
+    // sessions survive new store in same directory
+    store.save("s3", &session("three", &["c"])).unwrap();
+    let mut store = FileStore::new(&dir).unwrap();
+    assert_eq!(store.load("s3").unwrap(), Some(session("three", &["c"])));
+
+    // session id can not escape store directory
+    assert!(store.save("../s4", &StoredSession::default()).is_err());
+    assert!(store.load("").is_err());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_persist() {
+    let store = CountingStore::default();
+    let mut srv = TestManager::new(SockJSManager::<Notes>::default().store(store.clone()));
+
+    let tr = srv.connect("s1");
+    srv.send(&tr, "a");
+    srv.send(&tr, "b");
+    srv.disconnect(tr);
+    assert_eq!(store.saves.load(Ordering::SeqCst), 1);
+    assert_eq!(store.store.clone().load("s1").unwrap(), Some(session("a,b", &[])));
+
+    // unchanged session is not saved again
+    let tr = srv.connect("s1");
+    srv.disconnect(tr);
+    assert_eq!(store.saves.load(Ordering::SeqCst), 1);
+
+    let tr = srv.connect("s1");
+    srv.send(&tr, "c");
+    srv.disconnect(tr);
+    assert_eq!(store.saves.load(Ordering::SeqCst), 2);
+    assert_eq!(store.store.clone().load("s1").unwrap(), Some(session("a,b,c", &[])));
+
+    // closed session get removed from store
+    let tr = srv.connect("s1");
+    srv.interrupt(tr);
+    assert_eq!(store.removes.load(Ordering::SeqCst), 1);
+    assert_eq!(store.store.clone().load("s1").unwrap(), None);
+}
+
+#[test]
+fn test_restore() {
+    let mut store = MemoryStore::new();
+    store.save("s1", &session("a,b", &["pending"])).unwrap();
+    let mut srv = TestManager::new(SockJSManager::<Notes>::default().store(store.clone()));
+
+    // restored session is not opened again
+    let tr = srv.connect("s1");
+    assert_eq!(tr.messages(), vec!["pending", "restored:a,b"]);
+    srv.send(&tr, "c");
+    srv.disconnect(tr);
+    assert_eq!(store.load("s1").unwrap(), Some(session("a,b,c", &[])));
+}
+
+#[test]
+fn test_not_stored() {
+    // session that was never saved is not removed from store
+    let store = CountingStore::default();
+    let mut srv = TestManager::new(SockJSManager::<Notes>::default().store(store.clone()));
+    let tr = srv.connect("s2");
+    srv.interrupt(tr);
+    assert_eq!(store.saves.load(Ordering::SeqCst), 0);
+    assert_eq!(store.removes.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,118 @@
+//! Topic subscriptions
+extern crate actix;
+extern crate sockjs;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::prelude::*;
+
+use sockjs::test::TestManager;
+use sockjs::{Message, Publish, Session, SockJSContext, SockJSManager, SubscribeTopic};
+
+/// Session controlled by peer, `sub <pattern>`, `unsub <pattern>` and `pub <topic> <msg>`
+#[derive(Default)]
+struct Topics;
+
+impl Actor for Topics {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Topics {}
+
+impl Handler<Message> for Topics {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        let parts: Vec<_> = msg.0.splitn(3, ' ').map(|s| s.to_owned()).collect();
+        match parts[0].as_str() {
+            "sub" => ctx.subscribe(parts[1].as_str()),
+            "unsub" => ctx.unsubscribe(parts[1].as_str()),
+            "pub" => ctx.publish(parts[1].as_str(), parts[2].clone()),
+            _ => (),
+        }
+    }
+}
+
+#[test]
+fn test_wildcards() {
+    let mut srv = TestManager::new(SockJSManager::<Topics>::default());
+    let exact = srv.connect("exact");
+    let star = srv.connect("star");
+    let hash = srv.connect("hash");
+    let other = srv.connect("other");
+    srv.send(&exact, "sub orders.eu.created");
+    srv.send(&star, "sub orders.*.created");
+    srv.send(&hash, "sub orders.#");
+    srv.send(&other, "sub users.*");
+    for tr in &[&exact, &star, &hash, &other] {
+        tr.frames();
+    }
+
+    srv.send(&other, "pub orders.eu.created o1");
+    srv.send(&other, "pub orders.us.created o2");
+    srv.send(&other, "pub orders.us.created.late o3");
+    srv.send(&other, "pub orders o4");
+    srv.send(&other, "pub users.u1.created u1");
+    assert_eq!(exact.messages(), vec!["o1".to_owned()]);
+    assert_eq!(star.messages(), vec!["o1".to_owned(), "o2".to_owned()]);
+    assert_eq!(hash.messages(),
+               vec!["o1".to_owned(), "o2".to_owned(), "o3".to_owned(), "o4".to_owned()]);
+    assert!(other.messages().is_empty());
+}
+
+#[test]
+fn test_unsubscribe() {
+    let mut srv = TestManager::new(SockJSManager::<Topics>::default());
+    let tr = srv.connect("s1");
+    srv.send(&tr, "sub a.*");
+    srv.send(&tr, "sub a.b");
+    tr.frames();
+
+    // session gets message once for all matching subscriptions
+    srv.send(&tr, "pub a.b m1");
+    srv.send(&tr, "unsub a.*");
+    srv.send(&tr, "pub a.c m2");
+    srv.send(&tr, "pub a.b m3");
+    assert_eq!(tr.messages(), vec!["m1".to_owned(), "m3".to_owned()]);
+}
+
+// publish from outside of sessions, session without transport buffers message
+#[test]
+fn test_manager_publish() {
+    let mut srv = TestManager::new(SockJSManager::<Topics>::default());
+    let tr = srv.connect("s1");
+    srv.manager().do_send(
+        SubscribeTopic {sid: Arc::new("s1".to_owned()), pattern: "a".to_owned()});
+    srv.disconnect(tr);
+
+    srv.manager().do_send(Publish::new("a", "hello"));
+    srv.settle();
+    let tr = srv.connect("s1");
+    assert_eq!(tr.messages(), vec!["hello".to_owned()]);
+
+    // subscriptions are removed with expired session
+    srv.disconnect(tr);
+    srv.advance(Duration::from_secs(60));
+    let tr = srv.connect("s1");
+    tr.frames();
+    srv.manager().do_send(Publish::new("a", "hello"));
+    srv.settle();
+    assert!(tr.messages().is_empty());
+}
+
+// many `#` wildcards against long topic do not backtrack
+#[test]
+fn test_pathological_pattern() {
+    let mut srv = TestManager::new(SockJSManager::<Topics>::default());
+    let tr = srv.connect("s1");
+    let pattern = vec!["#"; 32].join(".");
+    let topic = vec!["a"; 64].join(".");
+    srv.send(&tr, format!("sub {}.b", pattern));
+    srv.send(&tr, format!("sub a.{}.a", pattern));
+    tr.frames();
+
+    srv.send(&tr, format!("pub {} m1", topic));
+    srv.send(&tr, format!("pub {}.b m2", topic));
+    assert_eq!(tr.messages(), vec!["m1".to_owned(), "m2".to_owned()]);
+}
@@ -0,0 +1,158 @@
+//! Custom transport registered with `SockJS::transport()`
+extern crate actix;
+extern crate actix_web;
+extern crate sockjs;
+
+use std::sync::mpsc;
+use std::thread;
+
+use actix::prelude::*;
+use actix_web::http::{Method, StatusCode};
+use actix_web::test::TestServer;
+use actix_web::{App, HttpContext, HttpMessage, HttpRequest, HttpResponse};
+
+use sockjs::{Broadcast, ChannelItem, CloseCode, Frame, Message, Record, SendResult, Session,
+             SockJS, SockJSContext, SockJSManager, Transport, TransportMode, TransportState};
+
+#[derive(Default)]
+struct Echo;
+
+impl Actor for Echo {
+    type Context = SockJSContext<Self>;
+}
+
+impl Session for Echo {}
+
+impl Handler<Message> for Echo {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut SockJSContext<Self>) {
+        ctx.send(msg);
+    }
+}
+
+type Manager = SockJSManager<Echo>;
+
+/// Polling transport, writes one frame per request as plain text line
+struct Lines {
+    state: TransportState,
+}
+
+impl Lines {
+    fn init(req: HttpRequest<Addr<Syn, Manager>>) -> HttpResponse {
+        let session = req.match_info().get("session").unwrap().to_owned();
+        let mut ctx = HttpContext::from_request(req);
+        let mut transport = Lines{state: TransportState::new()};
+        transport.init_transport(session, &mut ctx);
+        HttpResponse::Ok().content_type("text/plain").body(ctx.actor(transport))
+    }
+}
+
+impl Actor for Lines {
+    type Context = HttpContext<Self, Addr<Syn, Manager>>;
+
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.release(ctx);
+        Running::Stop
+    }
+}
+
+impl Transport<Echo, Manager> for Lines {
+    const NAME: &'static str = "lines";
+    const MODE: TransportMode = TransportMode::Polling;
+
+    fn send(&mut self, ctx: &mut Self::Context, msg: &Frame, record: &mut Record) -> SendResult {
+        match *msg {
+            Frame::Open => ctx.write("open\n"),
+            Frame::Heartbeat => ctx.write("heartbeat\n"),
+            Frame::Message(ref s) => ctx.write(format!("message {}\n", s)),
+            Frame::MessageVec(ref s) => ctx.write(format!("messages {}\n", s)),
+            Frame::MessageBlob(_) => (),
+            Frame::Close(code) => {
+                record.close();
+                ctx.write(format!("close {}\n", code.num()));
+            }
+        }
+        ctx.write_eof();
+        SendResult::Stop
+    }
+
+    fn send_close(&mut self, ctx: &mut Self::Context, code: CloseCode) {
+        ctx.write(format!("close {}\n", code.num()));
+        ctx.write_eof();
+    }
+
+    fn send_heartbeat(&mut self, ctx: &mut Self::Context) {
+        ctx.write("heartbeat\n");
+        ctx.write_eof();
+    }
+
+    fn transport_state(&mut self) -> &mut TransportState {
+        &mut self.state
+    }
+}
+
+impl Handler<ChannelItem> for Lines {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChannelItem, ctx: &mut Self::Context) {
+        self.handle_message(msg, ctx)
+    }
+}
+
+impl Handler<Broadcast> for Lines {
+    type Result = ();
+
+    fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
+        self.handle_broadcast(msg, ctx)
+    }
+}
+
+fn serve() -> TestServer {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let sys = System::new("sockjs-managers");
+        let echo: Addr<Syn, _> = Manager::start_default();
+        tx.send(echo).unwrap();
+        sys.run();
+    });
+    let echo = rx.recv().unwrap();
+
+    TestServer::with_factory(move || {
+        App::new()
+            .handler("/echo", SockJS::new(echo.clone()).transport("lines", Lines::init))
+    })
+}
+
+fn post(srv: &mut TestServer, path: &str, body: Option<&str>) -> (StatusCode, String) {
+    let req = match body {
+        Some(body) => srv.client(Method::POST, path).body(body.to_owned()).unwrap(),
+        None => srv.client(Method::POST, path).finish().unwrap(),
+    };
+    let resp = srv.execute(req.send()).unwrap();
+    let status = resp.status();
+    let body = srv.execute(resp.body()).unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[test]
+fn test_custom_transport() {
+    let mut srv = serve();
+    let url = "/echo/000/custom";
+
+    assert_eq!(post(&mut srv, &format!("{}/lines", url), None),
+               (StatusCode::OK, "open\n".to_owned()));
+
+    // custom transport shares session with built-in transports
+    let (status, _) = post(&mut srv, &format!("{}/xhr_send", url), Some("[\"a\"]"));
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, body) = post(&mut srv, &format!("{}/lines", url), None);
+    assert_eq!(status, StatusCode::OK);
+    assert!(body == "message a\n" || body == "messages [\"a\"]\n", "{}", body);
+
+    // built-in transport sees the same session
+    let (status, _) = post(&mut srv, &format!("{}/xhr_send", url), Some("[\"b\"]"));
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(post(&mut srv, &format!("{}/xhr", url), None),
+               (StatusCode::OK, "a[\"b\"]\n".to_owned()));
+}